@@ -0,0 +1,6 @@
+-- Add down migration script here
+DROP INDEX pages_search_idx;
+DROP TRIGGER pages_search_update ON pages;
+DROP FUNCTION pages_search_update;
+ALTER TABLE pages
+  DROP COLUMN search RESTRICT;
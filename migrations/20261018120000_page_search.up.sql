@@ -0,0 +1,22 @@
+-- Add up migration script here
+ALTER TABLE pages
+  ADD COLUMN search tsvector DEFAULT ''::tsvector NOT NULL;
+
+-- Titles live inside the metadata as <title> tags and outweigh the body.
+-- Tags are stripped from the body so markup doesn't end up in the index.
+CREATE FUNCTION pages_search_update() RETURNS trigger AS $$
+BEGIN
+    NEW.search :=
+        setweight(to_tsvector('english', coalesce(substring(array_to_string(NEW.metadata, ' ') from '<title>(.*?)</title>'), '')), 'A') ||
+        setweight(to_tsvector('english', regexp_replace(NEW.body, '<[^>]*>', ' ', 'g')), 'B');
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER pages_search_update
+  BEFORE INSERT OR UPDATE OF metadata, body ON pages
+  FOR EACH ROW EXECUTE FUNCTION pages_search_update();
+
+UPDATE pages SET body = body;
+
+CREATE INDEX pages_search_idx ON pages USING GIN (search);
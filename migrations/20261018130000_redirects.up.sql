@@ -0,0 +1,14 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS redirects (
+    id uuid NOT NULL,
+    source text NOT NULL,
+    target text NOT NULL,
+    status_code smallint DEFAULT 301 NOT NULL,
+    is_regex boolean DEFAULT false NOT NULL,
+    created_at timestamp default current_timestamp NOT NULL,
+    PRIMARY KEY (id),
+    UNIQUE (source),
+    CHECK (status_code IN (301, 302, 307, 308)),
+    -- Matching against the pattern once makes Postgres reject invalid regexes
+    CHECK (NOT is_regex OR ('' ~ source) IS NOT NULL)
+);
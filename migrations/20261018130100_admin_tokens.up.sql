@@ -0,0 +1,7 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS admin_tokens (
+    token_hash text NOT NULL,
+    admin_id uuid references admins(id) ON DELETE CASCADE NOT NULL,
+    created_at timestamp default current_timestamp NOT NULL,
+    PRIMARY KEY (token_hash)
+);
@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP TABLE menu_items;
+DROP TABLE menus;
+ALTER TABLE pages
+  DROP COLUMN sort_order RESTRICT;
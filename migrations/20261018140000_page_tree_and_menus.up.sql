@@ -0,0 +1,22 @@
+-- Add up migration script here
+ALTER TABLE pages
+  ADD COLUMN sort_order integer DEFAULT 0 NOT NULL;
+
+CREATE TABLE IF NOT EXISTS menus (
+    name text NOT NULL,
+    PRIMARY KEY (name)
+);
+
+-- Items either link to a managed page or to an arbitrary url, never both
+CREATE TABLE IF NOT EXISTS menu_items (
+    id uuid NOT NULL,
+    menu text references menus(name) ON UPDATE CASCADE ON DELETE CASCADE NOT NULL,
+    sort_order integer DEFAULT 0 NOT NULL,
+    label text NOT NULL,
+    page_path text references pages(path) ON UPDATE CASCADE ON DELETE CASCADE,
+    url text,
+    PRIMARY KEY (id),
+    CHECK ((page_path IS NULL) <> (url IS NULL))
+);
+
+CREATE INDEX menu_items_menu_idx ON menu_items (menu, sort_order);
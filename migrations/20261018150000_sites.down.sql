@@ -0,0 +1,34 @@
+-- Add down migration script here
+DROP TABLE site_grants;
+ALTER TABLE admins DROP COLUMN superuser RESTRICT;
+
+-- Only the default site survives going back to a single site
+DELETE FROM sites WHERE NOT is_default;
+
+ALTER TABLE redirects
+  DROP CONSTRAINT redirects_site_id_source_key,
+  ADD UNIQUE (source);
+
+ALTER TABLE menu_items
+  DROP CONSTRAINT menu_items_menu_fkey,
+  DROP CONSTRAINT menu_items_page_path_fkey;
+
+ALTER TABLE menus
+  DROP CONSTRAINT menus_pkey,
+  ADD PRIMARY KEY (name);
+
+ALTER TABLE pages
+  DROP CONSTRAINT pages_pkey,
+  ADD PRIMARY KEY (path);
+
+ALTER TABLE menu_items
+  ADD CONSTRAINT menu_items_menu_fkey FOREIGN KEY (menu)
+    references menus(name) ON UPDATE CASCADE ON DELETE CASCADE,
+  ADD CONSTRAINT menu_items_page_path_fkey FOREIGN KEY (page_path)
+    references pages(path) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE redirects DROP COLUMN site_id RESTRICT;
+ALTER TABLE menu_items DROP COLUMN site_id RESTRICT;
+ALTER TABLE menus DROP COLUMN site_id RESTRICT;
+ALTER TABLE pages DROP COLUMN site_id RESTRICT;
+DROP TABLE sites;
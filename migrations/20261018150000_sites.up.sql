@@ -0,0 +1,64 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS sites (
+    id uuid NOT NULL,
+    host text NOT NULL,
+    name text NOT NULL,
+    -- Requests for hosts without a site of their own fall back to this one
+    is_default boolean DEFAULT false NOT NULL,
+    PRIMARY KEY (id),
+    UNIQUE (host)
+);
+
+CREATE UNIQUE INDEX sites_default_idx ON sites (is_default) WHERE is_default;
+
+INSERT INTO sites VALUES (md5(random()::text)::uuid, 'localhost', 'Default', true);
+
+-- Everything that used to be global now belongs to the default site
+ALTER TABLE pages ADD COLUMN site_id uuid references sites(id) ON DELETE CASCADE;
+UPDATE pages SET site_id = (SELECT id FROM sites WHERE is_default);
+ALTER TABLE pages ALTER COLUMN site_id SET NOT NULL;
+
+ALTER TABLE menus ADD COLUMN site_id uuid references sites(id) ON DELETE CASCADE;
+UPDATE menus SET site_id = (SELECT id FROM sites WHERE is_default);
+ALTER TABLE menus ALTER COLUMN site_id SET NOT NULL;
+
+ALTER TABLE menu_items ADD COLUMN site_id uuid;
+UPDATE menu_items SET site_id = (SELECT id FROM sites WHERE is_default);
+ALTER TABLE menu_items ALTER COLUMN site_id SET NOT NULL;
+
+ALTER TABLE redirects ADD COLUMN site_id uuid references sites(id) ON DELETE CASCADE;
+UPDATE redirects SET site_id = (SELECT id FROM sites WHERE is_default);
+ALTER TABLE redirects ALTER COLUMN site_id SET NOT NULL;
+
+ALTER TABLE menu_items
+  DROP CONSTRAINT menu_items_menu_fkey,
+  DROP CONSTRAINT menu_items_page_path_fkey;
+
+ALTER TABLE pages
+  DROP CONSTRAINT pages_pkey,
+  ADD PRIMARY KEY (site_id, path);
+
+ALTER TABLE menus
+  DROP CONSTRAINT menus_pkey,
+  ADD PRIMARY KEY (site_id, name);
+
+ALTER TABLE menu_items
+  ADD CONSTRAINT menu_items_menu_fkey FOREIGN KEY (site_id, menu)
+    references menus(site_id, name) ON UPDATE CASCADE ON DELETE CASCADE,
+  ADD CONSTRAINT menu_items_page_path_fkey FOREIGN KEY (site_id, page_path)
+    references pages(site_id, path) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE redirects
+  DROP CONSTRAINT redirects_source_key,
+  ADD UNIQUE (site_id, source);
+
+-- Superusers manage sites and users and can edit every site.
+-- Existing admins already had full access so they keep it.
+ALTER TABLE admins ADD COLUMN superuser boolean DEFAULT false NOT NULL;
+UPDATE admins SET superuser = true;
+
+CREATE TABLE IF NOT EXISTS site_grants (
+    admin_id uuid references admins(id) ON DELETE CASCADE NOT NULL,
+    site_id uuid references sites(id) ON DELETE CASCADE NOT NULL,
+    PRIMARY KEY (admin_id, site_id)
+);
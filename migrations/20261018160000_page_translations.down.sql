@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE page_translations;
+ALTER TABLE sites DROP COLUMN default_locale;
@@ -0,0 +1,20 @@
+-- Add up migration script here
+-- The page row itself is written in the site's default locale
+ALTER TABLE sites ADD COLUMN default_locale text NOT NULL DEFAULT 'en'
+  CHECK (default_locale ~ '^[a-z]{2,3}(-[a-z0-9]{2,8})*$');
+
+CREATE TABLE page_translations (
+  site_id uuid NOT NULL,
+  "path" text NOT NULL,
+  locale text NOT NULL CHECK (locale ~ '^[a-z]{2,3}(-[a-z0-9]{2,8})*$'),
+  created_at timestamp NOT NULL,
+  created_by uuid NOT NULL references admins(id),
+  modified_at timestamp NOT NULL,
+  modified_by uuid NOT NULL references admins(id),
+  published boolean NOT NULL,
+  metadata text[] NOT NULL DEFAULT '{}',
+  body text NOT NULL,
+  PRIMARY KEY (site_id, "path", locale),
+  FOREIGN KEY (site_id, "path") references pages(site_id, "path")
+    ON UPDATE CASCADE ON DELETE CASCADE
+);
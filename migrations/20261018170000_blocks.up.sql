@@ -0,0 +1,9 @@
+-- Add up migration script here
+CREATE TABLE blocks (
+  site_id uuid NOT NULL references sites(id) ON DELETE CASCADE,
+  name text NOT NULL CHECK (name ~ '^[a-z0-9_-]+$'),
+  body text NOT NULL,
+  modified_at timestamp NOT NULL,
+  modified_by uuid NOT NULL references admins(id),
+  PRIMARY KEY (site_id, name)
+);
@@ -0,0 +1,6 @@
+-- Add down migration script here
+ALTER TABLE pages
+  DROP COLUMN fields,
+  DROP COLUMN content_type;
+
+DROP TABLE content_types;
@@ -0,0 +1,14 @@
+-- Add up migration script here
+CREATE TABLE content_types (
+  site_id uuid NOT NULL references sites(id) ON DELETE CASCADE,
+  name text NOT NULL CHECK (name ~ '^[a-z0-9_-]+$'),
+  label text NOT NULL,
+  fields jsonb NOT NULL DEFAULT '[]' CHECK (jsonb_typeof(fields) = 'array'),
+  template text NOT NULL DEFAULT '',
+  PRIMARY KEY (site_id, name)
+);
+
+ALTER TABLE pages
+  ADD COLUMN content_type text,
+  ADD COLUMN fields jsonb NOT NULL DEFAULT '{}' CHECK (jsonb_typeof(fields) = 'object'),
+  ADD FOREIGN KEY (site_id, content_type) references content_types(site_id, name) ON UPDATE CASCADE;
@@ -0,0 +1,8 @@
+-- Add up migration script here
+CREATE TABLE api_keys (
+  id uuid PRIMARY KEY,
+  site_id uuid NOT NULL references sites(id) ON DELETE CASCADE,
+  name text NOT NULL,
+  key_hash text NOT NULL UNIQUE,
+  created_at timestamp NOT NULL DEFAULT current_timestamp
+);
@@ -0,0 +1,30 @@
+-- Add up migration script here
+CREATE TABLE webhooks (
+  id uuid PRIMARY KEY,
+  site_id uuid NOT NULL references sites(id) ON DELETE CASCADE,
+  url text NOT NULL CHECK (url ~ '^https?://'),
+  secret text NOT NULL,
+  -- Empty means every event
+  events text[] NOT NULL DEFAULT '{}' CHECK (
+    events <@ ARRAY['page.created', 'page.updated', 'page.published', 'page.unpublished', 'page.deleted']
+  ),
+  enabled boolean NOT NULL DEFAULT true,
+  created_at timestamp NOT NULL DEFAULT current_timestamp
+);
+
+CREATE TABLE webhook_deliveries (
+  id uuid PRIMARY KEY,
+  webhook_id uuid NOT NULL references webhooks(id) ON DELETE CASCADE,
+  event text NOT NULL,
+  payload jsonb NOT NULL,
+  status text NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'delivered', 'failed')),
+  attempts integer NOT NULL DEFAULT 0,
+  next_attempt_at timestamp NOT NULL,
+  last_status_code smallint,
+  last_error text,
+  created_at timestamp NOT NULL,
+  delivered_at timestamp
+);
+
+CREATE INDEX webhook_deliveries_pending ON webhook_deliveries (next_attempt_at) WHERE status = 'pending';
+CREATE INDEX webhook_deliveries_webhook ON webhook_deliveries (webhook_id, created_at);
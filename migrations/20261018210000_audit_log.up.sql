@@ -0,0 +1,29 @@
+-- Add up migration script here
+-- Rows outlive what they describe, so nothing here references other tables
+CREATE TABLE audit_log (
+  id uuid PRIMARY KEY,
+  -- NULL for commands the server issues itself, like creating the first admin
+  actor uuid,
+  ip text,
+  action text NOT NULL,
+  site_id uuid,
+  target_type text NOT NULL,
+  target text NOT NULL,
+  before jsonb,
+  after jsonb,
+  created_at timestamp NOT NULL
+);
+
+CREATE INDEX audit_log_site_id_idx ON audit_log (site_id, id);
+CREATE INDEX audit_log_actor_idx ON audit_log (actor, id);
+CREATE INDEX audit_log_target_idx ON audit_log (target_type, target, id);
+
+CREATE FUNCTION audit_log_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_append_only
+  BEFORE UPDATE OR DELETE OR TRUNCATE ON audit_log
+  FOR EACH STATEMENT EXECUTE FUNCTION audit_log_append_only();
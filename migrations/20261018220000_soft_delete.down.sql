@@ -0,0 +1,4 @@
+-- Add down migration script here
+DELETE FROM pages WHERE deleted_at IS NOT NULL;
+ALTER TABLE pages DROP COLUMN deleted_at;
+ALTER TABLE admins DROP COLUMN deleted_at;
@@ -0,0 +1,7 @@
+-- Add up migration script here
+-- Deleted pages and users stay around until the purge task removes them
+ALTER TABLE pages ADD COLUMN deleted_at timestamp;
+ALTER TABLE admins ADD COLUMN deleted_at timestamp;
+
+CREATE INDEX pages_deleted_at_idx ON pages (deleted_at) WHERE deleted_at IS NOT NULL;
+CREATE INDEX admins_deleted_at_idx ON admins (deleted_at) WHERE deleted_at IS NOT NULL;
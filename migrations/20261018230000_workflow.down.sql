@@ -0,0 +1,7 @@
+-- Add down migration script here
+DROP TABLE workflow_comments;
+DROP TABLE page_drafts;
+DROP TRIGGER workflow_default_transitions ON sites;
+DROP FUNCTION workflow_default_transitions();
+DROP TABLE workflow_transitions;
+ALTER TABLE sites DROP COLUMN require_review;
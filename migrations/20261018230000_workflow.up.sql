@@ -0,0 +1,71 @@
+-- Add up migration script here
+-- With require_review set, only superusers may change what is public without going through a draft
+ALTER TABLE sites ADD COLUMN require_review boolean NOT NULL DEFAULT false;
+
+-- Who may take a transition: anyone editing the site, the assigned reviewer or superusers.
+-- Superusers may take every transition there is.
+CREATE TABLE workflow_transitions (
+  site_id uuid NOT NULL references sites(id) ON DELETE CASCADE,
+  from_state text NOT NULL CHECK (from_state IN ('draft', 'in_review', 'approved')),
+  to_state text NOT NULL CHECK (to_state IN ('draft', 'in_review', 'approved', 'published')),
+  allowed text NOT NULL DEFAULT 'editor' CHECK (allowed IN ('editor', 'reviewer', 'superuser')),
+  PRIMARY KEY (site_id, from_state, to_state),
+  CHECK (from_state <> to_state)
+);
+
+CREATE FUNCTION workflow_default_transitions() RETURNS trigger AS $$
+BEGIN
+    INSERT INTO workflow_transitions (site_id, from_state, to_state, allowed) VALUES
+        (NEW.id, 'draft', 'in_review', 'editor'),
+        (NEW.id, 'in_review', 'draft', 'reviewer'),
+        (NEW.id, 'in_review', 'approved', 'reviewer'),
+        (NEW.id, 'approved', 'draft', 'editor'),
+        (NEW.id, 'approved', 'published', 'editor');
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER workflow_default_transitions
+  AFTER INSERT ON sites
+  FOR EACH ROW EXECUTE FUNCTION workflow_default_transitions();
+
+INSERT INTO workflow_transitions (site_id, from_state, to_state, allowed)
+SELECT sites.id, defaults.from_state, defaults.to_state, defaults.allowed
+FROM sites, (VALUES
+    ('draft', 'in_review', 'editor'),
+    ('in_review', 'draft', 'reviewer'),
+    ('in_review', 'approved', 'reviewer'),
+    ('approved', 'draft', 'editor'),
+    ('approved', 'published', 'editor')
+) AS defaults (from_state, to_state, allowed);
+
+-- The next version of a page, pages itself keeps what is public until the draft is published
+CREATE TABLE page_drafts (
+  site_id uuid NOT NULL references sites(id) ON DELETE CASCADE,
+  "path" text NOT NULL CHECK ("path" ~ '^/'),
+  state text NOT NULL DEFAULT 'draft' CHECK (state IN ('draft', 'in_review', 'approved')),
+  reviewer_id uuid references admins(id) ON DELETE SET NULL,
+  created_at timestamp NOT NULL,
+  created_by uuid NOT NULL references admins(id),
+  modified_at timestamp NOT NULL,
+  modified_by uuid NOT NULL references admins(id),
+  metadata text[] NOT NULL DEFAULT '{}',
+  body text NOT NULL,
+  sort_order integer NOT NULL DEFAULT 0,
+  content_type text,
+  fields jsonb NOT NULL DEFAULT '{}' CHECK (jsonb_typeof(fields) = 'object'),
+  PRIMARY KEY (site_id, "path"),
+  FOREIGN KEY (site_id, content_type) references content_types(site_id, name) ON UPDATE CASCADE
+);
+
+-- Kept per path rather than per draft so the review history outlives publishing
+CREATE TABLE workflow_comments (
+  id uuid PRIMARY KEY,
+  site_id uuid NOT NULL references sites(id) ON DELETE CASCADE,
+  "path" text NOT NULL,
+  author_id uuid NOT NULL references admins(id),
+  body text NOT NULL,
+  created_at timestamp NOT NULL
+);
+
+CREATE INDEX workflow_comments_path_idx ON workflow_comments (site_id, "path", created_at);
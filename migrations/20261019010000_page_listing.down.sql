@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP INDEX pages_modified_at_idx;
+DROP INDEX pages_created_at_idx;
@@ -0,0 +1,4 @@
+-- Add up migration script here
+-- ListPages walks these newest first, the path breaks ties between equal times
+CREATE INDEX pages_created_at_idx ON pages (site_id, created_at DESC, "path" DESC) WHERE deleted_at IS NULL;
+CREATE INDEX pages_modified_at_idx ON pages (site_id, modified_at DESC, "path" DESC) WHERE deleted_at IS NULL;
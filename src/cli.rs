@@ -0,0 +1,332 @@
+/*
+ * cli.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * One-off commands run instead of the server, see USAGE. Without --site
+ * they work on the default site. Pages from WordPress and Markdown without
+ * a known author are attributed to --author, or the first superuser.
+ * Static exports are served from https://HOST unless --base-url says otherwise.
+ */
+
+use crate::{
+    database::{schema, Database},
+    plugin::Plugins,
+    transfer::{self, archive, markdown, wxr},
+    util::println,
+    web::static_export,
+};
+use color_eyre::{eyre::eyre, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+pub const USAGE: &str = "Usage:
+  magnetite-cms                 Start the server
+  magnetite-cms export [--site HOST] FILE
+  magnetite-cms import [--site HOST] [--dry-run] [--policy skip|overwrite|rename] FILE
+  magnetite-cms import-wordpress [--site HOST] [--author USERNAME] [--dry-run] [--policy ...] FILE
+  magnetite-cms import-markdown [--site HOST] [--author USERNAME] [--dry-run] [--policy ...] DIR
+  magnetite-cms export-static [--site HOST] [--base-url URL] [--assets DIR] [--full] DIR";
+
+pub enum Command {
+    Export {
+        site: Option<String>,
+        file: String,
+    },
+    Import {
+        site: Option<String>,
+        options: schema::ImportOptions,
+        file: String,
+    },
+    ImportWordpress {
+        site: Option<String>,
+        author: Option<String>,
+        options: schema::ImportOptions,
+        file: String,
+    },
+    ImportMarkdown {
+        site: Option<String>,
+        author: Option<String>,
+        options: schema::ImportOptions,
+        dir: String,
+    },
+    ExportStatic {
+        site: Option<String>,
+        base_url: Option<String>,
+        assets: Option<String>,
+        full: bool,
+        dir: String,
+    },
+}
+
+// None means no command was given and the server should start
+pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Command>, String> {
+    let name = match args.next() {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+
+    let importing = name.starts_with("import");
+    let converting = importing && name != "import";
+    let mut site = None;
+    let mut author = None;
+    let mut options = schema::ImportOptions::default();
+    let exporting = name == "export-static";
+    let mut base_url = None;
+    let mut assets = None;
+    let mut full = false;
+    let mut file = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--site" => site = Some(args.next().ok_or("--site needs a host")?),
+            "--author" if converting => {
+                author = Some(args.next().ok_or("--author needs a username")?)
+            }
+            "--base-url" if exporting => {
+                base_url = Some(args.next().ok_or("--base-url needs a URL")?)
+            }
+            "--assets" if exporting => {
+                assets = Some(args.next().ok_or("--assets needs a directory")?)
+            }
+            "--full" if exporting => full = true,
+            "--dry-run" if importing => options.dry_run = true,
+            "--policy" if importing => {
+                options.policy = match args.next().as_deref() {
+                    Some("skip") => schema::ConflictPolicy::Skip,
+                    Some("overwrite") => schema::ConflictPolicy::Overwrite,
+                    Some("rename") => schema::ConflictPolicy::Rename,
+                    _ => return Err(String::from("--policy is skip, overwrite or rename")),
+                }
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ if file.is_none() => file = Some(arg),
+            _ => return Err(format!("Unexpected argument {}", arg)),
+        }
+    }
+
+    let file = file.ok_or("Missing FILE")?;
+    match name.as_str() {
+        "export" => Ok(Some(Command::Export { site, file })),
+        "import" => Ok(Some(Command::Import {
+            site,
+            options,
+            file,
+        })),
+        "import-wordpress" => Ok(Some(Command::ImportWordpress {
+            site,
+            author,
+            options,
+            file,
+        })),
+        "import-markdown" => Ok(Some(Command::ImportMarkdown {
+            site,
+            author,
+            options,
+            dir: file,
+        })),
+        "export-static" => Ok(Some(Command::ExportStatic {
+            site,
+            base_url,
+            assets,
+            full,
+            dir: file,
+        })),
+        _ => Err(format!("Unknown command {}", name)),
+    }
+}
+
+pub async fn run(command: Command, db: &Database, plugins: Arc<Plugins>) -> Result<()> {
+    match command {
+        Command::Export { site, file } => {
+            let site = find_site(db, site).await?;
+            let export = db.export_site(site.id).await?;
+            fs::write(&file, archive::pack(&export)?)?;
+
+            println::important(format!(
+                "Exported {} pages of {} to {}",
+                export.pages.len(),
+                site.host,
+                file
+            ));
+        }
+        Command::Import {
+            site,
+            options,
+            file,
+        } => {
+            let site = find_site(db, site).await?;
+            let export = archive::unpack(&fs::read(&file)?)?;
+            let report = db.import_site(site.id, export, options).await?;
+            print_report(&report, &file, &site);
+        }
+        Command::ImportWordpress {
+            site,
+            author,
+            options,
+            file,
+        } => {
+            let site = find_site(db, site).await?;
+            let author = find_author(db, author).await?;
+            let converted = wxr::convert(&fs::read_to_string(&file)?, site.id, &author)?;
+            let report = transfer::import(db, site.id, converted, options).await?;
+            print_report(&report, &file, &site);
+        }
+        Command::ImportMarkdown {
+            site,
+            author,
+            options,
+            dir,
+        } => {
+            let site = find_site(db, site).await?;
+            let author = find_author(db, author).await?;
+            let converted = markdown::convert(Path::new(&dir), site.id, &author)?;
+            let report = transfer::import(db, site.id, converted, options).await?;
+            print_report(&report, &dir, &site);
+        }
+        Command::ExportStatic {
+            site,
+            base_url,
+            assets,
+            full,
+            dir,
+        } => {
+            let site = find_site(db, site).await?;
+            let options = static_export::Options {
+                dir: PathBuf::from(&dir),
+                base_url: base_url.unwrap_or_else(|| format!("https://{}", site.host)),
+                assets: assets.map(PathBuf::from),
+                full,
+            };
+            let summary = static_export::export(&site, db.clone(), plugins, &options).await?;
+
+            println::important(format!(
+                "Exported {} to {}: {} pages rendered, {} unchanged, {} removed, {} assets copied",
+                site.host,
+                dir,
+                summary.rendered,
+                summary.unchanged,
+                summary.removed,
+                summary.assets
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn print_report(report: &schema::ImportReport, source: &str, site: &schema::Site) {
+    for item in &report.items {
+        let line = match &item.detail {
+            Some(detail) => format!("{} {} {}: {}", item.outcome, item.kind, item.key, detail),
+            None => format!("{} {} {}", item.outcome, item.kind, item.key),
+        };
+        match item.outcome {
+            "unmapped" => println::warn(line),
+            _ => println::info(line),
+        }
+    }
+
+    match report.dry_run {
+        true => println::warn("Dry run, nothing was changed"),
+        false => println::important(format!("Imported {} into {}", source, site.host)),
+    }
+}
+
+async fn find_author(db: &Database, username: Option<String>) -> Result<schema::AdminUser> {
+    let users = db.get_users().await?;
+
+    let user = match &username {
+        Some(username) => users.into_iter().find(|user| &user.username == username),
+        None => users
+            .into_iter()
+            .find(|user| user.enabled && user.superuser),
+    };
+
+    user.ok_or_else(|| match username {
+        Some(username) => eyre!("No user named {}", username),
+        None => eyre!("No enabled superuser, pick an author with --author"),
+    })
+}
+
+// Unlike requests, an unknown host is an error instead of the default site
+async fn find_site(db: &Database, host: Option<String>) -> Result<schema::Site> {
+    let sites = db.get_sites().await?;
+
+    let site = match &host {
+        Some(host) => sites.into_iter().find(|site| &site.host == host),
+        None => sites.into_iter().find(|site| site.is_default),
+    };
+
+    site.ok_or_else(|| match host {
+        Some(host) => eyre!("No site with host {}", host),
+        None => eyre!("No default site"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Option<Command>, String> {
+        super::parse(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn starts_the_server_without_a_command() {
+        assert!(matches!(parse(""), Ok(None)));
+    }
+
+    #[test]
+    fn parses_commands_and_their_options() {
+        assert!(matches!(
+            parse("export --site example.com site.tar"),
+            Ok(Some(Command::Export { site: Some(site), file })) if site == "example.com" && file == "site.tar"
+        ));
+
+        let Ok(Some(Command::ImportWordpress {
+            site: None,
+            author: Some(author),
+            options,
+            file,
+        })) = parse("import-wordpress --dry-run --author jane --policy rename wp.xml")
+        else {
+            panic!("not an import-wordpress command");
+        };
+        assert_eq!(author, "jane");
+        assert!(options.dry_run);
+        assert_eq!(options.policy, schema::ConflictPolicy::Rename);
+        assert_eq!(file, "wp.xml");
+
+        assert!(matches!(
+            parse("import-markdown content"),
+            Ok(Some(Command::ImportMarkdown { author: None, dir, .. })) if dir == "content"
+        ));
+        assert!(matches!(
+            parse("export-static --full --base-url https://cdn.example.com --assets public out"),
+            Ok(Some(Command::ExportStatic { full: true, base_url: Some(_), assets: Some(_), dir, .. })) if dir == "out"
+        ));
+    }
+
+    #[test]
+    fn rejects_what_a_command_does_not_take() {
+        for (args, err) in [
+            ("export", "Missing FILE"),
+            ("export a b", "Unexpected argument b"),
+            ("export --dry-run a", "Unknown option --dry-run"),
+            ("import --author jane a", "Unknown option --author"),
+            (
+                "import --policy merge a",
+                "--policy is skip, overwrite or rename",
+            ),
+            ("import a --site", "--site needs a host"),
+            ("export-static --full", "Missing FILE"),
+            ("serve a", "Unknown command serve"),
+        ] {
+            assert_eq!(parse(args).err().as_deref(), Some(err), "{}", args);
+        }
+    }
+}
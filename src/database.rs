@@ -6,40 +6,289 @@
  */
 
 use super::util::println;
+use chrono::{NaiveDateTime, TimeDelta};
 use color_eyre::Result;
 use sqlx::postgres::PgPoolOptions;
-use tokio::sync::mpsc::error::TryRecvError;
+use std::fmt;
 use tokio::sync::{mpsc, oneshot};
 use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
 use uuid::Uuid;
 
+mod audit;
+mod batch;
 mod cache;
 mod process;
 pub mod schema;
+mod transfer;
+mod trash;
+mod webhook;
+
+pub use webhook::check_url as check_webhook_url;
 
 // This enum contains all possible commands that can be issued to the database
 pub enum DatabaseMpscCommand {
-    // GetPage(path, skip_cache, reply)
+    // GetPage(site_id, path, skip_cache, reply)
     // -> Result<schema::Page>
-    GetPage(String, bool, DatabaseOneshotReply<schema::Page>),
+    GetPage(Uuid, String, bool, DatabaseOneshotReply<schema::Page>),
+
+    // ListPages(site_id, query, reply)
+    // -> Result<Vec<schema::Page>>
+    ListPages(
+        Uuid,
+        schema::PageListQuery,
+        DatabaseOneshotReply<Vec<schema::Page>>,
+    ),
+
+    // GetPagesByPath(site_id, paths, reply)
+    // -> Result<Vec<schema::Page>>
+    GetPagesByPath(Uuid, Vec<String>, DatabaseOneshotReply<Vec<schema::Page>>),
+
+    // GetPageChildren(site_id, path, reply)
+    // -> Result<Vec<schema::Page>>
+    GetPageChildren(Uuid, String, DatabaseOneshotReply<Vec<schema::Page>>),
+
+    // GetChildrenOfPages(site_id, paths, reply)
+    // -> Result<Vec<schema::Page>>
+    GetChildrenOfPages(Uuid, Vec<String>, DatabaseOneshotReply<Vec<schema::Page>>),
+
+    // GetPagesByPrefix(site_id, prefix, include_unpublished, limit, offset, reply)
+    // -> Result<Vec<schema::Page>>
+    GetPagesByPrefix(
+        Uuid,
+        String,
+        bool,
+        i64,
+        i64,
+        DatabaseOneshotReply<Vec<schema::Page>>,
+    ),
+
+    // SearchPages(site_id, query, limit, reply)
+    // -> Result<Vec<schema::SearchResult>>
+    SearchPages(
+        Uuid,
+        String,
+        i64,
+        DatabaseOneshotReply<Vec<schema::SearchResult>>,
+    ),
 
     // SetPage(new_page, reply)
-    // -> Result<()>
-    SetPage(schema::Page, DatabaseOneshotReply<()>),
+    // -> Result<i32>, the page's new version
+    SetPage(schema::Page, DatabaseOneshotReply<i32>),
 
-    // DeletePage(path, reply)
+    // DeletePage(site_id, path, reply)
     // -> Result<()>
-    DeletePage(String, DatabaseOneshotReply<()>),
+    DeletePage(Uuid, String, DatabaseOneshotReply<()>),
 
     // NewPage(new_page, reply)
     // -> Result<()>
     NewPage(schema::Page, DatabaseOneshotReply<()>),
 
+    // Batch(ops, reply)
+    // -> Result<()>, all of the ops are applied or none
+    Batch(Vec<PageOp>, DatabaseOneshotReply<()>),
+
+    // GetPageTranslations(site_id, path, reply)
+    // -> Result<Vec<schema::PageTranslation>>
+    GetPageTranslations(
+        Uuid,
+        String,
+        DatabaseOneshotReply<Vec<schema::PageTranslation>>,
+    ),
+
+    // SetPageTranslation(translation, reply)
+    // -> Result<()>
+    SetPageTranslation(schema::PageTranslation, DatabaseOneshotReply<()>),
+
+    // DeletePageTranslation(site_id, path, locale, reply)
+    // -> Result<()>
+    DeletePageTranslation(Uuid, String, String, DatabaseOneshotReply<()>),
+
+    // GetRedirect(site_id, path, reply)
+    // -> Result<Option<schema::Redirect>>
+    GetRedirect(Uuid, String, DatabaseOneshotReply<Option<schema::Redirect>>),
+
+    // GetRedirects(site_id, reply)
+    // -> Result<Vec<schema::Redirect>>
+    GetRedirects(Uuid, DatabaseOneshotReply<Vec<schema::Redirect>>),
+
+    // NewRedirect(new_redirect, reply)
+    // -> Result<()>
+    NewRedirect(schema::Redirect, DatabaseOneshotReply<()>),
+
+    // DeleteRedirect(site_id, id, reply)
+    // -> Result<()>
+    DeleteRedirect(Uuid, Uuid, DatabaseOneshotReply<()>),
+
+    // GetMenus(site_id, reply)
+    // -> Result<Vec<schema::Menu>>
+    GetMenus(Uuid, DatabaseOneshotReply<Vec<schema::Menu>>),
+
+    // SetMenu(new_menu, reply)
+    // -> Result<()>
+    SetMenu(schema::Menu, DatabaseOneshotReply<()>),
+
+    // DeleteMenu(site_id, name, reply)
+    // -> Result<()>
+    DeleteMenu(Uuid, String, DatabaseOneshotReply<()>),
+
+    // GetBlocks(site_id, reply)
+    // -> Result<Vec<schema::Block>>
+    GetBlocks(Uuid, DatabaseOneshotReply<Vec<schema::Block>>),
+
+    // GetBlock(site_id, name, reply)
+    // -> Result<schema::Block>
+    GetBlock(Uuid, String, DatabaseOneshotReply<schema::Block>),
+
+    // SetBlock(block, reply)
+    // -> Result<()>
+    SetBlock(schema::Block, DatabaseOneshotReply<()>),
+
+    // DeleteBlock(site_id, name, reply)
+    // -> Result<()>
+    DeleteBlock(Uuid, String, DatabaseOneshotReply<()>),
+
+    // GetContentTypes(site_id, reply)
+    // -> Result<Vec<schema::ContentType>>
+    GetContentTypes(Uuid, DatabaseOneshotReply<Vec<schema::ContentType>>),
+
+    // GetContentType(site_id, name, reply)
+    // -> Result<schema::ContentType>
+    GetContentType(Uuid, String, DatabaseOneshotReply<schema::ContentType>),
+
+    // SetContentType(content_type, reply)
+    // -> Result<()>
+    SetContentType(schema::ContentType, DatabaseOneshotReply<()>),
+
+    // DeleteContentType(site_id, name, reply)
+    // -> Result<()>
+    DeleteContentType(Uuid, String, DatabaseOneshotReply<()>),
+
+    // GetRenderedPage(site_id, path, locale, reply)
+    // -> Result<Option<schema::RenderedPage>>
+    GetRenderedPage(
+        Uuid,
+        String,
+        String,
+        DatabaseOneshotReply<Option<schema::RenderedPage>>,
+    ),
+
+    // SetRenderedPage(rendered, reply)
+    // -> Result<()>
+    SetRenderedPage(schema::RenderedPage, DatabaseOneshotReply<()>),
+
+    // GetSites(reply)
+    // -> Result<Vec<schema::Site>>
+    GetSites(DatabaseOneshotReply<Vec<schema::Site>>),
+
+    // GetSite(id, reply)
+    // -> Result<schema::Site>
+    GetSite(Uuid, DatabaseOneshotReply<schema::Site>),
+
+    // GetSiteByHost(host, reply)
+    // -> Result<schema::Site>
+    GetSiteByHost(String, DatabaseOneshotReply<schema::Site>),
+
+    // SetSite(new_site, reply)
+    // -> Result<()>
+    SetSite(schema::Site, DatabaseOneshotReply<()>),
+
+    // DeleteSite(id, reply)
+    // -> Result<()>
+    DeleteSite(Uuid, DatabaseOneshotReply<()>),
+
+    // NewSite(new_site, reply)
+    // -> Result<()>
+    NewSite(schema::Site, DatabaseOneshotReply<()>),
+
+    // GetPageDrafts(site_id, reply)
+    // -> Result<Vec<schema::PageDraft>>
+    GetPageDrafts(Uuid, DatabaseOneshotReply<Vec<schema::PageDraft>>),
+
+    // GetPageDraft(site_id, path, reply)
+    // -> Result<schema::PageDraft>
+    GetPageDraft(Uuid, String, DatabaseOneshotReply<schema::PageDraft>),
+
+    // SetPageDraft(draft, reply)
+    // -> Result<()>
+    SetPageDraft(schema::PageDraft, DatabaseOneshotReply<()>),
+
+    // DeletePageDraft(site_id, path, reply)
+    // -> Result<()>
+    DeletePageDraft(Uuid, String, DatabaseOneshotReply<()>),
+
+    // TransitionPageDraft(site_id, path, from_state, to_state, reply)
+    // -> Result<()>
+    TransitionPageDraft(Uuid, String, String, String, DatabaseOneshotReply<()>),
+
+    // SetPageDraftReviewer(site_id, path, reviewer_id, reply)
+    // -> Result<()>
+    SetPageDraftReviewer(Uuid, String, Option<Uuid>, DatabaseOneshotReply<()>),
+
+    // GetWorkflowComments(site_id, path, reply)
+    // -> Result<Vec<schema::WorkflowComment>>
+    GetWorkflowComments(
+        Uuid,
+        String,
+        DatabaseOneshotReply<Vec<schema::WorkflowComment>>,
+    ),
+
+    // NewWorkflowComment(comment, reply)
+    // -> Result<()>
+    NewWorkflowComment(schema::WorkflowComment, DatabaseOneshotReply<()>),
+
+    // GetWorkflowTransitions(site_id, reply)
+    // -> Result<Vec<schema::WorkflowTransition>>
+    GetWorkflowTransitions(Uuid, DatabaseOneshotReply<Vec<schema::WorkflowTransition>>),
+
+    // SetWorkflowTransitions(site_id, transitions, reply)
+    // -> Result<()>
+    SetWorkflowTransitions(
+        Uuid,
+        Vec<schema::WorkflowTransition>,
+        DatabaseOneshotReply<()>,
+    ),
+
+    // GetPageTrash(site_id, reply)
+    // -> Result<Vec<schema::TrashedPage>>
+    GetPageTrash(Uuid, DatabaseOneshotReply<Vec<schema::TrashedPage>>),
+
+    // RestorePage(site_id, path, reply)
+    // -> Result<()>
+    RestorePage(Uuid, String, DatabaseOneshotReply<()>),
+
+    // PurgePage(site_id, path, reply)
+    // -> Result<()>
+    PurgePage(Uuid, String, DatabaseOneshotReply<()>),
+
+    // GetUserTrash(reply)
+    // -> Result<Vec<schema::TrashedUser>>
+    GetUserTrash(DatabaseOneshotReply<Vec<schema::TrashedUser>>),
+
+    // RestoreUser(id, reply)
+    // -> Result<()>
+    RestoreUser(Uuid, DatabaseOneshotReply<()>),
+
+    // PurgeUser(id, reply)
+    // -> Result<()>
+    PurgeUser(Uuid, DatabaseOneshotReply<()>),
+
+    // PurgeTrash(deleted_before, reply)
+    // -> Result<u64>
+    PurgeTrash(NaiveDateTime, DatabaseOneshotReply<u64>),
+
+    // GetUsers(reply)
+    // -> Result<Vec<schema::AdminUser>>
+    GetUsers(DatabaseOneshotReply<Vec<schema::AdminUser>>),
+
     // GetUser(id, skip_cache, reply)
     // -> Result<schema::AdminUser>
     GetUser(Uuid, bool, DatabaseOneshotReply<schema::AdminUser>),
 
+    // GetUsersById(ids, reply)
+    // -> Result<Vec<schema::AdminUser>>
+    GetUsersById(Vec<Uuid>, DatabaseOneshotReply<Vec<schema::AdminUser>>),
+
     // SetUser(new_user, reply)
     // -> Result<()>
     SetUser(schema::AdminUser, DatabaseOneshotReply<()>),
@@ -51,47 +300,314 @@ pub enum DatabaseMpscCommand {
     // NewUser(new_user, reply)
     // -> Result<()>
     NewUser(schema::AdminUser, DatabaseOneshotReply<()>),
+
+    // GetUserByToken(token_hash, reply)
+    // -> Result<schema::AdminUser>
+    GetUserByToken(String, DatabaseOneshotReply<schema::AdminUser>),
+
+    // NewToken(admin_id, token_hash, reply)
+    // -> Result<()>
+    NewToken(Uuid, String, DatabaseOneshotReply<()>),
+
+    // GetApiKeys(site_id, reply)
+    // -> Result<Vec<schema::ApiKey>>
+    GetApiKeys(Uuid, DatabaseOneshotReply<Vec<schema::ApiKey>>),
+
+    // GetApiKeyByHash(key_hash, reply)
+    // -> Result<schema::ApiKey>
+    GetApiKeyByHash(String, DatabaseOneshotReply<schema::ApiKey>),
+
+    // NewApiKey(api_key, key_hash, reply)
+    // -> Result<()>
+    NewApiKey(schema::ApiKey, String, DatabaseOneshotReply<()>),
+
+    // DeleteApiKey(site_id, id, reply)
+    // -> Result<()>
+    DeleteApiKey(Uuid, Uuid, DatabaseOneshotReply<()>),
+
+    // GetWebhooks(site_id, reply)
+    // -> Result<Vec<schema::Webhook>>
+    GetWebhooks(Uuid, DatabaseOneshotReply<Vec<schema::Webhook>>),
+
+    // NewWebhook(webhook, reply)
+    // -> Result<()>
+    NewWebhook(schema::Webhook, DatabaseOneshotReply<()>),
+
+    // SetWebhook(webhook, reply)
+    // -> Result<()>
+    SetWebhook(schema::Webhook, DatabaseOneshotReply<()>),
+
+    // DeleteWebhook(site_id, id, reply)
+    // -> Result<()>
+    DeleteWebhook(Uuid, Uuid, DatabaseOneshotReply<()>),
+
+    // GetWebhookDeliveries(site_id, webhook_id, limit, reply)
+    // -> Result<Vec<schema::WebhookDelivery>>
+    GetWebhookDeliveries(
+        Uuid,
+        Uuid,
+        i64,
+        DatabaseOneshotReply<Vec<schema::WebhookDelivery>>,
+    ),
+
+    // ReplayWebhookDelivery(site_id, id, reply)
+    // -> Result<schema::WebhookDelivery>
+    ReplayWebhookDelivery(Uuid, Uuid, DatabaseOneshotReply<schema::WebhookDelivery>),
+
+    // GetAuditLog(query, reply)
+    // -> Result<Vec<schema::AuditLogEntry>>
+    GetAuditLog(
+        schema::AuditLogQuery,
+        DatabaseOneshotReply<Vec<schema::AuditLogEntry>>,
+    ),
+
+    // GetSiteGrants(admin_id, reply)
+    // -> Result<Vec<Uuid>>
+    GetSiteGrants(Uuid, DatabaseOneshotReply<Vec<Uuid>>),
+
+    // NewSiteGrant(admin_id, site_id, reply)
+    // -> Result<()>
+    NewSiteGrant(Uuid, Uuid, DatabaseOneshotReply<()>),
+
+    // DeleteSiteGrant(admin_id, site_id, reply)
+    // -> Result<()>
+    DeleteSiteGrant(Uuid, Uuid, DatabaseOneshotReply<()>),
+
+    // ExportSite(site_id, reply)
+    // -> Result<schema::SiteExport>
+    ExportSite(Uuid, DatabaseOneshotReply<schema::SiteExport>),
+
+    // ImportSite(site_id, export, options, reply)
+    // -> Result<schema::ImportReport>
+    ImportSite(
+        Uuid,
+        Box<schema::SiteExport>,
+        schema::ImportOptions,
+        DatabaseOneshotReply<schema::ImportReport>,
+    ),
 }
 
 pub type DatabaseOneshotReply<T> = oneshot::Sender<Result<T>>;
 
+// The page changes that can be combined into a Batch, like the commands without their replies
+pub enum PageOp {
+    // Fails the batch with VersionConflict like SetPage
+    Set(schema::Page),
+    Delete(Uuid, String),
+    // Leaves a 301 at the old path, there is no command for this on its own
+    Rename(Uuid, String, String),
+}
+
+// Returned by SetPage when the page was changed after the version it was read at
+#[derive(Debug)]
+pub struct VersionConflict;
+
+impl fmt::Display for VersionConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The page was changed by someone else")
+    }
+}
+
+impl std::error::Error for VersionConflict {}
+
+// Returned when a page would take the path of one in the trash, which keeps the
+// path until it is restored or purged
+#[derive(Debug)]
+pub struct PathInTrash(pub String);
+
+impl fmt::Display for PathInTrash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The trashed page at {0} still holds its path, restore it with POST or purge it with DELETE /admin/api/trash/pages{0}",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for PathInTrash {}
+
+// Who issued the commands sent through a Database, kept in the audit log
+#[derive(Debug, Clone)]
+pub struct Actor {
+    pub user_id: Uuid,
+    pub ip: Option<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Database {
-    tx: mpsc::Sender<DatabaseMpscCommand>,
+    tx: mpsc::Sender<(Option<Actor>, DatabaseMpscCommand)>,
+    actor: Option<Actor>,
 }
 
 impl Database {
-    pub async fn get_page<S>(&self, path: S, skip_cache: bool) -> Result<schema::Page>
+    // A handle whose mutating commands are logged as done by the actor
+    pub fn as_actor(&self, actor: Actor) -> Database {
+        Database {
+            tx: self.tx.clone(),
+            actor: Some(actor),
+        }
+    }
+
+    // A handle whose commands arrive on the receiver instead, for tests to answer by hand
+    #[cfg(test)]
+    pub fn stub() -> (
+        Database,
+        mpsc::Receiver<(Option<Actor>, DatabaseMpscCommand)>,
+    ) {
+        let (tx, rx) = mpsc::channel(16);
+        (Database { tx, actor: None }, rx)
+    }
+
+    async fn send(&self, cmd: DatabaseMpscCommand) -> Result<()> {
+        self.tx.send((self.actor.clone(), cmd)).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_page<S>(
+        &self,
+        site_id: Uuid,
+        path: S,
+        skip_cache: bool,
+    ) -> Result<schema::Page>
     where
         S: Into<String>,
     {
         let (tx, rx) = oneshot::channel::<Result<schema::Page>>();
 
-        self.tx
-            .send(DatabaseMpscCommand::GetPage(path.into(), skip_cache, tx))
+        self.send(DatabaseMpscCommand::GetPage(
+            site_id,
+            path.into(),
+            skip_cache,
+            tx,
+        ))
+        .await?;
+
+        rx.await?
+    }
+
+    pub async fn list_pages(
+        &self,
+        site_id: Uuid,
+        query: schema::PageListQuery,
+    ) -> Result<Vec<schema::Page>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::Page>>>();
+
+        self.send(DatabaseMpscCommand::ListPages(site_id, query, tx))
             .await?;
 
         rx.await?
     }
 
-    pub async fn set_page(&self, new_page: schema::Page) -> Result<()> {
-        let (tx, rx) = oneshot::channel::<Result<()>>();
+    pub async fn get_pages_by_path(
+        &self,
+        site_id: Uuid,
+        paths: Vec<String>,
+    ) -> Result<Vec<schema::Page>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::Page>>>();
+
+        self.send(DatabaseMpscCommand::GetPagesByPath(site_id, paths, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_page_children<S>(&self, site_id: Uuid, path: S) -> Result<Vec<schema::Page>>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::Page>>>();
+
+        self.send(DatabaseMpscCommand::GetPageChildren(
+            site_id,
+            path.into(),
+            tx,
+        ))
+        .await?;
+
+        rx.await?
+    }
+
+    // Direct children of every path in one query, callers group them by parent
+    pub async fn get_children_of_pages(
+        &self,
+        site_id: Uuid,
+        paths: Vec<String>,
+    ) -> Result<Vec<schema::Page>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::Page>>>();
+
+        self.send(DatabaseMpscCommand::GetChildrenOfPages(site_id, paths, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_pages_by_prefix<S>(
+        &self,
+        site_id: Uuid,
+        prefix: S,
+        include_unpublished: bool,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<schema::Page>>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::Page>>>();
+
+        self.send(DatabaseMpscCommand::GetPagesByPrefix(
+            site_id,
+            prefix.into(),
+            include_unpublished,
+            limit,
+            offset,
+            tx,
+        ))
+        .await?;
+
+        rx.await?
+    }
 
-        self.tx
-            .send(DatabaseMpscCommand::SetPage(new_page, tx))
+    pub async fn search_pages<S>(
+        &self,
+        site_id: Uuid,
+        query: S,
+        limit: i64,
+    ) -> Result<Vec<schema::SearchResult>>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::SearchResult>>>();
+
+        self.send(DatabaseMpscCommand::SearchPages(
+            site_id,
+            query.into(),
+            limit,
+            tx,
+        ))
+        .await?;
+
+        rx.await?
+    }
+
+    // new_page.version has to be the version the page was read at, else VersionConflict
+    pub async fn set_page(&self, new_page: schema::Page) -> Result<i32> {
+        let (tx, rx) = oneshot::channel::<Result<i32>>();
+
+        self.send(DatabaseMpscCommand::SetPage(new_page, tx))
             .await?;
 
         rx.await?
     }
 
-    pub async fn delete_page<S>(&self, path: S) -> Result<()>
+    pub async fn delete_page<S>(&self, site_id: Uuid, path: S) -> Result<()>
     where
         S: Into<String>,
     {
         let (tx, rx) = oneshot::channel::<Result<()>>();
 
-        self.tx
-            .send(DatabaseMpscCommand::DeletePage(path.into(), tx))
+        self.send(DatabaseMpscCommand::DeletePage(site_id, path.into(), tx))
             .await?;
 
         rx.await?
@@ -100,94 +616,843 @@ impl Database {
     pub async fn new_page(&self, new_page: schema::Page) -> Result<()> {
         let (tx, rx) = oneshot::channel::<Result<()>>();
 
-        self.tx
-            .send(DatabaseMpscCommand::NewPage(new_page, tx))
+        self.send(DatabaseMpscCommand::NewPage(new_page, tx))
             .await?;
 
         rx.await?
     }
 
-    pub async fn get_user(&self, id: Uuid, skip_cache: bool) -> Result<schema::AdminUser> {
-        let (tx, rx) = oneshot::channel::<Result<schema::AdminUser>>();
+    pub async fn batch(&self, ops: Vec<PageOp>) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::Batch(ops, tx)).await?;
+
+        rx.await?
+    }
 
-        self.tx
-            .send(DatabaseMpscCommand::GetUser(id, skip_cache, tx))
+    pub async fn get_page_translations<S>(
+        &self,
+        site_id: Uuid,
+        path: S,
+    ) -> Result<Vec<schema::PageTranslation>>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::PageTranslation>>>();
+
+        self.send(DatabaseMpscCommand::GetPageTranslations(
+            site_id,
+            path.into(),
+            tx,
+        ))
+        .await?;
+
+        rx.await?
+    }
+
+    pub async fn set_page_translation(&self, translation: schema::PageTranslation) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::SetPageTranslation(translation, tx))
             .await?;
 
         rx.await?
     }
 
-    pub async fn set_user(&self, new_user: schema::AdminUser) -> Result<()> {
+    pub async fn delete_page_translation<S>(&self, site_id: Uuid, path: S, locale: S) -> Result<()>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::DeletePageTranslation(
+            site_id,
+            path.into(),
+            locale.into(),
+            tx,
+        ))
+        .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_redirect<S>(&self, site_id: Uuid, path: S) -> Result<Option<schema::Redirect>>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<Option<schema::Redirect>>>();
+
+        self.send(DatabaseMpscCommand::GetRedirect(site_id, path.into(), tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_redirects(&self, site_id: Uuid) -> Result<Vec<schema::Redirect>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::Redirect>>>();
+
+        self.send(DatabaseMpscCommand::GetRedirects(site_id, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn new_redirect(&self, new_redirect: schema::Redirect) -> Result<()> {
         let (tx, rx) = oneshot::channel::<Result<()>>();
 
-        self.tx
-            .send(DatabaseMpscCommand::SetUser(new_user, tx))
+        self.send(DatabaseMpscCommand::NewRedirect(new_redirect, tx))
             .await?;
 
         rx.await?
     }
 
-    pub async fn delete_user(&self, id: Uuid) -> Result<()> {
+    pub async fn delete_redirect(&self, site_id: Uuid, id: Uuid) -> Result<()> {
         let (tx, rx) = oneshot::channel::<Result<()>>();
 
-        self.tx
-            .send(DatabaseMpscCommand::DeleteUser(id, tx))
+        self.send(DatabaseMpscCommand::DeleteRedirect(site_id, id, tx))
             .await?;
 
         rx.await?
     }
 
-    pub async fn new_user(&self, new_user: schema::AdminUser) -> Result<()> {
+    pub async fn get_menus(&self, site_id: Uuid) -> Result<Vec<schema::Menu>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::Menu>>>();
+
+        self.send(DatabaseMpscCommand::GetMenus(site_id, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn set_menu(&self, new_menu: schema::Menu) -> Result<()> {
         let (tx, rx) = oneshot::channel::<Result<()>>();
 
-        self.tx
-            .send(DatabaseMpscCommand::NewUser(new_user, tx))
+        self.send(DatabaseMpscCommand::SetMenu(new_menu, tx))
             .await?;
 
         rx.await?
     }
 
-    pub async fn new(
-        database_url: String,
-        tracker: &TaskTracker,
-        cancel_token: CancellationToken,
-    ) -> Result<Database> {
-        let cache_cancel_token = cancel_token.clone();
+    pub async fn delete_menu<S>(&self, site_id: Uuid, name: S) -> Result<()>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
 
-        // No idea if 10 is a big enough channel. Remember to change.
-        let (tx, mut rx) = mpsc::channel::<DatabaseMpscCommand>(10);
+        self.send(DatabaseMpscCommand::DeleteMenu(site_id, name.into(), tx))
+            .await?;
 
-        let pool = PgPoolOptions::new().connect(&database_url).await?;
+        rx.await?
+    }
 
-        // run migrations
-        println::info("Running DB Migrations");
-        sqlx::migrate!("./migrations").run(&pool).await?;
+    pub async fn get_blocks(&self, site_id: Uuid) -> Result<Vec<schema::Block>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::Block>>>();
 
-        let cache_tracker = tracker.clone();
+        self.send(DatabaseMpscCommand::GetBlocks(site_id, tx))
+            .await?;
 
-        tracker.spawn(async move {
-            let mut cache = cache::Cache::new(cache_tracker, cache_cancel_token).await;
-            loop {
-                match rx.try_recv() {
-                    Ok(cmd) => {
-                        process::cmd(cmd, &pool, &mut cache).await;
-                    }
-                    Err(err) => {
-                        if err == TryRecvError::Disconnected {
-                            // Throw error message and stop loop
-                            println::error("All transmitters have been disconnected. Exiting...");
-                            break;
-                        }
-                    }
-                }
+        rx.await?
+    }
 
-                if cancel_token.is_cancelled() {
-                    println::error("DB Cancellation Token Received...");
-                    break;
-                }
-            }
-        });
+    pub async fn get_block<S>(&self, site_id: Uuid, name: S) -> Result<schema::Block>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<schema::Block>>();
+
+        self.send(DatabaseMpscCommand::GetBlock(site_id, name.into(), tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn set_block(&self, block: schema::Block) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::SetBlock(block, tx)).await?;
+
+        rx.await?
+    }
+
+    pub async fn delete_block<S>(&self, site_id: Uuid, name: S) -> Result<()>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::DeleteBlock(site_id, name.into(), tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_content_types(&self, site_id: Uuid) -> Result<Vec<schema::ContentType>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::ContentType>>>();
+
+        self.send(DatabaseMpscCommand::GetContentTypes(site_id, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_content_type<S>(&self, site_id: Uuid, name: S) -> Result<schema::ContentType>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<schema::ContentType>>();
+
+        self.send(DatabaseMpscCommand::GetContentType(
+            site_id,
+            name.into(),
+            tx,
+        ))
+        .await?;
+
+        rx.await?
+    }
+
+    pub async fn set_content_type(&self, content_type: schema::ContentType) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::SetContentType(content_type, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn delete_content_type<S>(&self, site_id: Uuid, name: S) -> Result<()>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::DeleteContentType(
+            site_id,
+            name.into(),
+            tx,
+        ))
+        .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_rendered_page<S>(
+        &self,
+        site_id: Uuid,
+        path: S,
+        locale: S,
+    ) -> Result<Option<schema::RenderedPage>>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<Option<schema::RenderedPage>>>();
+
+        self.send(DatabaseMpscCommand::GetRenderedPage(
+            site_id,
+            path.into(),
+            locale.into(),
+            tx,
+        ))
+        .await?;
+
+        rx.await?
+    }
+
+    pub async fn set_rendered_page(&self, rendered: schema::RenderedPage) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::SetRenderedPage(rendered, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_sites(&self) -> Result<Vec<schema::Site>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::Site>>>();
+
+        self.send(DatabaseMpscCommand::GetSites(tx)).await?;
+
+        rx.await?
+    }
+
+    pub async fn get_site(&self, id: Uuid) -> Result<schema::Site> {
+        let (tx, rx) = oneshot::channel::<Result<schema::Site>>();
+
+        self.send(DatabaseMpscCommand::GetSite(id, tx)).await?;
+
+        rx.await?
+    }
+
+    pub async fn get_site_by_host<S>(&self, host: S) -> Result<schema::Site>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<schema::Site>>();
+
+        self.send(DatabaseMpscCommand::GetSiteByHost(host.into(), tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn set_site(&self, new_site: schema::Site) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::SetSite(new_site, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn delete_site(&self, id: Uuid) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::DeleteSite(id, tx)).await?;
+
+        rx.await?
+    }
+
+    pub async fn new_site(&self, new_site: schema::Site) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::NewSite(new_site, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_page_drafts(&self, site_id: Uuid) -> Result<Vec<schema::PageDraft>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::PageDraft>>>();
+
+        self.send(DatabaseMpscCommand::GetPageDrafts(site_id, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_page_draft<S>(&self, site_id: Uuid, path: S) -> Result<schema::PageDraft>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<schema::PageDraft>>();
+
+        self.send(DatabaseMpscCommand::GetPageDraft(site_id, path.into(), tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn set_page_draft(&self, draft: schema::PageDraft) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::SetPageDraft(draft, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn delete_page_draft<S>(&self, site_id: Uuid, path: S) -> Result<()>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::DeletePageDraft(
+            site_id,
+            path.into(),
+            tx,
+        ))
+        .await?;
+
+        rx.await?
+    }
+
+    pub async fn transition_page_draft<S>(
+        &self,
+        site_id: Uuid,
+        path: S,
+        from_state: S,
+        to_state: S,
+    ) -> Result<()>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::TransitionPageDraft(
+            site_id,
+            path.into(),
+            from_state.into(),
+            to_state.into(),
+            tx,
+        ))
+        .await?;
+
+        rx.await?
+    }
+
+    pub async fn set_page_draft_reviewer<S>(
+        &self,
+        site_id: Uuid,
+        path: S,
+        reviewer_id: Option<Uuid>,
+    ) -> Result<()>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::SetPageDraftReviewer(
+            site_id,
+            path.into(),
+            reviewer_id,
+            tx,
+        ))
+        .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_workflow_comments<S>(
+        &self,
+        site_id: Uuid,
+        path: S,
+    ) -> Result<Vec<schema::WorkflowComment>>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::WorkflowComment>>>();
+
+        self.send(DatabaseMpscCommand::GetWorkflowComments(
+            site_id,
+            path.into(),
+            tx,
+        ))
+        .await?;
+
+        rx.await?
+    }
+
+    pub async fn new_workflow_comment(&self, comment: schema::WorkflowComment) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::NewWorkflowComment(comment, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_workflow_transitions(
+        &self,
+        site_id: Uuid,
+    ) -> Result<Vec<schema::WorkflowTransition>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::WorkflowTransition>>>();
+
+        self.send(DatabaseMpscCommand::GetWorkflowTransitions(site_id, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn set_workflow_transitions(
+        &self,
+        site_id: Uuid,
+        transitions: Vec<schema::WorkflowTransition>,
+    ) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::SetWorkflowTransitions(
+            site_id,
+            transitions,
+            tx,
+        ))
+        .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_page_trash(&self, site_id: Uuid) -> Result<Vec<schema::TrashedPage>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::TrashedPage>>>();
+
+        self.send(DatabaseMpscCommand::GetPageTrash(site_id, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn restore_page<S>(&self, site_id: Uuid, path: S) -> Result<()>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::RestorePage(site_id, path.into(), tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn purge_page<S>(&self, site_id: Uuid, path: S) -> Result<()>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::PurgePage(site_id, path.into(), tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_user_trash(&self) -> Result<Vec<schema::TrashedUser>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::TrashedUser>>>();
+
+        self.send(DatabaseMpscCommand::GetUserTrash(tx)).await?;
+
+        rx.await?
+    }
+
+    pub async fn restore_user(&self, id: Uuid) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::RestoreUser(id, tx)).await?;
+
+        rx.await?
+    }
+
+    pub async fn purge_user(&self, id: Uuid) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::PurgeUser(id, tx)).await?;
+
+        rx.await?
+    }
+
+    pub async fn purge_trash(&self, deleted_before: NaiveDateTime) -> Result<u64> {
+        let (tx, rx) = oneshot::channel::<Result<u64>>();
+
+        self.send(DatabaseMpscCommand::PurgeTrash(deleted_before, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_users(&self) -> Result<Vec<schema::AdminUser>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::AdminUser>>>();
+
+        self.send(DatabaseMpscCommand::GetUsers(tx)).await?;
+
+        rx.await?
+    }
+
+    pub async fn get_user(&self, id: Uuid, skip_cache: bool) -> Result<schema::AdminUser> {
+        let (tx, rx) = oneshot::channel::<Result<schema::AdminUser>>();
+
+        self.send(DatabaseMpscCommand::GetUser(id, skip_cache, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_users_by_id(&self, ids: Vec<Uuid>) -> Result<Vec<schema::AdminUser>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::AdminUser>>>();
+
+        self.send(DatabaseMpscCommand::GetUsersById(ids, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn set_user(&self, new_user: schema::AdminUser) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::SetUser(new_user, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn delete_user(&self, id: Uuid) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::DeleteUser(id, tx)).await?;
+
+        rx.await?
+    }
+
+    pub async fn new_user(&self, new_user: schema::AdminUser) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::NewUser(new_user, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_user_by_token<S>(&self, token_hash: S) -> Result<schema::AdminUser>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<schema::AdminUser>>();
+
+        self.send(DatabaseMpscCommand::GetUserByToken(token_hash.into(), tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn new_token<S>(&self, admin_id: Uuid, token_hash: S) -> Result<()>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::NewToken(
+            admin_id,
+            token_hash.into(),
+            tx,
+        ))
+        .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_api_keys(&self, site_id: Uuid) -> Result<Vec<schema::ApiKey>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::ApiKey>>>();
+
+        self.send(DatabaseMpscCommand::GetApiKeys(site_id, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_api_key_by_hash<S>(&self, key_hash: S) -> Result<schema::ApiKey>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<schema::ApiKey>>();
+
+        self.send(DatabaseMpscCommand::GetApiKeyByHash(key_hash.into(), tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn new_api_key<S>(&self, api_key: schema::ApiKey, key_hash: S) -> Result<()>
+    where
+        S: Into<String>,
+    {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::NewApiKey(api_key, key_hash.into(), tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn delete_api_key(&self, site_id: Uuid, id: Uuid) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::DeleteApiKey(site_id, id, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_webhooks(&self, site_id: Uuid) -> Result<Vec<schema::Webhook>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::Webhook>>>();
+
+        self.send(DatabaseMpscCommand::GetWebhooks(site_id, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn new_webhook(&self, webhook: schema::Webhook) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::NewWebhook(webhook, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn set_webhook(&self, webhook: schema::Webhook) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::SetWebhook(webhook, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn delete_webhook(&self, site_id: Uuid, id: Uuid) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::DeleteWebhook(site_id, id, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_webhook_deliveries(
+        &self,
+        site_id: Uuid,
+        webhook_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<schema::WebhookDelivery>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::WebhookDelivery>>>();
+
+        self.send(DatabaseMpscCommand::GetWebhookDeliveries(
+            site_id, webhook_id, limit, tx,
+        ))
+        .await?;
+
+        rx.await?
+    }
+
+    pub async fn replay_webhook_delivery(
+        &self,
+        site_id: Uuid,
+        id: Uuid,
+    ) -> Result<schema::WebhookDelivery> {
+        let (tx, rx) = oneshot::channel::<Result<schema::WebhookDelivery>>();
+
+        self.send(DatabaseMpscCommand::ReplayWebhookDelivery(site_id, id, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_audit_log(
+        &self,
+        query: schema::AuditLogQuery,
+    ) -> Result<Vec<schema::AuditLogEntry>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<schema::AuditLogEntry>>>();
+
+        self.send(DatabaseMpscCommand::GetAuditLog(query, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn get_site_grants(&self, admin_id: Uuid) -> Result<Vec<Uuid>> {
+        let (tx, rx) = oneshot::channel::<Result<Vec<Uuid>>>();
+
+        self.send(DatabaseMpscCommand::GetSiteGrants(admin_id, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn new_site_grant(&self, admin_id: Uuid, site_id: Uuid) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::NewSiteGrant(admin_id, site_id, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn delete_site_grant(&self, admin_id: Uuid, site_id: Uuid) -> Result<()> {
+        let (tx, rx) = oneshot::channel::<Result<()>>();
+
+        self.send(DatabaseMpscCommand::DeleteSiteGrant(admin_id, site_id, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn export_site(&self, site_id: Uuid) -> Result<schema::SiteExport> {
+        let (tx, rx) = oneshot::channel::<Result<schema::SiteExport>>();
+
+        self.send(DatabaseMpscCommand::ExportSite(site_id, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    pub async fn import_site(
+        &self,
+        site_id: Uuid,
+        export: schema::SiteExport,
+        options: schema::ImportOptions,
+    ) -> Result<schema::ImportReport> {
+        let (tx, rx) = oneshot::channel::<Result<schema::ImportReport>>();
+
+        self.send(DatabaseMpscCommand::ImportSite(
+            site_id,
+            Box::new(export),
+            options,
+            tx,
+        ))
+        .await?;
+
+        rx.await?
+    }
+
+    pub async fn new(
+        database_url: String,
+        trash_retention: TimeDelta,
+        tracker: &TaskTracker,
+        cancel_token: CancellationToken,
+    ) -> Result<Database> {
+        let cache_cancel_token = cancel_token.clone();
+
+        // No idea if 10 is a big enough channel. Remember to change.
+        let (tx, mut rx) = mpsc::channel::<(Option<Actor>, DatabaseMpscCommand)>(10);
+
+        let pool = PgPoolOptions::new().connect(&database_url).await?;
+
+        // run migrations
+        println::info("Running DB Migrations");
+        sqlx::migrate!("./migrations").run(&pool).await?;
+
+        let webhooks = webhook::Webhooks::new(pool.clone());
+        match webhook::client() {
+            Ok(client) => webhooks.spawn_worker(
+                webhook::Worker::new(pool.clone(), client),
+                tracker,
+                cancel_token.clone(),
+            ),
+            Err(err) => println::error(format!("Webhooks disabled, no HTTP client: {}", err)),
+        }
+        let trash_cancel_token = cancel_token.clone();
+        let cache_tracker = tracker.clone();
+
+        tracker.spawn(async move {
+            let mut cache = cache::Cache::new(cache_tracker, cache_cancel_token).await;
+            // Waiting on the channel instead of polling it keeps this task from
+            // pinning a worker thread, which would starve tasks woken by it
+            loop {
+                let cmd = tokio::select! {
+                    cmd = rx.recv() => cmd,
+                    _ = cancel_token.cancelled() => {
+                        println::error("DB Cancellation Token Received...");
+                        break;
+                    }
+                };
+
+                match cmd {
+                    Some((actor, cmd)) => {
+                        let audit = audit::Pending::start(&pool, &cmd).await;
+                        process::cmd(cmd, actor.clone(), &pool, &mut cache, &webhooks).await;
+                        if let Some(audit) = audit {
+                            audit.finish(&pool, actor).await;
+                        }
+                    }
+                    None => {
+                        // Throw error message and stop loop
+                        println::error("All transmitters have been disconnected. Exiting...");
+                        break;
+                    }
+                }
+            }
+        });
+
+        let db = Database { tx, actor: None };
+        trash::spawn_purge(db.clone(), trash_retention, tracker, trash_cancel_token);
 
-        Ok(Database { tx })
+        Ok(db)
     }
 }
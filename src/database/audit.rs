@@ -0,0 +1,712 @@
+/*
+ * database/audit.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * Append-only audit log of mutating commands. The target of a command is
+ * read as JSON before and after it runs and a row is only written when the
+ * two differ, so failed commands leave no trace. Secrets and hashes are
+ * left out of the snapshots. Page changes and batches, and deletes of users
+ * and sites, write their entry in the transaction of the change itself, an
+ * entry that can't be written rolls them back. Everything else is logged
+ * right after it ran.
+ */
+
+use super::{Actor, DatabaseMpscCommand, PageOp};
+use crate::util::println;
+use chrono::{NaiveDateTime, Utc};
+use color_eyre::Result;
+use serde_json::Value;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+// The row a command changes, as far as it can be found before the command runs
+#[derive(Clone, PartialEq)]
+enum Target {
+    Page(Uuid, String),
+    PageTranslation(Uuid, String, String),
+    Redirect(Uuid, Uuid),
+    Menu(Uuid, String),
+    Block(Uuid, String),
+    ContentType(Uuid, String),
+    Site(Uuid),
+    User(Uuid),
+    Token(String),
+    ApiKey(Uuid, Uuid),
+    Webhook(Uuid, Uuid),
+    // The newest delivery of the webhook the given delivery belongs to
+    LatestDelivery(Uuid, Uuid),
+    SiteGrant(Uuid, Uuid),
+    PageDraft(Uuid, String),
+    WorkflowComment(Uuid),
+    WorkflowTransitions(Uuid),
+    // Everything deleted before the time, as lists of keys
+    ExpiredTrash(NaiveDateTime),
+    // The site and the keys of what it holds, with page versions
+    SiteContent(Uuid),
+}
+
+pub struct Pending {
+    action: &'static str,
+    site_id: Option<Uuid>,
+    target_type: &'static str,
+    target: String,
+    before_target: Target,
+    // Renames read the new key after the command
+    after_target: Target,
+    before: Option<Value>,
+}
+
+impl Pending {
+    // None for commands that don't change anything or write their own entry
+    pub async fn start(pool: &PgPool, cmd: &DatabaseMpscCommand) -> Option<Pending> {
+        let description = describe(cmd)?;
+        match pool.acquire().await {
+            Ok(mut conn) => Some(Pending::start_in(&mut conn, description).await),
+            Err(err) => {
+                println::error(format!("Failed to read audit snapshot: {}", err));
+                None
+            }
+        }
+    }
+
+    // For commands writing their entry themselves, conn is their transaction
+    pub async fn start_in(conn: &mut PgConnection, description: Description) -> Pending {
+        let Description(action, site_id, target_type, target, before_target, after_target) =
+            description;
+        let before = snapshot(conn, &before_target).await;
+
+        Pending {
+            action,
+            site_id,
+            target_type,
+            target,
+            before_target,
+            after_target,
+            before,
+        }
+    }
+
+    // Failing to write the entry is logged, the command itself already happened
+    pub async fn finish(self, pool: &PgPool, actor: Option<Actor>) {
+        let action = self.action;
+        let result = match pool.acquire().await {
+            Ok(mut conn) => self.write(&mut conn, actor).await,
+            Err(err) => Err(err.into()),
+        };
+
+        if let Err(err) = result {
+            println::error(format!("Failed to write audit log for {}: {}", action, err));
+        }
+    }
+
+    pub async fn write(self, conn: &mut PgConnection, actor: Option<Actor>) -> Result<()> {
+        let after = snapshot(conn, &self.after_target).await;
+        if after == self.before {
+            return Ok(());
+        }
+        // A rename that went through leaves nothing behind at the old key
+        if self.before_target != self.after_target
+            && snapshot(conn, &self.before_target).await.is_some()
+        {
+            return Ok(());
+        }
+
+        let (actor, ip) = match actor {
+            Some(actor) => (Some(actor.user_id), actor.ip),
+            None => (None, None),
+        };
+
+        sqlx::query!(
+            "INSERT INTO audit_log (id, actor, ip, action, site_id, target_type, target, before, after, created_at)
+            VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+            Uuid::now_v7(),
+            actor,
+            ip,
+            self.action,
+            self.site_id,
+            self.target_type,
+            self.target,
+            self.before,
+            after,
+            Utc::now().naive_utc()
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+// What Pending needs to know about a command before it runs
+pub struct Description(
+    &'static str,
+    Option<Uuid>,
+    &'static str,
+    String,
+    Target,
+    Target,
+);
+
+// For commands that read the same target before and after
+fn entry(
+    action: &'static str,
+    site_id: Option<Uuid>,
+    target_type: &'static str,
+    target: String,
+    snapshot_target: Target,
+) -> Description {
+    Description(
+        action,
+        site_id,
+        target_type,
+        target,
+        snapshot_target.clone(),
+        snapshot_target,
+    )
+}
+
+pub fn page_entry(action: &'static str, site_id: Uuid, path: &str) -> Description {
+    let target = Target::Page(site_id, path.to_string());
+    entry(action, Some(site_id), "page", path.to_string(), target)
+}
+
+pub fn site_entry(action: &'static str, id: Uuid) -> Description {
+    entry(action, Some(id), "site", id.to_string(), Target::Site(id))
+}
+
+pub fn user_entry(action: &'static str, id: Uuid) -> Description {
+    entry(action, None, "user", id.to_string(), Target::User(id))
+}
+
+pub fn rename_entry(site_id: Uuid, from: &str, to: &str) -> Description {
+    Description(
+        "rename_page",
+        Some(site_id),
+        "page",
+        from.to_string(),
+        Target::Page(site_id, from.to_string()),
+        Target::Page(site_id, to.to_string()),
+    )
+}
+
+// Ops in a batch are logged the same as the commands they stand for
+pub fn describe_op(op: &PageOp) -> Description {
+    match op {
+        PageOp::Set(page) => page_entry("set_page", page.site_id, &page.path),
+        PageOp::Delete(site_id, path) => page_entry("delete_page", *site_id, path),
+        PageOp::Rename(site_id, from, to) => rename_entry(*site_id, from, to),
+    }
+}
+
+fn describe(cmd: &DatabaseMpscCommand) -> Option<Description> {
+    use DatabaseMpscCommand as Cmd;
+
+    let description = match cmd {
+        Cmd::NewPage(page, _) => page_entry("new_page", page.site_id, &page.path),
+        Cmd::RestorePage(site_id, path, _) => page_entry("restore_page", *site_id, path),
+        Cmd::PurgePage(site_id, path, _) => page_entry("purge_page", *site_id, path),
+        Cmd::SetPageDraft(draft, _) => entry(
+            "set_page_draft",
+            Some(draft.site_id),
+            "page_draft",
+            draft.path.clone(),
+            Target::PageDraft(draft.site_id, draft.path.clone()),
+        ),
+        Cmd::DeletePageDraft(site_id, path, _) => entry(
+            "delete_page_draft",
+            Some(*site_id),
+            "page_draft",
+            path.clone(),
+            Target::PageDraft(*site_id, path.clone()),
+        ),
+        // Publishing removes the draft, the page it became is logged under the same path
+        Cmd::TransitionPageDraft(site_id, path, _, to_state, _) => entry(
+            if to_state == "published" {
+                "publish_page_draft"
+            } else {
+                "transition_page_draft"
+            },
+            Some(*site_id),
+            "page_draft",
+            path.clone(),
+            Target::PageDraft(*site_id, path.clone()),
+        ),
+        Cmd::SetPageDraftReviewer(site_id, path, _, _) => entry(
+            "set_page_draft_reviewer",
+            Some(*site_id),
+            "page_draft",
+            path.clone(),
+            Target::PageDraft(*site_id, path.clone()),
+        ),
+        Cmd::NewWorkflowComment(comment, _) => entry(
+            "new_workflow_comment",
+            Some(comment.site_id),
+            "workflow_comment",
+            comment.id.to_string(),
+            Target::WorkflowComment(comment.id),
+        ),
+        Cmd::SetWorkflowTransitions(site_id, _, _) => entry(
+            "set_workflow_transitions",
+            Some(*site_id),
+            "workflow_transitions",
+            site_id.to_string(),
+            Target::WorkflowTransitions(*site_id),
+        ),
+        Cmd::SetPageTranslation(translation, _) => {
+            let target = Target::PageTranslation(
+                translation.site_id,
+                translation.path.clone(),
+                translation.locale.clone(),
+            );
+            entry(
+                "set_page_translation",
+                Some(translation.site_id),
+                "page_translation",
+                format!("{}@{}", translation.path, translation.locale),
+                target,
+            )
+        }
+        Cmd::DeletePageTranslation(site_id, path, locale, _) => {
+            let target = Target::PageTranslation(*site_id, path.clone(), locale.clone());
+            entry(
+                "delete_page_translation",
+                Some(*site_id),
+                "page_translation",
+                format!("{}@{}", path, locale),
+                target,
+            )
+        }
+        Cmd::NewRedirect(redirect, _) => entry(
+            "new_redirect",
+            Some(redirect.site_id),
+            "redirect",
+            redirect.id.to_string(),
+            Target::Redirect(redirect.site_id, redirect.id),
+        ),
+        Cmd::DeleteRedirect(site_id, id, _) => entry(
+            "delete_redirect",
+            Some(*site_id),
+            "redirect",
+            id.to_string(),
+            Target::Redirect(*site_id, *id),
+        ),
+        Cmd::SetMenu(menu, _) => entry(
+            "set_menu",
+            Some(menu.site_id),
+            "menu",
+            menu.name.clone(),
+            Target::Menu(menu.site_id, menu.name.clone()),
+        ),
+        Cmd::DeleteMenu(site_id, name, _) => entry(
+            "delete_menu",
+            Some(*site_id),
+            "menu",
+            name.clone(),
+            Target::Menu(*site_id, name.clone()),
+        ),
+        Cmd::SetBlock(block, _) => entry(
+            "set_block",
+            Some(block.site_id),
+            "block",
+            block.name.clone(),
+            Target::Block(block.site_id, block.name.clone()),
+        ),
+        Cmd::DeleteBlock(site_id, name, _) => entry(
+            "delete_block",
+            Some(*site_id),
+            "block",
+            name.clone(),
+            Target::Block(*site_id, name.clone()),
+        ),
+        Cmd::SetContentType(content_type, _) => entry(
+            "set_content_type",
+            Some(content_type.site_id),
+            "content_type",
+            content_type.name.clone(),
+            Target::ContentType(content_type.site_id, content_type.name.clone()),
+        ),
+        Cmd::DeleteContentType(site_id, name, _) => entry(
+            "delete_content_type",
+            Some(*site_id),
+            "content_type",
+            name.clone(),
+            Target::ContentType(*site_id, name.clone()),
+        ),
+        Cmd::SetSite(site, _) => site_entry("set_site", site.id),
+        Cmd::NewSite(site, _) => site_entry("new_site", site.id),
+        Cmd::SetUser(user, _) => user_entry("set_user", user.id),
+        Cmd::NewUser(user, _) => user_entry("new_user", user.id),
+        Cmd::RestoreUser(id, _) => user_entry("restore_user", *id),
+        Cmd::PurgeUser(id, _) => user_entry("purge_user", *id),
+        // After holds what the purge had to keep
+        Cmd::PurgeTrash(deleted_before, _) => entry(
+            "purge_trash",
+            None,
+            "trash",
+            deleted_before.to_string(),
+            Target::ExpiredTrash(*deleted_before),
+        ),
+        // Logged against the user, the hash never leaves the database
+        Cmd::NewToken(admin_id, token_hash, _) => entry(
+            "new_token",
+            None,
+            "user",
+            admin_id.to_string(),
+            Target::Token(token_hash.clone()),
+        ),
+        Cmd::NewApiKey(api_key, _, _) => entry(
+            "new_api_key",
+            Some(api_key.site_id),
+            "api_key",
+            api_key.id.to_string(),
+            Target::ApiKey(api_key.site_id, api_key.id),
+        ),
+        Cmd::DeleteApiKey(site_id, id, _) => entry(
+            "delete_api_key",
+            Some(*site_id),
+            "api_key",
+            id.to_string(),
+            Target::ApiKey(*site_id, *id),
+        ),
+        Cmd::NewWebhook(webhook, _) => entry(
+            "new_webhook",
+            Some(webhook.site_id),
+            "webhook",
+            webhook.id.to_string(),
+            Target::Webhook(webhook.site_id, webhook.id),
+        ),
+        Cmd::SetWebhook(webhook, _) => entry(
+            "set_webhook",
+            Some(webhook.site_id),
+            "webhook",
+            webhook.id.to_string(),
+            Target::Webhook(webhook.site_id, webhook.id),
+        ),
+        Cmd::DeleteWebhook(site_id, id, _) => entry(
+            "delete_webhook",
+            Some(*site_id),
+            "webhook",
+            id.to_string(),
+            Target::Webhook(*site_id, *id),
+        ),
+        Cmd::ReplayWebhookDelivery(site_id, id, _) => entry(
+            "replay_webhook_delivery",
+            Some(*site_id),
+            "webhook_delivery",
+            id.to_string(),
+            Target::LatestDelivery(*site_id, *id),
+        ),
+        Cmd::NewSiteGrant(admin_id, site_id, _) => entry(
+            "new_site_grant",
+            Some(*site_id),
+            "user",
+            admin_id.to_string(),
+            Target::SiteGrant(*admin_id, *site_id),
+        ),
+        Cmd::DeleteSiteGrant(admin_id, site_id, _) => entry(
+            "delete_site_grant",
+            Some(*site_id),
+            "user",
+            admin_id.to_string(),
+            Target::SiteGrant(*admin_id, *site_id),
+        ),
+        // Dry runs are rolled back, they fall through to the reads below
+        Cmd::ImportSite(site_id, _, options, _) if !options.dry_run => entry(
+            "import_site",
+            Some(*site_id),
+            "site",
+            site_id.to_string(),
+            Target::SiteContent(*site_id),
+        ),
+
+        // Written in the transaction of the change, see process::cmd and batch::run
+        Cmd::SetPage(..)
+        | Cmd::DeletePage(..)
+        | Cmd::Batch(..)
+        | Cmd::DeleteSite(..)
+        | Cmd::DeleteUser(..) => return None,
+
+        // Listed one by one so new commands have to decide whether they are audited
+        Cmd::GetPage(..)
+        | Cmd::ListPages(..)
+        | Cmd::GetPagesByPath(..)
+        | Cmd::GetPageChildren(..)
+        | Cmd::GetChildrenOfPages(..)
+        | Cmd::GetPagesByPrefix(..)
+        | Cmd::SearchPages(..)
+        | Cmd::GetPageTranslations(..)
+        | Cmd::GetRedirect(..)
+        | Cmd::GetRedirects(..)
+        | Cmd::GetMenus(..)
+        | Cmd::GetBlocks(..)
+        | Cmd::GetBlock(..)
+        | Cmd::GetContentTypes(..)
+        | Cmd::GetContentType(..)
+        | Cmd::GetRenderedPage(..)
+        | Cmd::SetRenderedPage(..)
+        | Cmd::GetSites(..)
+        | Cmd::GetSite(..)
+        | Cmd::GetSiteByHost(..)
+        | Cmd::GetPageDrafts(..)
+        | Cmd::GetPageDraft(..)
+        | Cmd::GetWorkflowComments(..)
+        | Cmd::GetWorkflowTransitions(..)
+        | Cmd::GetPageTrash(..)
+        | Cmd::GetUserTrash(..)
+        | Cmd::GetUsers(..)
+        | Cmd::GetUser(..)
+        | Cmd::GetUsersById(..)
+        | Cmd::GetUserByToken(..)
+        | Cmd::GetApiKeys(..)
+        | Cmd::GetApiKeyByHash(..)
+        | Cmd::GetWebhooks(..)
+        | Cmd::GetWebhookDeliveries(..)
+        | Cmd::GetAuditLog(..)
+        | Cmd::GetSiteGrants(..)
+        | Cmd::ExportSite(..)
+        | Cmd::ImportSite(..) => return None,
+    };
+
+    Some(description)
+}
+
+// The target as JSON, None when it doesn't exist or can't be read
+async fn snapshot(conn: &mut PgConnection, target: &Target) -> Option<Value> {
+    let result = match target {
+        // The search vector is derived from the rest of the row
+        Target::Page(site_id, path) => {
+            sqlx::query_scalar!(
+                "SELECT to_jsonb(pages) - 'search' AS \"snapshot!\" FROM pages
+                WHERE site_id = $1 AND \"path\" = $2",
+                site_id,
+                path
+            )
+            .fetch_optional(&mut *conn)
+            .await
+        }
+        Target::PageTranslation(site_id, path, locale) => {
+            sqlx::query_scalar!(
+                "SELECT to_jsonb(page_translations) AS \"snapshot!\" FROM page_translations
+                WHERE site_id = $1 AND \"path\" = $2 AND locale = $3",
+                site_id,
+                path,
+                locale
+            )
+            .fetch_optional(&mut *conn)
+            .await
+        }
+        Target::Redirect(site_id, id) => {
+            sqlx::query_scalar!(
+                "SELECT to_jsonb(redirects) AS \"snapshot!\" FROM redirects
+                WHERE site_id = $1 AND id = $2",
+                site_id,
+                id
+            )
+            .fetch_optional(&mut *conn)
+            .await
+        }
+        Target::Menu(site_id, name) => {
+            sqlx::query_scalar!(
+                "SELECT jsonb_build_object(
+                    'name', name,
+                    'items', (
+                        SELECT coalesce(jsonb_agg(to_jsonb(menu_items) - 'site_id' ORDER BY sort_order), '[]')
+                        FROM menu_items
+                        WHERE menu_items.site_id = menus.site_id AND menu = menus.name
+                    )
+                ) AS \"snapshot!\" FROM menus
+                WHERE site_id = $1 AND name = $2",
+                site_id,
+                name
+            )
+            .fetch_optional(&mut *conn)
+            .await
+        }
+        Target::Block(site_id, name) => {
+            sqlx::query_scalar!(
+                "SELECT to_jsonb(blocks) AS \"snapshot!\" FROM blocks
+                WHERE site_id = $1 AND name = $2",
+                site_id,
+                name
+            )
+            .fetch_optional(&mut *conn)
+            .await
+        }
+        Target::ContentType(site_id, name) => {
+            sqlx::query_scalar!(
+                "SELECT to_jsonb(content_types) AS \"snapshot!\" FROM content_types
+                WHERE site_id = $1 AND name = $2",
+                site_id,
+                name
+            )
+            .fetch_optional(&mut *conn)
+            .await
+        }
+        Target::Site(id) => {
+            sqlx::query_scalar!(
+                "SELECT to_jsonb(sites) AS \"snapshot!\" FROM sites WHERE id = $1",
+                id
+            )
+            .fetch_optional(&mut *conn)
+            .await
+        }
+        Target::User(id) => {
+            sqlx::query_scalar!(
+                "SELECT to_jsonb(admins) AS \"snapshot!\" FROM admins WHERE id = $1",
+                id
+            )
+            .fetch_optional(&mut *conn)
+            .await
+        }
+        Target::Token(token_hash) => {
+            sqlx::query_scalar!(
+                "SELECT to_jsonb(admin_tokens) - 'token_hash' AS \"snapshot!\" FROM admin_tokens
+                WHERE token_hash = $1",
+                token_hash
+            )
+            .fetch_optional(&mut *conn)
+            .await
+        }
+        Target::ApiKey(site_id, id) => {
+            sqlx::query_scalar!(
+                "SELECT to_jsonb(api_keys) - 'key_hash' AS \"snapshot!\" FROM api_keys
+                WHERE site_id = $1 AND id = $2",
+                site_id,
+                id
+            )
+            .fetch_optional(&mut *conn)
+            .await
+        }
+        Target::Webhook(site_id, id) => {
+            sqlx::query_scalar!(
+                "SELECT to_jsonb(webhooks) - 'secret' AS \"snapshot!\" FROM webhooks
+                WHERE site_id = $1 AND id = $2",
+                site_id,
+                id
+            )
+            .fetch_optional(&mut *conn)
+            .await
+        }
+        Target::LatestDelivery(site_id, id) => {
+            sqlx::query_scalar!(
+                "SELECT to_jsonb(webhook_deliveries) - 'payload' AS \"snapshot!\" FROM webhook_deliveries
+                WHERE webhook_id = (
+                    SELECT webhook_id FROM webhook_deliveries
+                    JOIN webhooks ON webhooks.id = webhook_deliveries.webhook_id
+                    WHERE webhooks.site_id = $1 AND webhook_deliveries.id = $2
+                )
+                ORDER BY created_at DESC
+                LIMIT 1",
+                site_id,
+                id
+            )
+            .fetch_optional(&mut *conn)
+            .await
+        }
+        Target::SiteGrant(admin_id, site_id) => {
+            sqlx::query_scalar!(
+                "SELECT to_jsonb(site_grants) AS \"snapshot!\" FROM site_grants
+                WHERE admin_id = $1 AND site_id = $2",
+                admin_id,
+                site_id
+            )
+            .fetch_optional(&mut *conn)
+            .await
+        }
+        Target::PageDraft(site_id, path) => {
+            sqlx::query_scalar!(
+                "SELECT to_jsonb(page_drafts) AS \"snapshot!\" FROM page_drafts
+                WHERE site_id = $1 AND \"path\" = $2",
+                site_id,
+                path
+            )
+            .fetch_optional(&mut *conn)
+            .await
+        }
+        Target::WorkflowComment(id) => {
+            sqlx::query_scalar!(
+                "SELECT to_jsonb(workflow_comments) AS \"snapshot!\" FROM workflow_comments
+                WHERE id = $1",
+                id
+            )
+            .fetch_optional(&mut *conn)
+            .await
+        }
+        Target::WorkflowTransitions(site_id) => {
+            sqlx::query_scalar!(
+                "SELECT coalesce(
+                    jsonb_agg(to_jsonb(workflow_transitions) - 'site_id' ORDER BY from_state, to_state),
+                    '[]'
+                ) AS \"snapshot!\"
+                FROM workflow_transitions WHERE site_id = $1",
+                site_id
+            )
+            .fetch_optional(&mut *conn)
+            .await
+        }
+        Target::ExpiredTrash(deleted_before) => {
+            sqlx::query_scalar!(
+                "SELECT jsonb_build_object(
+                    'pages', (
+                        SELECT coalesce(jsonb_agg(jsonb_build_object('site_id', site_id, 'path', \"path\")), '[]')
+                        FROM pages WHERE deleted_at < $1
+                    ),
+                    'users', (
+                        SELECT coalesce(jsonb_agg(id), '[]') FROM admins WHERE deleted_at < $1
+                    )
+                ) AS \"snapshot!\"",
+                deleted_before
+            )
+            .fetch_optional(&mut *conn)
+            .await
+        }
+        Target::SiteContent(site_id) => {
+            sqlx::query_scalar!(
+                "SELECT jsonb_build_object(
+                    'site', to_jsonb(sites),
+                    'pages', (
+                        SELECT coalesce(jsonb_object_agg(\"path\", version), '{}')
+                        FROM pages WHERE site_id = $1 AND deleted_at IS NULL
+                    ),
+                    'translations', (
+                        SELECT coalesce(jsonb_agg(\"path\" || '@' || locale ORDER BY \"path\", locale), '[]')
+                        FROM page_translations WHERE site_id = $1
+                    ),
+                    'menus', (
+                        SELECT coalesce(jsonb_object_agg(menu, items), '{}') FROM (
+                            SELECT menu, count(*) AS items FROM menu_items
+                            WHERE site_id = $1 GROUP BY menu
+                        ) AS menu_counts
+                    ),
+                    'blocks', (
+                        SELECT coalesce(jsonb_object_agg(name, modified_at), '{}')
+                        FROM blocks WHERE site_id = $1
+                    ),
+                    'content_types', (
+                        SELECT coalesce(jsonb_agg(name ORDER BY name), '[]')
+                        FROM content_types WHERE site_id = $1
+                    ),
+                    'redirects', (
+                        SELECT coalesce(jsonb_object_agg(source, target), '{}')
+                        FROM redirects WHERE site_id = $1
+                    ),
+                    'users', (SELECT count(*) FROM admins)
+                ) AS \"snapshot!\"
+                FROM sites WHERE id = $1",
+                site_id
+            )
+            .fetch_optional(&mut *conn)
+            .await
+        }
+    };
+
+    match result {
+        Ok(snapshot) => snapshot,
+        Err(err) => {
+            println::error(format!("Failed to read audit snapshot: {}", err));
+            None
+        }
+    }
+}
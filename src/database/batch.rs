@@ -0,0 +1,381 @@
+/*
+ * database/batch.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * Page changes written against a single connection, so the same steps
+ * serve the plain commands and Batch, which runs a list of them in one
+ * transaction. Each step records what it means for the cache and
+ * webhooks instead of acting on it, the caller applies those effects once
+ * the change is committed.
+ */
+
+use super::{audit, cache, schema, webhook, Actor, PageOp, PathInTrash, VersionConflict};
+use chrono::Utc;
+use color_eyre::Result;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+pub enum Effect {
+    SetPage(schema::Page),
+    // The page and its translations
+    RemovePage(Uuid, String),
+    RemoveMenus(Uuid),
+    RemoveRendered(Uuid, String),
+    RemoveAllRendered(Uuid),
+    Fire(Uuid, &'static str, String, Option<schema::Page>),
+}
+
+// All or nothing, on an error the transaction is rolled back and no effects are returned.
+// Each op gets its own audit entry, written before the commit.
+pub async fn run(pool: &PgPool, ops: Vec<PageOp>, actor: Option<Actor>) -> Result<Vec<Effect>> {
+    let mut effects = Vec::new();
+    let mut transaction = pool.begin().await?;
+
+    let mut audits = Vec::with_capacity(ops.len());
+    for op in &ops {
+        audits.push(audit::Pending::start_in(&mut transaction, audit::describe_op(op)).await);
+    }
+
+    for op in ops {
+        match op {
+            PageOp::Set(page) => {
+                set_page(&mut transaction, page, &mut effects).await?;
+            }
+            // Pages that are already gone are fine in a batch
+            PageOp::Delete(site_id, path) => {
+                delete_page(&mut transaction, site_id, path, &mut effects).await?;
+            }
+            PageOp::Rename(site_id, old_path, new_path) => {
+                rename_page(&mut transaction, site_id, old_path, new_path, &mut effects).await?
+            }
+        }
+    }
+
+    for audit in audits {
+        audit.write(&mut transaction, actor.clone()).await?;
+    }
+
+    transaction.commit().await?;
+    Ok(effects)
+}
+
+pub async fn apply(effects: Vec<Effect>, cache: &mut cache::Cache, webhooks: &webhook::Webhooks) {
+    for effect in effects {
+        match effect {
+            Effect::SetPage(page) => cache.set_page(&page).await,
+            Effect::RemovePage(site_id, path) => {
+                cache.remove_page(site_id, &path).await;
+                cache.remove_translations(site_id, path).await;
+            }
+            Effect::RemoveMenus(site_id) => cache.remove_menus(site_id).await,
+            Effect::RemoveRendered(site_id, path) => cache.remove_rendered(site_id, path).await,
+            Effect::RemoveAllRendered(site_id) => cache.remove_all_rendered(site_id).await,
+            Effect::Fire(site_id, event, path, page) => {
+                webhooks.fire(site_id, event, &path, page.as_ref()).await
+            }
+        }
+    }
+}
+
+pub async fn set_page(
+    conn: &mut PgConnection,
+    mut new_page: schema::Page,
+    effects: &mut Vec<Effect>,
+) -> Result<i32> {
+    // The old published flag tells webhooks whether this publishes the page,
+    // the CTE reads it from the snapshot before the update.
+    // Nothing is written unless the page is still at the version it was read at.
+    let row = sqlx::query!(
+        "WITH previous AS (
+            SELECT published FROM pages
+            WHERE site_id = $11 AND \"path\" = $12
+        )
+        UPDATE pages SET
+        created_at = $1,
+        created_by = $2,
+        modified_at = $3,
+        modified_by = $4,
+        published = $5,
+        metadata = $6,
+        body = $7,
+        sort_order = $8,
+        content_type = $9,
+        fields = $10,
+        version = version + 1
+        WHERE site_id = $11 AND \"path\" = $12 AND version = $13 AND deleted_at IS NULL
+        RETURNING version, (SELECT published FROM previous) AS \"was_published!\"",
+        new_page.created_at,
+        new_page.created_by,
+        new_page.modified_at,
+        new_page.modified_by,
+        new_page.published,
+        new_page.metadata.as_slice(),
+        new_page.body,
+        new_page.sort_order,
+        new_page.content_type,
+        new_page.fields,
+        new_page.site_id,
+        new_page.path,
+        new_page.version
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let row = match row {
+        Some(row) => row,
+        None => {
+            // Either the page is gone or someone else saved it first
+            let exists = sqlx::query_scalar!(
+                "SELECT EXISTS(
+                    SELECT 1 FROM pages
+                    WHERE site_id = $1 AND \"path\" = $2 AND deleted_at IS NULL
+                ) AS \"exists!\"",
+                new_page.site_id,
+                new_page.path
+            )
+            .fetch_one(&mut *conn)
+            .await?;
+
+            return match exists {
+                true => Err(VersionConflict.into()),
+                false => Err(sqlx::Error::RowNotFound.into()),
+            };
+        }
+    };
+
+    new_page.version = row.version;
+    let (site_id, path) = (new_page.site_id, new_page.path.clone());
+    effects.push(Effect::SetPage(new_page.clone()));
+    effects.push(Effect::RemoveRendered(site_id, path.clone()));
+    effects.push(Effect::Fire(
+        site_id,
+        webhook::PAGE_UPDATED,
+        path.clone(),
+        Some(new_page.clone()),
+    ));
+    if new_page.published && !row.was_published {
+        effects.push(Effect::Fire(
+            site_id,
+            webhook::PAGE_PUBLISHED,
+            path,
+            Some(new_page),
+        ));
+    } else if !new_page.published && row.was_published {
+        effects.push(Effect::Fire(
+            site_id,
+            webhook::PAGE_UNPUBLISHED,
+            path,
+            Some(new_page),
+        ));
+    }
+
+    Ok(row.version)
+}
+
+// Returns whether there was a page to delete
+pub async fn delete_page(
+    conn: &mut PgConnection,
+    site_id: Uuid,
+    path: String,
+    effects: &mut Vec<Effect>,
+) -> Result<bool> {
+    // Only moves the page to the trash, PurgePage and the purge task delete it
+    let result = sqlx::query!(
+        "UPDATE pages SET deleted_at = $3
+        WHERE site_id = $1 AND \"path\" = $2 AND deleted_at IS NULL",
+        site_id,
+        path,
+        Utc::now().naive_utc()
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    effects.push(Effect::RemovePage(site_id, path.clone()));
+    effects.push(Effect::RemoveMenus(site_id));
+    effects.push(Effect::RemoveAllRendered(site_id));
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    effects.push(Effect::Fire(site_id, webhook::PAGE_DELETED, path, None));
+    Ok(true)
+}
+
+pub async fn rename_page(
+    conn: &mut PgConnection,
+    site_id: Uuid,
+    old_path: String,
+    new_path: String,
+    effects: &mut Vec<Effect>,
+) -> Result<()> {
+    // A redirect from a path to itself would loop as soon as the page is gone
+    if old_path == new_path {
+        sqlx::query!(
+            "SELECT \"path\" FROM pages
+            WHERE site_id = $1 AND \"path\" = $2 AND deleted_at IS NULL",
+            site_id,
+            old_path
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        return Ok(());
+    }
+
+    // Checked first, a unique violation would abort the transaction
+    let trashed = sqlx::query_scalar!(
+        "SELECT EXISTS (
+            SELECT 1 FROM pages
+            WHERE site_id = $1 AND \"path\" = $2 AND deleted_at IS NOT NULL
+        ) AS \"trashed!\"",
+        site_id,
+        new_path
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if trashed {
+        return Err(PathInTrash(new_path).into());
+    }
+
+    // One statement so the rename and its redirect land together.
+    // Redirects already pointing at the old path follow the page, except the
+    // ones leaving from the new path. The page lives there now, so those go.
+    // Drafts and review comments are kept by path, so they move along, else
+    // publishing the draft would bring the old path back.
+    let result = sqlx::query!(
+        "WITH renamed AS (
+            UPDATE pages SET \"path\" = $3
+            WHERE site_id = $1 AND \"path\" = $2 AND deleted_at IS NULL
+            RETURNING \"path\"
+        ), moved_draft AS (
+            UPDATE page_drafts SET \"path\" = $3
+            WHERE site_id = $1 AND \"path\" = $2
+            AND EXISTS (SELECT 1 FROM renamed)
+        ), moved_comments AS (
+            UPDATE workflow_comments SET \"path\" = $3
+            WHERE site_id = $1 AND \"path\" = $2
+            AND EXISTS (SELECT 1 FROM renamed)
+        ), retargeted AS (
+            UPDATE redirects SET target = $3
+            WHERE site_id = $1 AND target = $2 AND NOT is_regex
+            AND source NOT IN ($2, $3)
+            AND EXISTS (SELECT 1 FROM renamed)
+        ), replaced AS (
+            DELETE FROM redirects
+            WHERE site_id = $1 AND source = $3 AND NOT is_regex
+            AND EXISTS (SELECT 1 FROM renamed)
+        )
+        INSERT INTO redirects (id, site_id, source, target, status_code, is_regex)
+        SELECT $4, $1, $2, $3, 301, false FROM renamed
+        ON CONFLICT (site_id, source) DO UPDATE SET
+        target = EXCLUDED.target,
+        status_code = EXCLUDED.status_code,
+        is_regex = EXCLUDED.is_regex",
+        site_id,
+        old_path,
+        new_path,
+        Uuid::now_v7()
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound.into());
+    }
+
+    effects.push(Effect::RemovePage(site_id, old_path));
+    effects.push(Effect::RemoveMenus(site_id));
+    effects.push(Effect::RemoveAllRendered(site_id));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::webhook::tests::scratch_pool;
+    use sqlx::Executor;
+
+    // Run with --include-ignored against a Postgres server, CI does
+    #[tokio::test]
+    #[ignore = "needs a Postgres server in DATABASE_URL"]
+    async fn writes_audit_entries_with_the_batch() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set");
+        let (admin, pool, name) = scratch_pool(&url).await;
+
+        let site_id: Uuid = sqlx::query_scalar("SELECT id FROM sites WHERE is_default")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let user_id = Uuid::now_v7();
+        sqlx::query(
+            "INSERT INTO admins (id, username, enabled, email) VALUES ($1, 'editor', true, '')",
+        )
+        .bind(user_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+        for path in ["/a", "/b"] {
+            sqlx::query(
+                "INSERT INTO pages (site_id, \"path\", created_by, modified_by, published, body)
+                VALUES ($1, $2, $3, $3, true, '')",
+            )
+            .bind(site_id)
+            .bind(path)
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        let actor = Some(Actor {
+            user_id,
+            ip: Some(String::from("192.0.2.1")),
+        });
+        let entries = || async {
+            sqlx::query_as::<_, (String, String, Option<Uuid>)>(
+                "SELECT action, target, actor FROM audit_log ORDER BY id",
+            )
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+        };
+
+        // /a is in the trash by the time of the rename, so the delete is rolled back too
+        let ops = vec![
+            PageOp::Delete(site_id, String::from("/a")),
+            PageOp::Rename(site_id, String::from("/b"), String::from("/a")),
+        ];
+        assert!(run(&pool, ops, actor.clone()).await.is_err());
+        assert!(entries().await.is_empty());
+
+        let ops = vec![
+            PageOp::Delete(site_id, String::from("/a")),
+            PageOp::Rename(site_id, String::from("/b"), String::from("/c")),
+        ];
+        run(&pool, ops, actor).await.unwrap();
+        assert_eq!(
+            entries().await,
+            [
+                (
+                    String::from("delete_page"),
+                    String::from("/a"),
+                    Some(user_id)
+                ),
+                (
+                    String::from("rename_page"),
+                    String::from("/b"),
+                    Some(user_id)
+                ),
+            ]
+        );
+
+        pool.close().await;
+        admin
+            .execute(format!("DROP DATABASE {} WITH (FORCE)", name).as_str())
+            .await
+            .unwrap();
+    }
+}
@@ -19,14 +19,26 @@ use uuid::Uuid;
 
 #[derive(Eq, PartialEq, Hash, Clone)]
 enum CacheKey {
-    Page(String),
+    Page(Uuid, String),
+    Translations(Uuid, String),
     User(Uuid),
+    Menus(Uuid),
+    Site(String),
+    DefaultSite,
+    Block(Uuid, String),
+    // site_id, path, locale
+    Rendered(Uuid, String, String),
 }
 
 #[derive(Clone)]
 enum CacheValue {
     Page(schema::Page, NaiveDateTime),
+    Translations(Vec<schema::PageTranslation>, NaiveDateTime),
     User(schema::AdminUser, NaiveDateTime),
+    Menus(Vec<schema::Menu>, NaiveDateTime),
+    Site(schema::Site, NaiveDateTime),
+    Block(schema::Block, NaiveDateTime),
+    Rendered(schema::RenderedPage, NaiveDateTime),
 }
 
 pub struct Cache {
@@ -46,7 +58,12 @@ impl Cache {
                 for (key, value) in storage.clone().into_iter() {
                     let valid_until = match value {
                         CacheValue::Page(_, valid_until) => valid_until,
+                        CacheValue::Translations(_, valid_until) => valid_until,
                         CacheValue::User(_, valid_until) => valid_until,
+                        CacheValue::Menus(_, valid_until) => valid_until,
+                        CacheValue::Site(_, valid_until) => valid_until,
+                        CacheValue::Block(_, valid_until) => valid_until,
+                        CacheValue::Rendered(_, valid_until) => valid_until,
                     };
 
                     let now = Utc::now().naive_utc();
@@ -66,12 +83,12 @@ impl Cache {
         Cache { storage }
     }
 
-    pub async fn get_page<S>(&self, path: S) -> Option<schema::Page>
+    pub async fn get_page<S>(&self, site_id: Uuid, path: S) -> Option<schema::Page>
     where
         S: Into<String>,
     {
         let storage = self.storage.lock().await;
-        let result = storage.get(&CacheKey::Page(path.into()));
+        let result = storage.get(&CacheKey::Page(site_id, path.into()));
 
         if let Some(result) = result {
             match result {
@@ -83,6 +100,26 @@ impl Cache {
         }
     }
 
+    pub async fn get_translations<S>(
+        &self,
+        site_id: Uuid,
+        path: S,
+    ) -> Option<Vec<schema::PageTranslation>>
+    where
+        S: Into<String>,
+    {
+        let storage = self.storage.lock().await;
+        let result = storage.get(&CacheKey::Translations(site_id, path.into()));
+        if let Some(result) = result {
+            match result {
+                CacheValue::Translations(translations, _) => Some(translations.clone()),
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+
     pub async fn get_user<U>(&self, id: U) -> Option<schema::AdminUser>
     where
         U: Into<Uuid>,
@@ -99,6 +136,85 @@ impl Cache {
         }
     }
 
+    pub async fn get_menus(&self, site_id: Uuid) -> Option<Vec<schema::Menu>> {
+        let storage = self.storage.lock().await;
+        let result = storage.get(&CacheKey::Menus(site_id));
+        if let Some(result) = result {
+            match result {
+                CacheValue::Menus(menus, _) => Some(menus.clone()),
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+
+    pub async fn get_site<S>(&self, host: S) -> Option<schema::Site>
+    where
+        S: Into<String>,
+    {
+        let storage = self.storage.lock().await;
+        let result = storage.get(&CacheKey::Site(host.into()));
+        if let Some(result) = result {
+            match result {
+                CacheValue::Site(site, _) => Some(site.clone()),
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+
+    pub async fn get_default_site(&self) -> Option<schema::Site> {
+        let storage = self.storage.lock().await;
+        let result = storage.get(&CacheKey::DefaultSite);
+        if let Some(result) = result {
+            match result {
+                CacheValue::Site(site, _) => Some(site.clone()),
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+
+    pub async fn get_block<S>(&self, site_id: Uuid, name: S) -> Option<schema::Block>
+    where
+        S: Into<String>,
+    {
+        let storage = self.storage.lock().await;
+        let result = storage.get(&CacheKey::Block(site_id, name.into()));
+        if let Some(result) = result {
+            match result {
+                CacheValue::Block(block, _) => Some(block.clone()),
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+
+    pub async fn get_rendered<S>(
+        &self,
+        site_id: Uuid,
+        path: S,
+        locale: S,
+    ) -> Option<schema::RenderedPage>
+    where
+        S: Into<String>,
+    {
+        let storage = self.storage.lock().await;
+        let result = storage.get(&CacheKey::Rendered(site_id, path.into(), locale.into()));
+        if let Some(result) = result {
+            match result {
+                CacheValue::Rendered(rendered, _) => Some(rendered.clone()),
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+
     pub async fn set_page(&mut self, page: &schema::Page) {
         let naive_time = Utc::now().naive_utc();
         let valid_until = match naive_time.checked_add_signed(TimeDelta::seconds(30)) {
@@ -108,11 +224,32 @@ impl Cache {
 
         let mut storage = self.storage.lock().await;
         storage.insert(
-            CacheKey::Page(page.path.clone()),
+            CacheKey::Page(page.site_id, page.path.clone()),
             CacheValue::Page(page.clone(), valid_until),
         );
     }
 
+    pub async fn set_translations<S>(
+        &mut self,
+        site_id: Uuid,
+        path: S,
+        translations: &[schema::PageTranslation],
+    ) where
+        S: Into<String>,
+    {
+        let naive_time = Utc::now().naive_utc();
+        let valid_until = match naive_time.checked_add_signed(TimeDelta::seconds(30)) {
+            Some(result) => result,
+            None => return,
+        };
+
+        let mut storage = self.storage.lock().await;
+        storage.insert(
+            CacheKey::Translations(site_id, path.into()),
+            CacheValue::Translations(translations.to_vec(), valid_until),
+        );
+    }
+
     pub async fn set_user(&mut self, user: &schema::AdminUser) {
         let naive_time = Utc::now().naive_utc();
         let valid_until = match naive_time.checked_add_signed(TimeDelta::seconds(30)) {
@@ -126,4 +263,164 @@ impl Cache {
             CacheValue::User(user.clone(), valid_until),
         );
     }
+
+    pub async fn set_menus(&mut self, site_id: Uuid, menus: &[schema::Menu]) {
+        let naive_time = Utc::now().naive_utc();
+        let valid_until = match naive_time.checked_add_signed(TimeDelta::seconds(30)) {
+            Some(result) => result,
+            None => return,
+        };
+
+        let mut storage = self.storage.lock().await;
+        storage.insert(
+            CacheKey::Menus(site_id),
+            CacheValue::Menus(menus.to_vec(), valid_until),
+        );
+    }
+
+    // Only a site's own host is cached, so arbitrary Host headers can't grow the cache
+    pub async fn set_site(&mut self, site: &schema::Site) {
+        let naive_time = Utc::now().naive_utc();
+        let valid_until = match naive_time.checked_add_signed(TimeDelta::seconds(30)) {
+            Some(result) => result,
+            None => return,
+        };
+
+        let mut storage = self.storage.lock().await;
+        storage.insert(
+            CacheKey::Site(site.host.clone()),
+            CacheValue::Site(site.clone(), valid_until),
+        );
+    }
+
+    pub async fn set_default_site(&mut self, site: &schema::Site) {
+        let naive_time = Utc::now().naive_utc();
+        let valid_until = match naive_time.checked_add_signed(TimeDelta::seconds(30)) {
+            Some(result) => result,
+            None => return,
+        };
+
+        let mut storage = self.storage.lock().await;
+        storage.insert(
+            CacheKey::DefaultSite,
+            CacheValue::Site(site.clone(), valid_until),
+        );
+    }
+
+    pub async fn set_block(&mut self, block: &schema::Block) {
+        let naive_time = Utc::now().naive_utc();
+        let valid_until = match naive_time.checked_add_signed(TimeDelta::seconds(30)) {
+            Some(result) => result,
+            None => return,
+        };
+
+        let mut storage = self.storage.lock().await;
+        storage.insert(
+            CacheKey::Block(block.site_id, block.name.clone()),
+            CacheValue::Block(block.clone(), valid_until),
+        );
+    }
+
+    pub async fn set_rendered(&mut self, rendered: &schema::RenderedPage) {
+        let naive_time = Utc::now().naive_utc();
+        let valid_until = match naive_time.checked_add_signed(TimeDelta::seconds(30)) {
+            Some(result) => result,
+            None => return,
+        };
+
+        let mut storage = self.storage.lock().await;
+        storage.insert(
+            CacheKey::Rendered(
+                rendered.site_id,
+                rendered.path.clone(),
+                rendered.locale.clone(),
+            ),
+            CacheValue::Rendered(rendered.clone(), valid_until),
+        );
+    }
+
+    pub async fn remove_page<S>(&mut self, site_id: Uuid, path: S)
+    where
+        S: Into<String>,
+    {
+        let mut storage = self.storage.lock().await;
+        storage.remove(&CacheKey::Page(site_id, path.into()));
+    }
+
+    pub async fn remove_translations<S>(&mut self, site_id: Uuid, path: S)
+    where
+        S: Into<String>,
+    {
+        let mut storage = self.storage.lock().await;
+        storage.remove(&CacheKey::Translations(site_id, path.into()));
+    }
+
+    pub async fn remove_user<U>(&mut self, id: U)
+    where
+        U: Into<Uuid>,
+    {
+        let mut storage = self.storage.lock().await;
+        storage.remove(&CacheKey::User(id.into()));
+    }
+
+    pub async fn remove_menus(&mut self, site_id: Uuid) {
+        let mut storage = self.storage.lock().await;
+        storage.remove(&CacheKey::Menus(site_id));
+    }
+
+    // Drops the block and every rendered page that used it
+    pub async fn remove_block<S>(&mut self, site_id: Uuid, name: S)
+    where
+        S: Into<String>,
+    {
+        let name = name.into();
+        let mut storage = self.storage.lock().await;
+        storage.remove(&CacheKey::Block(site_id, name.clone()));
+        storage.retain(|_, value| match value {
+            CacheValue::Rendered(rendered, _) => {
+                rendered.site_id != site_id || !rendered.blocks.contains(&name)
+            }
+            _ => true,
+        });
+    }
+
+    // Drops the rendered page at the path in every locale and every page that included it
+    pub async fn remove_rendered<S>(&mut self, site_id: Uuid, path: S)
+    where
+        S: Into<String>,
+    {
+        let path = path.into();
+        let mut storage = self.storage.lock().await;
+        storage.retain(|_, value| match value {
+            CacheValue::Rendered(rendered, _) => {
+                rendered.site_id != site_id
+                    || (rendered.path != path && !rendered.pages.contains(&path))
+            }
+            _ => true,
+        });
+    }
+
+    // For changes that show up on every page, like menus
+    pub async fn remove_all_rendered(&mut self, site_id: Uuid) {
+        let mut storage = self.storage.lock().await;
+        storage.retain(|key, _| !matches!(key, CacheKey::Rendered(rendered_site_id, _, _) if *rendered_site_id == site_id));
+    }
+
+    // Hosts and the default can move between sites, so every site lookup is dropped
+    pub async fn remove_sites(&mut self) {
+        let mut storage = self.storage.lock().await;
+        storage.retain(|key, _| !matches!(key, CacheKey::Site(_) | CacheKey::DefaultSite));
+    }
+
+    pub async fn remove_site_content(&mut self, site_id: Uuid) {
+        let mut storage = self.storage.lock().await;
+        storage.retain(|key, _| match key {
+            CacheKey::Page(page_site_id, _) => *page_site_id != site_id,
+            CacheKey::Translations(page_site_id, _) => *page_site_id != site_id,
+            CacheKey::Menus(menu_site_id) => *menu_site_id != site_id,
+            CacheKey::Block(block_site_id, _) => *block_site_id != site_id,
+            CacheKey::Rendered(rendered_site_id, _, _) => *rendered_site_id != site_id,
+            _ => true,
+        });
+    }
 }
@@ -5,85 +5,1213 @@
  * See the file "LICENSE" in the root of this project.
  */
 
-use super::{cache, schema, DatabaseMpscCommand};
-use sqlx::PgPool;
+use super::{
+    audit, batch, cache, schema, transfer, webhook, Actor, DatabaseMpscCommand, PathInTrash,
+};
+use chrono::Utc;
+use color_eyre::Result;
+use sqlx::{types::Json, PgPool};
 
-pub async fn cmd(cmd: DatabaseMpscCommand, pool: &PgPool, cache: &mut cache::Cache) {
+// The actor is only needed by commands that write their audit entry themselves
+pub async fn cmd(
+    cmd: DatabaseMpscCommand,
+    actor: Option<Actor>,
+    pool: &PgPool,
+    cache: &mut cache::Cache,
+    webhooks: &webhook::Webhooks,
+) {
     match cmd {
-        DatabaseMpscCommand::GetPage(path, skip_cache, reply) => {
+        DatabaseMpscCommand::GetPage(site_id, path, skip_cache, reply) => {
             if !skip_cache {
-                if let Some(result) = cache.get_page(&path).await {
+                if let Some(result) = cache.get_page(site_id, &path).await {
                     let _ = reply.send(Ok(result));
                     return;
                 }
             }
 
-            let page =
-                match sqlx::query_as!(schema::Page, "SELECT * FROM pages WHERE path = $1", path)
-                    .fetch_one(pool)
-                    .await
-                {
-                    Ok(page) => page,
-                    Err(err) => {
-                        let _ = reply.send(Err(err.into()));
-                        return;
-                    }
-                };
+            let page = match sqlx::query_as!(
+                schema::Page,
+                "SELECT site_id, path, created_at, created_by, modified_at, modified_by, published, metadata, body, sort_order, content_type, fields, version
+                FROM pages WHERE site_id = $1 AND path = $2 AND deleted_at IS NULL",
+                site_id,
+                path
+            )
+            .fetch_one(pool)
+            .await
+            {
+                Ok(page) => page,
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                    return;
+                }
+            };
+            cache.set_page(&page).await;
+            let _ = reply.send(Ok(page));
+        }
+        DatabaseMpscCommand::ListPages(site_id, query, reply) => {
+            // Keyset pagination over (sort time, path), the two orders have matching indexes
+            let result = match query.sort {
+                schema::PageSort::CreatedAt => sqlx::query_as!(
+                schema::Page,
+                "SELECT site_id, path, created_at, created_by, modified_at, modified_by, published, metadata, body, sort_order, content_type, fields, version
+                FROM pages
+                WHERE site_id = $1 AND deleted_at IS NULL
+                AND ($2::text IS NULL OR starts_with(\"path\", $2))
+                AND ($3::boolean IS NULL OR published = $3)
+                AND ($4::uuid IS NULL OR created_by = $4)
+                AND ($5::timestamp IS NULL OR created_at >= $5)
+                AND ($6::timestamp IS NULL OR created_at < $6)
+                AND ($7::timestamp IS NULL OR modified_at >= $7)
+                AND ($8::timestamp IS NULL OR modified_at < $8)
+                AND ($9::timestamp IS NULL OR (created_at, \"path\") < ($9, $10))
+                ORDER BY created_at DESC, \"path\" DESC
+                LIMIT $11",
+                site_id,
+                query.prefix,
+                query.published,
+                query.author,
+                query.created_since,
+                query.created_until,
+                query.modified_since,
+                query.modified_until,
+                query.after_at,
+                query.after_path.unwrap_or_default(),
+                query.limit
+            )
+            .fetch_all(pool)
+            .await,
+                schema::PageSort::ModifiedAt => sqlx::query_as!(
+                schema::Page,
+                "SELECT site_id, path, created_at, created_by, modified_at, modified_by, published, metadata, body, sort_order, content_type, fields, version
+                FROM pages
+                WHERE site_id = $1 AND deleted_at IS NULL
+                AND ($2::text IS NULL OR starts_with(\"path\", $2))
+                AND ($3::boolean IS NULL OR published = $3)
+                AND ($4::uuid IS NULL OR created_by = $4)
+                AND ($5::timestamp IS NULL OR created_at >= $5)
+                AND ($6::timestamp IS NULL OR created_at < $6)
+                AND ($7::timestamp IS NULL OR modified_at >= $7)
+                AND ($8::timestamp IS NULL OR modified_at < $8)
+                AND ($9::timestamp IS NULL OR (modified_at, \"path\") < ($9, $10))
+                ORDER BY modified_at DESC, \"path\" DESC
+                LIMIT $11",
+                site_id,
+                query.prefix,
+                query.published,
+                query.author,
+                query.created_since,
+                query.created_until,
+                query.modified_since,
+                query.modified_until,
+                query.after_at,
+                query.after_path.unwrap_or_default(),
+                query.limit
+            )
+            .fetch_all(pool)
+            .await,
+            };
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::GetPagesByPath(site_id, paths, reply) => {
+            let result = sqlx::query_as!(
+                schema::Page,
+                "SELECT site_id, path, created_at, created_by, modified_at, modified_by, published, metadata, body, sort_order, content_type, fields, version
+                FROM pages WHERE site_id = $1 AND \"path\" = ANY($2) AND deleted_at IS NULL",
+                site_id,
+                paths.as_slice()
+            )
+            .fetch_all(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::GetPageChildren(site_id, path, reply) => {
+            // Direct children only, so nothing may follow the prefix but a single segment
+            let prefix = format!("{}/", path.trim_end_matches('/'));
+            let result = sqlx::query_as!(
+                schema::Page,
+                "SELECT site_id, path, created_at, created_by, modified_at, modified_by, published, metadata, body, sort_order, content_type, fields, version
+                FROM pages
+                WHERE site_id = $1 AND deleted_at IS NULL
+                AND starts_with(\"path\", $2)
+                AND \"path\" <> $2
+                AND strpos(substr(\"path\", length($2) + 1), '/') = 0
+                ORDER BY sort_order, \"path\"",
+                site_id,
+                prefix
+            )
+            .fetch_all(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::GetChildrenOfPages(site_id, paths, reply) => {
+            // LIKE narrows it down to descendants, the parent check keeps direct children only
+            let prefixes: Vec<String> = paths
+                .iter()
+                .map(|path| format!("{}/", path.trim_end_matches('/')))
+                .collect();
+            let patterns: Vec<String> = prefixes
+                .iter()
+                .map(|prefix| {
+                    let escaped = prefix
+                        .replace('\\', "\\\\")
+                        .replace('%', "\\%")
+                        .replace('_', "\\_");
+                    format!("{}_%", escaped)
+                })
+                .collect();
+
+            let result = sqlx::query_as!(
+                schema::Page,
+                "SELECT site_id, path, created_at, created_by, modified_at, modified_by, published, metadata, body, sort_order, content_type, fields, version
+                FROM pages
+                WHERE site_id = $1 AND deleted_at IS NULL
+                AND \"path\" LIKE ANY($2)
+                AND left(\"path\", length(\"path\") - strpos(reverse(\"path\"), '/') + 1) = ANY($3)
+                ORDER BY sort_order, \"path\"",
+                site_id,
+                patterns.as_slice(),
+                prefixes.as_slice()
+            )
+            .fetch_all(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::GetPagesByPrefix(
+            site_id,
+            prefix,
+            include_unpublished,
+            limit,
+            offset,
+            reply,
+        ) => {
+            // Error pages only show up alongside the other unpublished content
+            let result = sqlx::query_as!(
+                schema::Page,
+                "SELECT site_id, path, created_at, created_by, modified_at, modified_by, published, metadata, body, sort_order, content_type, fields, version
+                FROM pages
+                WHERE site_id = $1 AND starts_with(\"path\", $2) AND deleted_at IS NULL
+                AND ($3 OR (published AND NOT starts_with(\"path\", '/_errors/')))
+                ORDER BY \"path\"
+                LIMIT $4 OFFSET $5",
+                site_id,
+                prefix,
+                include_unpublished,
+                limit,
+                offset
+            )
+            .fetch_all(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::SearchPages(site_id, query, limit, reply) => {
+            let result = sqlx::query_as!(
+                schema::SearchResult,
+                "SELECT path,
+                substring(array_to_string(metadata, ' ') from '<title>(.*?)</title>') AS title,
+                ts_headline(
+                    'english',
+                    translate(regexp_replace(body, '<[^>]*>', ' ', 'g'), chr(2) || chr(3), ''),
+                    query,
+                    format('StartSel=%s, StopSel=%s, MaxFragments=2', chr(2), chr(3))
+                ) AS \"snippet!\"
+                FROM pages, websearch_to_tsquery('english', $2) query
+                WHERE site_id = $1 AND published AND deleted_at IS NULL AND search @@ query
+                AND NOT starts_with(\"path\", '/_errors/')
+                ORDER BY ts_rank(search, query) DESC
+                LIMIT $3",
+                site_id,
+                query,
+                limit
+            )
+            .fetch_all(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::SetPage(new_page, reply) => {
+            let mut effects = Vec::new();
+            let result: Result<i32> = async {
+                let mut transaction = pool.begin().await?;
+                let entry = audit::page_entry("set_page", new_page.site_id, &new_page.path);
+                let audit = audit::Pending::start_in(&mut transaction, entry).await;
+
+                let version = batch::set_page(&mut transaction, new_page, &mut effects).await?;
+
+                audit.write(&mut transaction, actor).await?;
+                transaction.commit().await?;
+                Ok(version)
+            }
+            .await;
+
+            // Rolled back, so there is nothing to tell the cache or webhooks about
+            if result.is_err() {
+                effects.clear();
+            }
+            let _ = reply.send(result);
+            batch::apply(effects, cache, webhooks).await;
+        }
+        DatabaseMpscCommand::DeletePage(site_id, path, reply) => {
+            let mut effects = Vec::new();
+            let result: Result<bool> = async {
+                let mut transaction = pool.begin().await?;
+                let entry = audit::page_entry("delete_page", site_id, &path);
+                let audit = audit::Pending::start_in(&mut transaction, entry).await;
+
+                let deleted =
+                    batch::delete_page(&mut transaction, site_id, path, &mut effects).await?;
+
+                audit.write(&mut transaction, actor).await?;
+                transaction.commit().await?;
+                Ok(deleted)
+            }
+            .await;
+
+            let result = match result {
+                Ok(true) => Ok(()),
+                Ok(false) => Err(sqlx::Error::RowNotFound.into()),
+                Err(err) => {
+                    effects.clear();
+                    Err(err)
+                }
+            };
+            let _ = reply.send(result);
+            batch::apply(effects, cache, webhooks).await;
+        }
+        DatabaseMpscCommand::NewPage(new_page, reply) => {
+            // A live page at the path is a unique violation, a trashed one inserts nothing
+            let result = sqlx::query!(
+                "INSERT INTO pages
+                (site_id, \"path\", created_at, created_by, modified_at, modified_by, published, body, metadata, sort_order, content_type, fields)
+                SELECT $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12
+                WHERE NOT EXISTS (
+                    SELECT 1 FROM pages
+                    WHERE site_id = $1 AND \"path\" = $2 AND deleted_at IS NOT NULL
+                )",
+                new_page.site_id,
+                new_page.path,
+                new_page.created_at,
+                new_page.created_by,
+                new_page.modified_at,
+                new_page.modified_by,
+                new_page.published,
+                new_page.body,
+                new_page.metadata.as_slice(),
+                new_page.sort_order,
+                new_page.content_type,
+                new_page.fields
+            )
+            .execute(pool)
+            .await;
+
+            match result {
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                    return;
+                }
+                Ok(result) if result.rows_affected() == 0 => {
+                    let _ = reply.send(Err(PathInTrash(new_page.path).into()));
+                    return;
+                }
+                Ok(_) => {}
+            }
+
+            let _ = reply.send(Ok(()));
+            // Menus, children and includes that were missing may all pick it up
+            cache.remove_all_rendered(new_page.site_id).await;
+
+            let page = Some(&new_page);
+            let (site_id, path) = (new_page.site_id, &new_page.path);
+            webhooks
+                .fire(site_id, webhook::PAGE_CREATED, path, page)
+                .await;
+            if new_page.published {
+                webhooks
+                    .fire(site_id, webhook::PAGE_PUBLISHED, path, page)
+                    .await;
+            }
+        }
+        DatabaseMpscCommand::Batch(ops, reply) => match batch::run(pool, ops, actor).await {
+            Ok(effects) => {
+                let _ = reply.send(Ok(()));
+                batch::apply(effects, cache, webhooks).await;
+            }
+            Err(err) => {
+                let _ = reply.send(Err(err));
+            }
+        },
+        DatabaseMpscCommand::GetPageTranslations(site_id, path, reply) => {
+            if let Some(translations) = cache.get_translations(site_id, &path).await {
+                let _ = reply.send(Ok(translations));
+                return;
+            }
+
+            let translations = match sqlx::query_as!(
+                schema::PageTranslation,
+                "SELECT * FROM page_translations
+                WHERE site_id = $1 AND \"path\" = $2
+                ORDER BY locale",
+                site_id,
+                path
+            )
+            .fetch_all(pool)
+            .await
+            {
+                Ok(translations) => translations,
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                    return;
+                }
+            };
+            cache.set_translations(site_id, path, &translations).await;
+            let _ = reply.send(Ok(translations));
+        }
+        DatabaseMpscCommand::SetPageTranslation(translation, reply) => {
+            // Creating and updating are the same call, the original author is kept
+            let result = sqlx::query!(
+                "INSERT INTO page_translations
+                (site_id, \"path\", locale, created_at, created_by, modified_at, modified_by, published, metadata, body)
+                VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                ON CONFLICT (site_id, \"path\", locale) DO UPDATE SET
+                modified_at = EXCLUDED.modified_at,
+                modified_by = EXCLUDED.modified_by,
+                published = EXCLUDED.published,
+                metadata = EXCLUDED.metadata,
+                body = EXCLUDED.body",
+                translation.site_id,
+                translation.path,
+                translation.locale,
+                translation.created_at,
+                translation.created_by,
+                translation.modified_at,
+                translation.modified_by,
+                translation.published,
+                translation.metadata.as_slice(),
+                translation.body
+            )
+            .execute(pool)
+            .await;
+
+            if let Err(err) = result {
+                let _ = reply.send(Err(err.into()));
+            } else {
+                let _ = reply.send(Ok(()));
+                cache
+                    .remove_translations(translation.site_id, &translation.path)
+                    .await;
+                cache
+                    .remove_rendered(translation.site_id, translation.path)
+                    .await;
+            }
+        }
+        DatabaseMpscCommand::DeletePageTranslation(site_id, path, locale, reply) => {
+            let result = sqlx::query!(
+                "DELETE FROM page_translations
+                WHERE site_id = $1 AND \"path\" = $2 AND locale = $3",
+                site_id,
+                path,
+                locale
+            )
+            .execute(pool)
+            .await;
+
+            match result {
+                Ok(result) if result.rows_affected() == 0 => {
+                    let _ = reply.send(Err(sqlx::Error::RowNotFound.into()));
+                }
+                Ok(_) => {
+                    let _ = reply.send(Ok(()));
+                    cache.remove_translations(site_id, &path).await;
+                    cache.remove_rendered(site_id, path).await;
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                }
+            }
+        }
+        DatabaseMpscCommand::GetRedirect(site_id, path, reply) => {
+            // Exact matches win over patterns, older patterns win over newer ones
+            let result = sqlx::query_as!(
+                schema::Redirect,
+                "SELECT id, site_id, source,
+                CASE WHEN is_regex THEN regexp_replace($2, source, target) ELSE target END AS \"target!\",
+                status_code, is_regex, created_at
+                FROM redirects
+                WHERE site_id = $1
+                AND ((NOT is_regex AND source = $2) OR (is_regex AND $2 ~ source))
+                ORDER BY is_regex, created_at
+                LIMIT 1",
+                site_id,
+                path
+            )
+            .fetch_optional(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::GetRedirects(site_id, reply) => {
+            let result = sqlx::query_as!(
+                schema::Redirect,
+                "SELECT id, site_id, source, target, status_code, is_regex, created_at
+                FROM redirects WHERE site_id = $1 ORDER BY created_at",
+                site_id
+            )
+            .fetch_all(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::NewRedirect(new_redirect, reply) => {
+            let result = sqlx::query!(
+                "INSERT INTO redirects
+                (id, site_id, source, target, status_code, is_regex, created_at)
+                VALUES($1, $2, $3, $4, $5, $6, $7)",
+                new_redirect.id,
+                new_redirect.site_id,
+                new_redirect.source,
+                new_redirect.target,
+                new_redirect.status_code,
+                new_redirect.is_regex,
+                new_redirect.created_at
+            )
+            .execute(pool)
+            .await;
+
+            if let Err(err) = result {
+                let _ = reply.send(Err(err.into()));
+            } else {
+                let _ = reply.send(Ok(()));
+            }
+        }
+        DatabaseMpscCommand::DeleteRedirect(site_id, id, reply) => {
+            let result = sqlx::query!(
+                "DELETE FROM redirects WHERE site_id = $1 AND id = $2",
+                site_id,
+                id
+            )
+            .execute(pool)
+            .await;
+
+            match result {
+                Ok(result) if result.rows_affected() == 0 => {
+                    let _ = reply.send(Err(sqlx::Error::RowNotFound.into()));
+                }
+                Ok(_) => {
+                    let _ = reply.send(Ok(()));
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                }
+            }
+        }
+        DatabaseMpscCommand::GetMenus(site_id, reply) => {
+            if let Some(menus) = cache.get_menus(site_id).await {
+                let _ = reply.send(Ok(menus));
+                return;
+            }
+
+            let items = match sqlx::query_as!(
+                schema::MenuItem,
+                "SELECT id, menu, sort_order, label, page_path, url FROM menu_items
+                WHERE site_id = $1 AND NOT EXISTS (
+                    SELECT 1 FROM pages
+                    WHERE pages.site_id = menu_items.site_id AND \"path\" = page_path
+                    AND deleted_at IS NOT NULL
+                )
+                ORDER BY menu, sort_order",
+                site_id
+            )
+            .fetch_all(pool)
+            .await
+            {
+                Ok(items) => items,
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                    return;
+                }
+            };
+
+            let names = match sqlx::query_scalar!(
+                "SELECT name FROM menus WHERE site_id = $1 ORDER BY name",
+                site_id
+            )
+            .fetch_all(pool)
+            .await
+            {
+                Ok(names) => names,
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                    return;
+                }
+            };
+
+            let menus: Vec<schema::Menu> = names
+                .into_iter()
+                .map(|name| schema::Menu {
+                    site_id,
+                    items: items
+                        .iter()
+                        .filter(|item| item.menu == name)
+                        .cloned()
+                        .collect(),
+                    name,
+                })
+                .collect();
+
+            cache.set_menus(site_id, &menus).await;
+            let _ = reply.send(Ok(menus));
+        }
+        DatabaseMpscCommand::SetMenu(new_menu, reply) => {
+            // Items are replaced wholesale so the order always matches the request
+            let result = async {
+                let mut transaction = pool.begin().await?;
+
+                sqlx::query!(
+                    "INSERT INTO menus (site_id, name) VALUES($1, $2) ON CONFLICT DO NOTHING",
+                    new_menu.site_id,
+                    new_menu.name
+                )
+                .execute(&mut *transaction)
+                .await?;
+
+                sqlx::query!(
+                    "DELETE FROM menu_items WHERE site_id = $1 AND menu = $2",
+                    new_menu.site_id,
+                    new_menu.name
+                )
+                .execute(&mut *transaction)
+                .await?;
+
+                for item in &new_menu.items {
+                    sqlx::query!(
+                        "INSERT INTO menu_items
+                        (id, site_id, menu, sort_order, label, page_path, url)
+                        VALUES($1, $2, $3, $4, $5, $6, $7)",
+                        item.id,
+                        new_menu.site_id,
+                        new_menu.name,
+                        item.sort_order,
+                        item.label,
+                        item.page_path,
+                        item.url
+                    )
+                    .execute(&mut *transaction)
+                    .await?;
+                }
+
+                transaction.commit().await
+            }
+            .await;
+
+            if let Err(err) = result {
+                let _ = reply.send(Err(err.into()));
+            } else {
+                let _ = reply.send(Ok(()));
+                cache.remove_menus(new_menu.site_id).await;
+                cache.remove_all_rendered(new_menu.site_id).await;
+            }
+        }
+        DatabaseMpscCommand::DeleteMenu(site_id, name, reply) => {
+            let result = sqlx::query!(
+                "DELETE FROM menus WHERE site_id = $1 AND name = $2",
+                site_id,
+                name
+            )
+            .execute(pool)
+            .await;
+
+            match result {
+                Ok(result) if result.rows_affected() == 0 => {
+                    let _ = reply.send(Err(sqlx::Error::RowNotFound.into()));
+                }
+                Ok(_) => {
+                    let _ = reply.send(Ok(()));
+                    cache.remove_menus(site_id).await;
+                    cache.remove_all_rendered(site_id).await;
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                }
+            }
+        }
+        DatabaseMpscCommand::GetBlocks(site_id, reply) => {
+            let result = sqlx::query_as!(
+                schema::Block,
+                "SELECT * FROM blocks WHERE site_id = $1 ORDER BY name",
+                site_id
+            )
+            .fetch_all(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::GetBlock(site_id, name, reply) => {
+            if let Some(block) = cache.get_block(site_id, &name).await {
+                let _ = reply.send(Ok(block));
+                return;
+            }
+
+            let block = match sqlx::query_as!(
+                schema::Block,
+                "SELECT * FROM blocks WHERE site_id = $1 AND name = $2",
+                site_id,
+                name
+            )
+            .fetch_one(pool)
+            .await
+            {
+                Ok(block) => block,
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                    return;
+                }
+            };
+            let _ = reply.send(Ok(block.clone()));
+            cache.set_block(&block).await;
+        }
+        DatabaseMpscCommand::SetBlock(new_block, reply) => {
+            let result = sqlx::query!(
+                "INSERT INTO blocks (site_id, name, body, modified_at, modified_by)
+                VALUES($1, $2, $3, $4, $5)
+                ON CONFLICT (site_id, name) DO UPDATE SET
+                body = EXCLUDED.body,
+                modified_at = EXCLUDED.modified_at,
+                modified_by = EXCLUDED.modified_by",
+                new_block.site_id,
+                new_block.name,
+                new_block.body,
+                new_block.modified_at,
+                new_block.modified_by
+            )
+            .execute(pool)
+            .await;
+
+            if let Err(err) = result {
+                let _ = reply.send(Err(err.into()));
+            } else {
+                let _ = reply.send(Ok(()));
+                cache.remove_block(new_block.site_id, new_block.name).await;
+            }
+        }
+        DatabaseMpscCommand::DeleteBlock(site_id, name, reply) => {
+            let result = sqlx::query!(
+                "DELETE FROM blocks WHERE site_id = $1 AND name = $2",
+                site_id,
+                name
+            )
+            .execute(pool)
+            .await;
+
+            match result {
+                Ok(result) if result.rows_affected() == 0 => {
+                    let _ = reply.send(Err(sqlx::Error::RowNotFound.into()));
+                }
+                Ok(_) => {
+                    let _ = reply.send(Ok(()));
+                    cache.remove_block(site_id, name).await;
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                }
+            }
+        }
+        DatabaseMpscCommand::GetContentTypes(site_id, reply) => {
+            let result = sqlx::query_as!(
+                schema::ContentType,
+                "SELECT site_id, name, label, fields AS \"fields: Json<Vec<schema::FieldDefinition>>\", template
+                FROM content_types WHERE site_id = $1 ORDER BY name",
+                site_id
+            )
+            .fetch_all(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::GetContentType(site_id, name, reply) => {
+            let result = sqlx::query_as!(
+                schema::ContentType,
+                "SELECT site_id, name, label, fields AS \"fields: Json<Vec<schema::FieldDefinition>>\", template
+                FROM content_types WHERE site_id = $1 AND name = $2",
+                site_id,
+                name
+            )
+            .fetch_one(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::SetContentType(content_type, reply) => {
+            let result = sqlx::query!(
+                "INSERT INTO content_types (site_id, name, label, fields, template)
+                VALUES($1, $2, $3, $4, $5)
+                ON CONFLICT (site_id, name) DO UPDATE SET
+                label = EXCLUDED.label,
+                fields = EXCLUDED.fields,
+                template = EXCLUDED.template",
+                content_type.site_id,
+                content_type.name,
+                content_type.label,
+                content_type.fields as _,
+                content_type.template
+            )
+            .execute(pool)
+            .await;
+
+            if let Err(err) = result {
+                let _ = reply.send(Err(err.into()));
+            } else {
+                let _ = reply.send(Ok(()));
+                // The template is part of every page of the type
+                cache.remove_all_rendered(content_type.site_id).await;
+            }
+        }
+        DatabaseMpscCommand::DeleteContentType(site_id, name, reply) => {
+            let result = sqlx::query!(
+                "DELETE FROM content_types WHERE site_id = $1 AND name = $2",
+                site_id,
+                name
+            )
+            .execute(pool)
+            .await;
+
+            match result {
+                Ok(result) if result.rows_affected() == 0 => {
+                    let _ = reply.send(Err(sqlx::Error::RowNotFound.into()));
+                }
+                Ok(_) => {
+                    let _ = reply.send(Ok(()));
+                    // Pages of the type were rendered with its template
+                    cache.remove_all_rendered(site_id).await;
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                }
+            }
+        }
+        DatabaseMpscCommand::GetRenderedPage(site_id, path, locale, reply) => {
+            let _ = reply.send(Ok(cache.get_rendered(site_id, path, locale).await));
+        }
+        DatabaseMpscCommand::SetRenderedPage(rendered, reply) => {
+            cache.set_rendered(&rendered).await;
+            let _ = reply.send(Ok(()));
+        }
+        DatabaseMpscCommand::GetSites(reply) => {
+            let result = sqlx::query_as!(schema::Site, "SELECT * FROM sites ORDER BY name")
+                .fetch_all(pool)
+                .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::GetSite(id, reply) => {
+            let result = sqlx::query_as!(schema::Site, "SELECT * FROM sites WHERE id = $1", id)
+                .fetch_one(pool)
+                .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::GetSiteByHost(host, reply) => {
+            if let Some(site) = cache.get_site(&host).await {
+                let _ = reply.send(Ok(site));
+                return;
+            }
+
+            // A site registered for the host beats the default site
+            match sqlx::query_as!(schema::Site, "SELECT * FROM sites WHERE host = $1", host)
+                .fetch_optional(pool)
+                .await
+            {
+                Ok(Some(site)) => {
+                    cache.set_site(&site).await;
+                    let _ = reply.send(Ok(site));
+                    return;
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                    return;
+                }
+            }
+
+            if let Some(site) = cache.get_default_site().await {
+                let _ = reply.send(Ok(site));
+                return;
+            }
+
+            let result = sqlx::query_as!(schema::Site, "SELECT * FROM sites WHERE is_default")
+                .fetch_one(pool)
+                .await;
+
+            if let Ok(site) = &result {
+                cache.set_default_site(site).await;
+            }
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::SetSite(new_site, reply) => {
+            // Only one site can be the default
+            let result = async {
+                let mut transaction = pool.begin().await?;
+
+                if new_site.is_default {
+                    sqlx::query!(
+                        "UPDATE sites SET is_default = false WHERE is_default AND id <> $1",
+                        new_site.id
+                    )
+                    .execute(&mut *transaction)
+                    .await?;
+                }
+
+                let result = sqlx::query!(
+                    "UPDATE sites SET
+                    host = $1,
+                    name = $2,
+                    is_default = $3,
+                    default_locale = $4,
+                    require_review = $5
+                    WHERE id = $6",
+                    new_site.host,
+                    new_site.name,
+                    new_site.is_default,
+                    new_site.default_locale,
+                    new_site.require_review,
+                    new_site.id
+                )
+                .execute(&mut *transaction)
+                .await?;
+
+                if result.rows_affected() == 0 {
+                    return Err(sqlx::Error::RowNotFound);
+                }
+
+                transaction.commit().await
+            }
+            .await;
+
+            if let Err(err) = result {
+                let _ = reply.send(Err(err.into()));
+            } else {
+                let _ = reply.send(Ok(()));
+                cache.remove_sites().await;
+            }
+        }
+        DatabaseMpscCommand::DeleteSite(id, reply) => {
+            let result: Result<u64> = async {
+                let mut transaction = pool.begin().await?;
+                let entry = audit::site_entry("delete_site", id);
+                let audit = audit::Pending::start_in(&mut transaction, entry).await;
+
+                let result = sqlx::query!("DELETE FROM sites WHERE id = $1", id)
+                    .execute(&mut *transaction)
+                    .await?;
+
+                audit.write(&mut transaction, actor).await?;
+                transaction.commit().await?;
+                Ok(result.rows_affected())
+            }
+            .await;
+
+            match result {
+                Ok(0) => {
+                    let _ = reply.send(Err(sqlx::Error::RowNotFound.into()));
+                }
+                Ok(_) => {
+                    let _ = reply.send(Ok(()));
+                    cache.remove_sites().await;
+                    cache.remove_site_content(id).await;
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(err));
+                }
+            }
+        }
+        DatabaseMpscCommand::NewSite(new_site, reply) => {
+            let result = async {
+                let mut transaction = pool.begin().await?;
+
+                if new_site.is_default {
+                    sqlx::query!("UPDATE sites SET is_default = false WHERE is_default")
+                        .execute(&mut *transaction)
+                        .await?;
+                }
+
+                sqlx::query!(
+                    "INSERT INTO sites VALUES($1, $2, $3, $4, $5, $6)",
+                    new_site.id,
+                    new_site.host,
+                    new_site.name,
+                    new_site.is_default,
+                    new_site.default_locale,
+                    new_site.require_review
+                )
+                .execute(&mut *transaction)
+                .await?;
+
+                transaction.commit().await
+            }
+            .await;
+
+            if let Err(err) = result {
+                let _ = reply.send(Err(err.into()));
+            } else {
+                let _ = reply.send(Ok(()));
+                cache.remove_sites().await;
+            }
+        }
+        DatabaseMpscCommand::GetPageDrafts(site_id, reply) => {
+            let result = sqlx::query_as!(
+                schema::PageDraft,
+                "SELECT * FROM page_drafts WHERE site_id = $1 ORDER BY modified_at DESC",
+                site_id
+            )
+            .fetch_all(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::GetPageDraft(site_id, path, reply) => {
+            let result = sqlx::query_as!(
+                schema::PageDraft,
+                "SELECT * FROM page_drafts WHERE site_id = $1 AND \"path\" = $2",
+                site_id,
+                path
+            )
+            .fetch_one(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::SetPageDraft(draft, reply) => {
+            // Content is frozen once the draft is in review, the original author and reviewer are kept
+            let result = sqlx::query!(
+                "INSERT INTO page_drafts
+                (site_id, \"path\", created_at, created_by, modified_at, modified_by, metadata, body, sort_order, content_type, fields)
+                VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                ON CONFLICT (site_id, \"path\") DO UPDATE SET
+                modified_at = EXCLUDED.modified_at,
+                modified_by = EXCLUDED.modified_by,
+                metadata = EXCLUDED.metadata,
+                body = EXCLUDED.body,
+                sort_order = EXCLUDED.sort_order,
+                content_type = EXCLUDED.content_type,
+                fields = EXCLUDED.fields
+                WHERE page_drafts.state = 'draft'",
+                draft.site_id,
+                draft.path,
+                draft.created_at,
+                draft.created_by,
+                draft.modified_at,
+                draft.modified_by,
+                draft.metadata.as_slice(),
+                draft.body,
+                draft.sort_order,
+                draft.content_type,
+                draft.fields
+            )
+            .execute(pool)
+            .await;
+
+            match result {
+                Ok(result) if result.rows_affected() == 0 => {
+                    let _ = reply.send(Err(sqlx::Error::RowNotFound.into()));
+                }
+                Ok(_) => {
+                    let _ = reply.send(Ok(()));
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                }
+            }
+        }
+        DatabaseMpscCommand::DeletePageDraft(site_id, path, reply) => {
+            let result = sqlx::query!(
+                "DELETE FROM page_drafts WHERE site_id = $1 AND \"path\" = $2",
+                site_id,
+                path
+            )
+            .execute(pool)
+            .await;
+
+            match result {
+                Ok(result) if result.rows_affected() == 0 => {
+                    let _ = reply.send(Err(sqlx::Error::RowNotFound.into()));
+                }
+                Ok(_) => {
+                    let _ = reply.send(Ok(()));
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                }
+            }
+        }
+        DatabaseMpscCommand::TransitionPageDraft(site_id, path, from_state, to_state, reply)
+            if to_state == "published" =>
+        {
+            // The draft replaces the public page in one statement, a page in the trash comes back.
+            // Only the draft in from_state is published so a concurrent change can't slip through.
+            let result = sqlx::query!(
+                "WITH draft AS (
+                    DELETE FROM page_drafts
+                    WHERE site_id = $1 AND \"path\" = $2 AND state = $3
+                    RETURNING *
+                ), previous AS (
+                    SELECT published FROM pages
+                    WHERE site_id = $1 AND \"path\" = $2 AND deleted_at IS NULL
+                )
+                INSERT INTO pages
+                (site_id, \"path\", created_at, created_by, modified_at, modified_by, published, metadata, body, sort_order, content_type, fields)
+                SELECT site_id, \"path\", created_at, created_by, $4, modified_by, true, metadata, body, sort_order, content_type, fields
+                FROM draft
+                ON CONFLICT (site_id, \"path\") DO UPDATE SET
+                created_at = CASE WHEN pages.deleted_at IS NULL THEN pages.created_at ELSE EXCLUDED.created_at END,
+                created_by = CASE WHEN pages.deleted_at IS NULL THEN pages.created_by ELSE EXCLUDED.created_by END,
+                modified_at = EXCLUDED.modified_at,
+                modified_by = EXCLUDED.modified_by,
+                published = true,
+                metadata = EXCLUDED.metadata,
+                body = EXCLUDED.body,
+                sort_order = EXCLUDED.sort_order,
+                content_type = EXCLUDED.content_type,
+                fields = EXCLUDED.fields,
+                version = pages.version + 1,
+                deleted_at = NULL
+                RETURNING site_id, \"path\", created_at, created_by, modified_at, modified_by, published, metadata, body, sort_order, content_type, fields, version,
+                (SELECT published FROM previous) AS was_published",
+                site_id,
+                path,
+                from_state,
+                Utc::now().naive_utc()
+            )
+            .fetch_optional(pool)
+            .await;
+
+            let row = match result {
+                Ok(Some(row)) => row,
+                Ok(None) => {
+                    let _ = reply.send(Err(sqlx::Error::RowNotFound.into()));
+                    return;
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                    return;
+                }
+            };
+            let _ = reply.send(Ok(()));
+
+            let page = schema::Page {
+                site_id: row.site_id,
+                path: row.path,
+                created_at: row.created_at,
+                created_by: row.created_by,
+                modified_at: row.modified_at,
+                modified_by: row.modified_by,
+                published: row.published,
+                metadata: row.metadata,
+                body: row.body,
+                sort_order: row.sort_order,
+                content_type: row.content_type,
+                fields: row.fields,
+                version: row.version,
+            };
             cache.set_page(&page).await;
-            let _ = reply.send(Ok(page));
+
+            let published = Some(&page);
+            match row.was_published {
+                Some(was_published) => {
+                    cache.remove_rendered(site_id, &path).await;
+                    webhooks
+                        .fire(site_id, webhook::PAGE_UPDATED, &path, published)
+                        .await;
+                    if !was_published {
+                        webhooks
+                            .fire(site_id, webhook::PAGE_PUBLISHED, &path, published)
+                            .await;
+                    }
+                }
+                None => {
+                    cache.remove_menus(site_id).await;
+                    cache.remove_all_rendered(site_id).await;
+                    webhooks
+                        .fire(site_id, webhook::PAGE_CREATED, &path, published)
+                        .await;
+                    webhooks
+                        .fire(site_id, webhook::PAGE_PUBLISHED, &path, published)
+                        .await;
+                }
+            }
         }
-        DatabaseMpscCommand::SetPage(new_page, reply) => {
+        DatabaseMpscCommand::TransitionPageDraft(site_id, path, from_state, to_state, reply) => {
             let result = sqlx::query!(
-                "UPDATE pages SET 
-                created_at = $1, 
-                created_by = $2, 
-                modified_at = $3, 
-                modified_by = $4, 
-                published = $5, 
-                metadata = $6,
-                body = $7 
-                WHERE \"path\" = $8",
-                new_page.created_at,
-                new_page.created_by,
-                new_page.modified_at,
-                new_page.modified_by,
-                new_page.published,
-                new_page.metadata.as_slice(),
-                new_page.body,
-                new_page.path
+                "UPDATE page_drafts SET state = $4
+                WHERE site_id = $1 AND \"path\" = $2 AND state = $3",
+                site_id,
+                path,
+                from_state,
+                to_state
             )
             .execute(pool)
             .await;
 
-            if let Err(err) = result {
-                let _ = reply.send(Err(err.into()));
-            } else {
-                let _ = reply.send(Ok(()));
-                cache.set_page(&new_page).await;
+            match result {
+                Ok(result) if result.rows_affected() == 0 => {
+                    let _ = reply.send(Err(sqlx::Error::RowNotFound.into()));
+                }
+                Ok(_) => {
+                    let _ = reply.send(Ok(()));
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                }
             }
         }
-        DatabaseMpscCommand::DeletePage(path, reply) => {
-            let result = sqlx::query!("DELETE FROM pages WHERE \"path\" = $1", path)
-                .execute(pool)
-                .await;
+        DatabaseMpscCommand::SetPageDraftReviewer(site_id, path, reviewer_id, reply) => {
+            let result = sqlx::query!(
+                "UPDATE page_drafts SET reviewer_id = $3 WHERE site_id = $1 AND \"path\" = $2",
+                site_id,
+                path,
+                reviewer_id
+            )
+            .execute(pool)
+            .await;
 
-            if let Err(err) = result {
-                let _ = reply.send(Err(err.into()));
-            } else {
-                let _ = reply.send(Ok(()));
+            match result {
+                Ok(result) if result.rows_affected() == 0 => {
+                    let _ = reply.send(Err(sqlx::Error::RowNotFound.into()));
+                }
+                Ok(_) => {
+                    let _ = reply.send(Ok(()));
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                }
             }
         }
-        DatabaseMpscCommand::NewPage(new_page, reply) => {
+        DatabaseMpscCommand::GetWorkflowComments(site_id, path, reply) => {
+            let result = sqlx::query_as!(
+                schema::WorkflowComment,
+                "SELECT * FROM workflow_comments
+                WHERE site_id = $1 AND \"path\" = $2
+                ORDER BY created_at",
+                site_id,
+                path
+            )
+            .fetch_all(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::NewWorkflowComment(comment, reply) => {
             let result = sqlx::query!(
-                "INSERT INTO pages VALUES($1, $2, $3, $4, $5, $6, $7, $8)",
-                new_page.path,
-                new_page.created_at,
-                new_page.created_by,
-                new_page.modified_at,
-                new_page.modified_by,
-                new_page.published,
-                new_page.body,
-                new_page.metadata.as_slice()
+                "INSERT INTO workflow_comments (id, site_id, \"path\", author_id, body, created_at)
+                VALUES($1, $2, $3, $4, $5, $6)",
+                comment.id,
+                comment.site_id,
+                comment.path,
+                comment.author_id,
+                comment.body,
+                comment.created_at
             )
             .execute(pool)
             .await;
@@ -94,6 +1222,237 @@ pub async fn cmd(cmd: DatabaseMpscCommand, pool: &PgPool, cache: &mut cache::Cac
                 let _ = reply.send(Ok(()));
             }
         }
+        DatabaseMpscCommand::GetWorkflowTransitions(site_id, reply) => {
+            let result = sqlx::query_as!(
+                schema::WorkflowTransition,
+                "SELECT from_state, to_state, allowed FROM workflow_transitions
+                WHERE site_id = $1
+                ORDER BY from_state, to_state",
+                site_id
+            )
+            .fetch_all(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::SetWorkflowTransitions(site_id, transitions, reply) => {
+            // Replaces the whole set so removed transitions go away
+            let result = async {
+                let mut transaction = pool.begin().await?;
+
+                sqlx::query!(
+                    "DELETE FROM workflow_transitions WHERE site_id = $1",
+                    site_id
+                )
+                .execute(&mut *transaction)
+                .await?;
+
+                for transition in &transitions {
+                    sqlx::query!(
+                        "INSERT INTO workflow_transitions (site_id, from_state, to_state, allowed)
+                        VALUES($1, $2, $3, $4)",
+                        site_id,
+                        transition.from_state,
+                        transition.to_state,
+                        transition.allowed
+                    )
+                    .execute(&mut *transaction)
+                    .await?;
+                }
+
+                transaction.commit().await
+            }
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::GetPageTrash(site_id, reply) => {
+            let result = sqlx::query_as!(
+                schema::TrashedPage,
+                "SELECT site_id, \"path\", published, metadata, modified_at, modified_by, deleted_at AS \"deleted_at!\"
+                FROM pages
+                WHERE site_id = $1 AND deleted_at IS NOT NULL
+                ORDER BY deleted_at DESC",
+                site_id
+            )
+            .fetch_all(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::RestorePage(site_id, path, reply) => {
+            let result = sqlx::query_as!(
+                schema::Page,
+                "UPDATE pages SET deleted_at = NULL
+                WHERE site_id = $1 AND \"path\" = $2 AND deleted_at IS NOT NULL
+                RETURNING site_id, path, created_at, created_by, modified_at, modified_by, published, metadata, body, sort_order, content_type, fields, version",
+                site_id,
+                path
+            )
+            .fetch_optional(pool)
+            .await;
+
+            match result {
+                Ok(None) => {
+                    let _ = reply.send(Err(sqlx::Error::RowNotFound.into()));
+                }
+                Ok(Some(page)) => {
+                    let _ = reply.send(Ok(()));
+                    // Same as a new page, menus and links to it come back
+                    cache.remove_menus(site_id).await;
+                    cache.remove_all_rendered(site_id).await;
+
+                    let restored = Some(&page);
+                    webhooks
+                        .fire(site_id, webhook::PAGE_CREATED, &path, restored)
+                        .await;
+                    if page.published {
+                        webhooks
+                            .fire(site_id, webhook::PAGE_PUBLISHED, &path, restored)
+                            .await;
+                    }
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                }
+            }
+        }
+        DatabaseMpscCommand::PurgePage(site_id, path, reply) => {
+            // Only pages already in the trash, translations and menu items go with them.
+            // Drafts and review comments aren't tied to the page, so they are deleted here.
+            let result = sqlx::query_scalar!(
+                "WITH purged AS (
+                    DELETE FROM pages
+                    WHERE site_id = $1 AND \"path\" = $2 AND deleted_at IS NOT NULL
+                    RETURNING 1
+                ), purged_draft AS (
+                    DELETE FROM page_drafts
+                    WHERE site_id = $1 AND \"path\" = $2 AND EXISTS (SELECT 1 FROM purged)
+                ), purged_comments AS (
+                    DELETE FROM workflow_comments
+                    WHERE site_id = $1 AND \"path\" = $2 AND EXISTS (SELECT 1 FROM purged)
+                )
+                SELECT count(*) AS \"count!\" FROM purged",
+                site_id,
+                path
+            )
+            .fetch_one(pool)
+            .await;
+
+            match result {
+                Ok(0) => {
+                    let _ = reply.send(Err(sqlx::Error::RowNotFound.into()));
+                }
+                Ok(_) => {
+                    let _ = reply.send(Ok(()));
+                    cache.remove_translations(site_id, path).await;
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                }
+            }
+        }
+        DatabaseMpscCommand::GetUserTrash(reply) => {
+            let result = sqlx::query_as!(
+                schema::TrashedUser,
+                "SELECT id, username, email, deleted_at AS \"deleted_at!\",
+                EXISTS (SELECT 1 FROM pages WHERE created_by = admins.id OR modified_by = admins.id)
+                OR EXISTS (SELECT 1 FROM page_translations WHERE created_by = admins.id OR modified_by = admins.id)
+                OR EXISTS (SELECT 1 FROM blocks WHERE modified_by = admins.id)
+                OR EXISTS (SELECT 1 FROM page_drafts WHERE created_by = admins.id OR modified_by = admins.id)
+                OR EXISTS (SELECT 1 FROM workflow_comments WHERE author_id = admins.id) AS \"authored!\"
+                FROM admins
+                WHERE deleted_at IS NOT NULL
+                ORDER BY deleted_at DESC"
+            )
+            .fetch_all(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::RestoreUser(id, reply) => {
+            // Restored users stay disabled until someone enables them again
+            let result = sqlx::query!(
+                "UPDATE admins SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL",
+                id
+            )
+            .execute(pool)
+            .await;
+
+            match result {
+                Ok(result) if result.rows_affected() == 0 => {
+                    let _ = reply.send(Err(sqlx::Error::RowNotFound.into()));
+                }
+                Ok(_) => {
+                    let _ = reply.send(Ok(()));
+                    cache.remove_user(id).await;
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                }
+            }
+        }
+        DatabaseMpscCommand::PurgeUser(id, reply) => {
+            // Fails on the foreign keys while the user still authored anything
+            let result = sqlx::query!(
+                "DELETE FROM admins WHERE id = $1 AND deleted_at IS NOT NULL",
+                id
+            )
+            .execute(pool)
+            .await;
+
+            match result {
+                Ok(result) if result.rows_affected() == 0 => {
+                    let _ = reply.send(Err(sqlx::Error::RowNotFound.into()));
+                }
+                Ok(_) => {
+                    let _ = reply.send(Ok(()));
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                }
+            }
+        }
+        DatabaseMpscCommand::PurgeTrash(deleted_before, reply) => {
+            // Users are checked against the pages as they were before this statement,
+            // so authors of pages purged here go with the next run
+            let result = sqlx::query_scalar!(
+                "WITH purged_pages AS (
+                    DELETE FROM pages WHERE deleted_at < $1
+                    RETURNING site_id, \"path\"
+                ), purged_drafts AS (
+                    DELETE FROM page_drafts USING purged_pages
+                    WHERE page_drafts.site_id = purged_pages.site_id
+                    AND page_drafts.\"path\" = purged_pages.\"path\"
+                ), purged_comments AS (
+                    DELETE FROM workflow_comments USING purged_pages
+                    WHERE workflow_comments.site_id = purged_pages.site_id
+                    AND workflow_comments.\"path\" = purged_pages.\"path\"
+                ), purged_users AS (
+                    DELETE FROM admins WHERE deleted_at < $1
+                    AND NOT EXISTS (SELECT 1 FROM pages WHERE created_by = admins.id OR modified_by = admins.id)
+                    AND NOT EXISTS (SELECT 1 FROM page_translations WHERE created_by = admins.id OR modified_by = admins.id)
+                    AND NOT EXISTS (SELECT 1 FROM blocks WHERE modified_by = admins.id)
+                    AND NOT EXISTS (SELECT 1 FROM page_drafts WHERE created_by = admins.id OR modified_by = admins.id)
+                    AND NOT EXISTS (SELECT 1 FROM workflow_comments WHERE author_id = admins.id)
+                    RETURNING 1
+                )
+                SELECT (SELECT count(*) FROM purged_pages) + (SELECT count(*) FROM purged_users) AS \"count!\"",
+                deleted_before
+            )
+            .fetch_one(pool)
+            .await;
+
+            let _ = reply.send(result.map(|count| count as u64).map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::GetUsers(reply) => {
+            let result =
+                sqlx::query_as!(schema::AdminUser, "SELECT id, username, enabled, email, superuser FROM admins WHERE deleted_at IS NULL ORDER BY username")
+                    .fetch_all(pool)
+                    .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
         DatabaseMpscCommand::GetUser(id, skip_cache, reply) => {
             if !skip_cache {
                 if let Some(user) = cache.get_user(id).await {
@@ -103,7 +1462,7 @@ pub async fn cmd(cmd: DatabaseMpscCommand, pool: &PgPool, cache: &mut cache::Cac
             }
 
             let user =
-                match sqlx::query_as!(schema::AdminUser, "SELECT * FROM admins WHERE id = $1", id)
+                match sqlx::query_as!(schema::AdminUser, "SELECT id, username, enabled, email, superuser FROM admins WHERE id = $1 AND deleted_at IS NULL", id)
                     .fetch_one(pool)
                     .await
                 {
@@ -116,16 +1475,29 @@ pub async fn cmd(cmd: DatabaseMpscCommand, pool: &PgPool, cache: &mut cache::Cac
             cache.set_user(&user).await;
             let _ = reply.send(Ok(user));
         }
+        DatabaseMpscCommand::GetUsersById(ids, reply) => {
+            let result = sqlx::query_as!(
+                schema::AdminUser,
+                "SELECT id, username, enabled, email, superuser FROM admins WHERE id = ANY($1)",
+                ids.as_slice()
+            )
+            .fetch_all(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
         DatabaseMpscCommand::SetUser(new_user, reply) => {
             let result = sqlx::query!(
                 "UPDATE admins SET
                 username = $1,
                 enabled = $2,
-                email = $3
-                WHERE id = $4",
+                email = $3,
+                superuser = $4
+                WHERE id = $5 AND deleted_at IS NULL",
                 new_user.username,
                 new_user.enabled,
                 new_user.email,
+                new_user.superuser,
                 new_user.id
             )
             .execute(pool)
@@ -135,26 +1507,325 @@ pub async fn cmd(cmd: DatabaseMpscCommand, pool: &PgPool, cache: &mut cache::Cac
                 let _ = reply.send(Err(err.into()));
             } else {
                 let _ = reply.send(Ok(()));
+                cache.set_user(&new_user).await;
             }
         }
         DatabaseMpscCommand::DeleteUser(id, reply) => {
-            let result = sqlx::query!("DELETE FROM admins WHERE id = $1", id)
-                .execute(pool)
-                .await;
+            let result: Result<u64> = async {
+                let mut transaction = pool.begin().await?;
+                let entry = audit::user_entry("delete_user", id);
+                let audit = audit::Pending::start_in(&mut transaction, entry).await;
 
-            if let Err(err) = result {
-                let _ = reply.send(Err(err.into()));
-            } else {
-                let _ = reply.send(Ok(()));
+                // Users stay as disabled authors of their pages, their tokens stop working at once
+                let result = sqlx::query!(
+                    "WITH revoked AS (
+                        DELETE FROM admin_tokens WHERE admin_id = $1
+                    )
+                    UPDATE admins SET enabled = false, deleted_at = $2
+                    WHERE id = $1 AND deleted_at IS NULL",
+                    id,
+                    Utc::now().naive_utc()
+                )
+                .execute(&mut *transaction)
+                .await?;
+
+                audit.write(&mut transaction, actor).await?;
+                transaction.commit().await?;
+                Ok(result.rows_affected())
+            }
+            .await;
+
+            match result {
+                Ok(0) => {
+                    let _ = reply.send(Err(sqlx::Error::RowNotFound.into()));
+                }
+                Ok(_) => {
+                    let _ = reply.send(Ok(()));
+                    cache.remove_user(id).await;
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(err));
+                }
             }
         }
         DatabaseMpscCommand::NewUser(new_user, reply) => {
             let result = sqlx::query!(
-                "INSERT INTO admins VALUES($1, $2, $3, $4)",
+                "INSERT INTO admins (id, username, enabled, email, superuser)
+                VALUES($1, $2, $3, $4, $5)",
                 new_user.id,
                 new_user.username,
                 new_user.enabled,
-                new_user.email
+                new_user.email,
+                new_user.superuser
+            )
+            .execute(pool)
+            .await;
+
+            if let Err(err) = result {
+                let _ = reply.send(Err(err.into()));
+            } else {
+                let _ = reply.send(Ok(()));
+            }
+        }
+        DatabaseMpscCommand::GetUserByToken(token_hash, reply) => {
+            // Never cached so revoked tokens and disabled users take effect at once
+            let result = sqlx::query_as!(
+                schema::AdminUser,
+                "SELECT id, username, enabled, email, superuser FROM admins
+                JOIN admin_tokens ON admin_tokens.admin_id = admins.id
+                WHERE admin_tokens.token_hash = $1 AND deleted_at IS NULL",
+                token_hash
+            )
+            .fetch_one(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::NewToken(admin_id, token_hash, reply) => {
+            let result = sqlx::query!(
+                "INSERT INTO admin_tokens (token_hash, admin_id) VALUES($1, $2)",
+                token_hash,
+                admin_id
+            )
+            .execute(pool)
+            .await;
+
+            if let Err(err) = result {
+                let _ = reply.send(Err(err.into()));
+            } else {
+                let _ = reply.send(Ok(()));
+            }
+        }
+        DatabaseMpscCommand::GetApiKeys(site_id, reply) => {
+            let result = sqlx::query_as!(
+                schema::ApiKey,
+                "SELECT id, site_id, name, created_at FROM api_keys
+                WHERE site_id = $1 ORDER BY created_at",
+                site_id
+            )
+            .fetch_all(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::GetApiKeyByHash(key_hash, reply) => {
+            // Never cached so deleted keys stop working at once
+            let result = sqlx::query_as!(
+                schema::ApiKey,
+                "SELECT id, site_id, name, created_at FROM api_keys WHERE key_hash = $1",
+                key_hash
+            )
+            .fetch_one(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::NewApiKey(api_key, key_hash, reply) => {
+            let result = sqlx::query!(
+                "INSERT INTO api_keys (id, site_id, name, key_hash, created_at)
+                VALUES($1, $2, $3, $4, $5)",
+                api_key.id,
+                api_key.site_id,
+                api_key.name,
+                key_hash,
+                api_key.created_at
+            )
+            .execute(pool)
+            .await;
+
+            if let Err(err) = result {
+                let _ = reply.send(Err(err.into()));
+            } else {
+                let _ = reply.send(Ok(()));
+            }
+        }
+        DatabaseMpscCommand::DeleteApiKey(site_id, id, reply) => {
+            let result = sqlx::query!(
+                "DELETE FROM api_keys WHERE site_id = $1 AND id = $2",
+                site_id,
+                id
+            )
+            .execute(pool)
+            .await;
+
+            match result {
+                Ok(result) if result.rows_affected() == 0 => {
+                    let _ = reply.send(Err(sqlx::Error::RowNotFound.into()));
+                }
+                Ok(_) => {
+                    let _ = reply.send(Ok(()));
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                }
+            }
+        }
+        DatabaseMpscCommand::GetWebhooks(site_id, reply) => {
+            let result = sqlx::query_as!(
+                schema::Webhook,
+                "SELECT * FROM webhooks WHERE site_id = $1 ORDER BY created_at",
+                site_id
+            )
+            .fetch_all(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::NewWebhook(new_webhook, reply) => {
+            let result = sqlx::query!(
+                "INSERT INTO webhooks (id, site_id, url, secret, events, enabled, created_at)
+                VALUES($1, $2, $3, $4, $5, $6, $7)",
+                new_webhook.id,
+                new_webhook.site_id,
+                new_webhook.url,
+                new_webhook.secret,
+                new_webhook.events.as_slice(),
+                new_webhook.enabled,
+                new_webhook.created_at
+            )
+            .execute(pool)
+            .await;
+
+            if let Err(err) = result {
+                let _ = reply.send(Err(err.into()));
+            } else {
+                let _ = reply.send(Ok(()));
+            }
+        }
+        DatabaseMpscCommand::SetWebhook(new_webhook, reply) => {
+            // The secret and creation time never change
+            let result = sqlx::query!(
+                "UPDATE webhooks SET url = $3, events = $4, enabled = $5
+                WHERE site_id = $1 AND id = $2",
+                new_webhook.site_id,
+                new_webhook.id,
+                new_webhook.url,
+                new_webhook.events.as_slice(),
+                new_webhook.enabled
+            )
+            .execute(pool)
+            .await;
+
+            match result {
+                Ok(result) if result.rows_affected() == 0 => {
+                    let _ = reply.send(Err(sqlx::Error::RowNotFound.into()));
+                }
+                Ok(_) => {
+                    let _ = reply.send(Ok(()));
+                    // Re-enabling a webhook makes its waiting deliveries due
+                    webhooks.wake();
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                }
+            }
+        }
+        DatabaseMpscCommand::DeleteWebhook(site_id, id, reply) => {
+            let result = sqlx::query!(
+                "DELETE FROM webhooks WHERE site_id = $1 AND id = $2",
+                site_id,
+                id
+            )
+            .execute(pool)
+            .await;
+
+            match result {
+                Ok(result) if result.rows_affected() == 0 => {
+                    let _ = reply.send(Err(sqlx::Error::RowNotFound.into()));
+                }
+                Ok(_) => {
+                    let _ = reply.send(Ok(()));
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(err.into()));
+                }
+            }
+        }
+        DatabaseMpscCommand::GetWebhookDeliveries(site_id, webhook_id, limit, reply) => {
+            let result = sqlx::query_as!(
+                schema::WebhookDelivery,
+                "SELECT webhook_deliveries.* FROM webhook_deliveries
+                JOIN webhooks ON webhooks.id = webhook_deliveries.webhook_id
+                WHERE webhooks.site_id = $1 AND webhook_id = $2
+                ORDER BY webhook_deliveries.created_at DESC
+                LIMIT $3",
+                site_id,
+                webhook_id,
+                limit
+            )
+            .fetch_all(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::ReplayWebhookDelivery(site_id, id, reply) => {
+            let result = webhook::replay(pool, site_id, id).await;
+
+            if result.is_ok() {
+                webhooks.wake();
+            }
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::GetAuditLog(query, reply) => {
+            // Newest first, a NULL filter matches everything
+            let result = sqlx::query_as!(
+                schema::AuditLogEntry,
+                "SELECT * FROM audit_log
+                WHERE ($1::uuid IS NULL OR actor = $1)
+                AND ($2::text IS NULL OR action = $2)
+                AND ($3::uuid IS NULL OR site_id = $3)
+                AND ($4::text IS NULL OR target_type = $4)
+                AND ($5::text IS NULL OR target = $5)
+                AND ($6::timestamp IS NULL OR created_at >= $6)
+                AND ($7::timestamp IS NULL OR created_at < $7)
+                AND ($8::uuid IS NULL OR id < $8)
+                ORDER BY id DESC
+                LIMIT $9",
+                query.actor,
+                query.action,
+                query.site_id,
+                query.target_type,
+                query.target,
+                query.since,
+                query.until,
+                query.before,
+                query.limit
+            )
+            .fetch_all(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::GetSiteGrants(admin_id, reply) => {
+            let result = sqlx::query_scalar!(
+                "SELECT site_id FROM site_grants WHERE admin_id = $1",
+                admin_id
+            )
+            .fetch_all(pool)
+            .await;
+
+            let _ = reply.send(result.map_err(|err| err.into()));
+        }
+        DatabaseMpscCommand::NewSiteGrant(admin_id, site_id, reply) => {
+            let result = sqlx::query!(
+                "INSERT INTO site_grants VALUES($1, $2) ON CONFLICT DO NOTHING",
+                admin_id,
+                site_id
+            )
+            .execute(pool)
+            .await;
+
+            if let Err(err) = result {
+                let _ = reply.send(Err(err.into()));
+            } else {
+                let _ = reply.send(Ok(()));
+            }
+        }
+        DatabaseMpscCommand::DeleteSiteGrant(admin_id, site_id, reply) => {
+            let result = sqlx::query!(
+                "DELETE FROM site_grants WHERE admin_id = $1 AND site_id = $2",
+                admin_id,
+                site_id
             )
             .execute(pool)
             .await;
@@ -165,5 +1836,19 @@ pub async fn cmd(cmd: DatabaseMpscCommand, pool: &PgPool, cache: &mut cache::Cac
                 let _ = reply.send(Ok(()));
             }
         }
+        DatabaseMpscCommand::ExportSite(site_id, reply) => {
+            let _ = reply.send(transfer::export(pool, site_id).await);
+        }
+        DatabaseMpscCommand::ImportSite(site_id, export, options, reply) => {
+            let dry_run = options.dry_run;
+            let result = transfer::import(pool, site_id, *export, options).await;
+
+            // Everything the site shows may have changed, the name included
+            if result.is_ok() && !dry_run {
+                cache.remove_site_content(site_id).await;
+                cache.remove_sites().await;
+            }
+            let _ = reply.send(result);
+        }
     }
 }
@@ -5,11 +5,15 @@
  * See the file "LICENSE" in the root of this project.
  */
 
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::types::Json;
 use uuid::Uuid;
 
-#[derive(sqlx::FromRow, Debug, Clone)]
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
 pub struct Page {
+    pub site_id: Uuid,
     pub path: String,
     pub created_at: NaiveDateTime,
     pub created_by: Uuid,
@@ -19,16 +23,509 @@ pub struct Page {
     // permissions: Unkown
     pub metadata: Vec<String>,
     pub body: String,
+    // Position among siblings, lower comes first
+    pub sort_order: i32,
+    pub content_type: Option<String>,
+    // Values for the content type's fields keyed by field name, {} without a type
+    pub fields: Value,
+    // Bumped by every update, SetPage only applies to the version the page was read at
+    pub version: i32,
     // styles: Unkown
     // scripts: Unkown
 }
 
+impl Page {
+    // Parents are derived from the path: /news/2024/post -> /news/2024 -> /news -> /
+    pub fn ancestor_paths(&self) -> Vec<String> {
+        let mut paths = Vec::new();
+        let mut path = self.path.trim_end_matches('/');
+
+        while let Some(index) = path.rfind('/') {
+            path = &path[..index];
+            paths.push(if path.is_empty() {
+                String::from("/")
+            } else {
+                path.to_string()
+            });
+        }
+
+        paths.reverse();
+        paths
+    }
+
+    // Paths end up as file names in static exports, so they can't climb out of a directory
+    pub fn check_path(path: &str) -> Result<(), &'static str> {
+        if !path.starts_with('/') {
+            return Err("Paths must start with /");
+        }
+        if path.contains('\\') {
+            return Err("Paths can't contain backslashes");
+        }
+        if path
+            .split('/')
+            .any(|segment| segment == "." || segment == "..")
+        {
+            return Err("Paths can't contain . or .. segments");
+        }
+
+        Ok(())
+    }
+
+    // Pulls the contents of the first <title> tag out of the metadata
+    pub fn title(&self) -> Option<String> {
+        self.metadata.iter().find_map(|line| {
+            let start = line.find("<title>")? + "<title>".len();
+            let end = line[start..].find("</title>")? + start;
+            Some(line[start..end].trim().to_string())
+        })
+    }
+
+    // Pulls the content of <meta name="description"> out of the metadata
+    pub fn summary(&self) -> Option<String> {
+        self.metadata.iter().find_map(|line| {
+            if !line.contains("name=\"description\"") {
+                return None;
+            }
+
+            let start = line.find("content=\"")? + "content=\"".len();
+            let end = line[start..].find('"')? + start;
+            Some(line[start..end].trim().to_string())
+        })
+    }
+
+    // The page as it should be rendered in the translation's locale
+    pub fn translated(mut self, translation: &PageTranslation) -> Page {
+        self.modified_at = translation.modified_at;
+        self.modified_by = translation.modified_by;
+        self.published = translation.published;
+        self.metadata = translation.metadata.clone();
+        self.body = translation.body.clone();
+        self
+    }
+}
+
+// A variant of a page in another locale, sharing its path and position in the tree
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
+pub struct PageTranslation {
+    pub site_id: Uuid,
+    pub path: String,
+    // Lowercase language tag such as "es" or "pt-br"
+    pub locale: String,
+    pub created_at: NaiveDateTime,
+    pub created_by: Uuid,
+    pub modified_at: NaiveDateTime,
+    pub modified_by: Uuid,
+    pub published: bool,
+    pub metadata: Vec<String>,
+    pub body: String,
+}
+
 #[derive(sqlx::FromRow, Debug, Clone)]
+pub struct SearchResult {
+    pub path: String,
+    pub title: Option<String>,
+    // Body text with tags stripped but entities left as they are.
+    // Matches are wrapped in \u{2} and \u{3}, which never occur in the text itself.
+    pub snippet: String,
+}
+
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
 pub struct AdminUser {
     pub id: Uuid,
     // permissions: Unkown
     pub username: String,
     pub enabled: bool,
     pub email: String,
+    // Superusers manage sites and users and may edit every site
+    pub superuser: bool,
     // authentication: Unkown
 }
+
+// A deleted page waiting in the trash
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct TrashedPage {
+    pub site_id: Uuid,
+    pub path: String,
+    pub published: bool,
+    pub metadata: Vec<String>,
+    pub modified_at: NaiveDateTime,
+    pub modified_by: Uuid,
+    pub deleted_at: NaiveDateTime,
+}
+
+// A deleted user, they stay disabled in the trash for as long as they authored anything
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct TrashedUser {
+    pub id: Uuid,
+    pub username: String,
+    pub email: String,
+    pub deleted_at: NaiveDateTime,
+    pub authored: bool,
+}
+
+// Lets headless clients read unpublished pages of one site, only a hash of the key is stored
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub site_id: Uuid,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
+pub struct Site {
+    pub id: Uuid,
+    // Matched against the Host header without its port
+    pub host: String,
+    pub name: String,
+    // Serves every host that has no site of its own
+    pub is_default: bool,
+    // Locale of the pages themselves, translations add the others
+    pub default_locale: String,
+    // Published pages only change through reviewed drafts, except for superusers
+    pub require_review: bool,
+}
+
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
+pub struct Redirect {
+    pub id: Uuid,
+    pub site_id: Uuid,
+    // Exact path, or a Postgres regex when is_regex is set
+    pub source: String,
+    // May reference regex capture groups as \1, \2, ...
+    pub target: String,
+    // One of 301, 302, 307 or 308
+    pub status_code: i16,
+    pub is_regex: bool,
+    pub created_at: NaiveDateTime,
+}
+
+// A named fragment pages pull in with [[block name]]
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
+pub struct Block {
+    pub site_id: Uuid,
+    pub name: String,
+    pub body: String,
+    pub modified_at: NaiveDateTime,
+    pub modified_by: Uuid,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldKind {
+    Text,
+    // HTML, inserted as is
+    RichText,
+    Number,
+    // YYYY-MM-DD
+    Date,
+    // Path of another page on the same site
+    Reference,
+    // URL or path of an image
+    Media,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FieldDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub label: String,
+    pub kind: FieldKind,
+    #[serde(default)]
+    pub required: bool,
+}
+
+// A schema for pages such as team members or events, see Page::fields
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
+pub struct ContentType {
+    pub site_id: Uuid,
+    pub name: String,
+    pub label: String,
+    pub fields: Json<Vec<FieldDefinition>>,
+    // Rendered in place of the body of its pages when not empty.
+    // [[field name]] inserts a field, [[body]] the page body.
+    pub template: String,
+}
+
+impl ContentType {
+    // Checks the values of a page against the field definitions
+    pub fn validate(&self, fields: &Value) -> Result<(), String> {
+        let fields = match fields.as_object() {
+            Some(fields) => fields,
+            None => return Err(String::from("Fields must be an object")),
+        };
+
+        for name in fields.keys() {
+            if !self.fields.iter().any(|field| &field.name == name) {
+                return Err(format!("{} has no field named {}", self.name, name));
+            }
+        }
+
+        for field in self.fields.iter() {
+            let value = match fields.get(&field.name) {
+                Some(Value::Null) | None if field.required => {
+                    return Err(format!("{} is required", field.name))
+                }
+                Some(Value::Null) | None => continue,
+                Some(value) => value,
+            };
+
+            let valid = match field.kind {
+                FieldKind::Text | FieldKind::RichText => value.is_string(),
+                FieldKind::Number => value.is_number(),
+                FieldKind::Date => value
+                    .as_str()
+                    .is_some_and(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").is_ok()),
+                FieldKind::Reference => value.as_str().is_some_and(|path| path.starts_with('/')),
+                FieldKind::Media => value.as_str().is_some_and(|url| {
+                    url.starts_with('/')
+                        || url.starts_with("https://")
+                        || url.starts_with("http://")
+                }),
+            };
+
+            if !valid {
+                return Err(format!(
+                    "{} is not a valid {:?} value",
+                    field.name, field.kind
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Paths the page's reference fields point at
+    pub fn references<'a>(&self, fields: &'a Value) -> Vec<&'a str> {
+        self.fields
+            .iter()
+            .filter(|field| field.kind == FieldKind::Reference)
+            .filter_map(|field| fields.get(&field.name)?.as_str())
+            .collect()
+    }
+
+    pub fn field(&self, name: &str) -> Option<&FieldDefinition> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+// An endpoint that is sent a signed POST whenever one of its events happens
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub site_id: Uuid,
+    pub url: String,
+    // Key for the X-Magnetite-Signature HMAC, only handed out when the webhook is created
+    #[serde(skip_serializing)]
+    pub secret: String,
+    // Empty subscribes to every event
+    pub events: Vec<String>,
+    pub enabled: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub event: String,
+    pub payload: Value,
+    // pending, delivered or failed once the attempts run out
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: NaiveDateTime,
+    pub last_status_code: Option<i16>,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub delivered_at: Option<NaiveDateTime>,
+}
+
+// The next version of a page on its way through review
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct PageDraft {
+    pub site_id: Uuid,
+    pub path: String,
+    // draft, in_review or approved, publishing replaces the page and removes the draft
+    pub state: String,
+    pub reviewer_id: Option<Uuid>,
+    pub created_at: NaiveDateTime,
+    pub created_by: Uuid,
+    pub modified_at: NaiveDateTime,
+    pub modified_by: Uuid,
+    pub metadata: Vec<String>,
+    pub body: String,
+    pub sort_order: i32,
+    pub content_type: Option<String>,
+    pub fields: Value,
+}
+
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
+pub struct WorkflowTransition {
+    pub from_state: String,
+    pub to_state: String,
+    // editor, reviewer or superuser
+    pub allowed: String,
+}
+
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct WorkflowComment {
+    pub id: Uuid,
+    pub site_id: Uuid,
+    pub path: String,
+    pub author_id: Uuid,
+    pub body: String,
+    pub created_at: NaiveDateTime,
+}
+
+// One mutating command, before and after hold the target as JSON, null when it didn't exist
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct AuditLogEntry {
+    pub id: Uuid,
+    pub actor: Option<Uuid>,
+    pub ip: Option<String>,
+    pub action: String,
+    pub site_id: Option<Uuid>,
+    pub target_type: String,
+    pub target: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub created_at: NaiveDateTime,
+}
+
+// Filters for reading the audit log, every one is optional
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct AuditLogQuery {
+    pub actor: Option<Uuid>,
+    pub action: Option<String>,
+    pub site_id: Option<Uuid>,
+    pub target_type: Option<String>,
+    pub target: Option<String>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+    // Entries older than this id, ids are time ordered so this pages backwards
+    pub before: Option<Uuid>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PageSort {
+    #[default]
+    CreatedAt,
+    ModifiedAt,
+}
+
+// Filters for ListPages, every one is optional. Pages come newest first by the sort time.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PageListQuery {
+    pub prefix: Option<String>,
+    pub published: Option<bool>,
+    // Matches created_by
+    pub author: Option<Uuid>,
+    pub created_since: Option<NaiveDateTime>,
+    pub created_until: Option<NaiveDateTime>,
+    pub modified_since: Option<NaiveDateTime>,
+    pub modified_until: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub sort: PageSort,
+    // Sort time and path of the last page already seen, pages after it come next
+    pub after_at: Option<NaiveDateTime>,
+    pub after_path: Option<String>,
+    pub limit: Option<i64>,
+}
+
+// Finished HTML of a page, only ever kept in the cache
+#[derive(Debug, Clone)]
+pub struct RenderedPage {
+    pub site_id: Uuid,
+    pub path: String,
+    pub locale: String,
+    // Scheme and host the absolute links in the HTML were built with
+    pub base: String,
+    pub html: String,
+    // Blocks and other pages that went into the HTML
+    pub blocks: Vec<String>,
+    pub pages: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Menu {
+    pub site_id: Uuid,
+    pub name: String,
+    pub items: Vec<MenuItem>,
+}
+
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
+pub struct MenuItem {
+    pub id: Uuid,
+    pub menu: String,
+    pub sort_order: i32,
+    pub label: String,
+    // Exactly one of page_path and url is set
+    pub page_path: Option<String>,
+    pub url: Option<String>,
+}
+
+impl MenuItem {
+    pub fn href(&self) -> &str {
+        self.page_path
+            .as_deref()
+            .or(self.url.as_deref())
+            .unwrap_or("#")
+    }
+}
+
+// One site's content and settings as export_site reads it, see transfer::archive.
+// Users are all of them, without tokens, so authors can be matched up on import.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SiteExport {
+    // None for sources without settings, like WordPress, the workflow is then left alone too
+    pub site: Option<Site>,
+    pub users: Vec<AdminUser>,
+    pub content_types: Vec<ContentType>,
+    pub pages: Vec<Page>,
+    pub translations: Vec<PageTranslation>,
+    pub menus: Vec<Menu>,
+    pub blocks: Vec<Block>,
+    pub redirects: Vec<Redirect>,
+    pub workflow_transitions: Vec<WorkflowTransition>,
+}
+
+// What happens to pages, menus, blocks, content types and redirects whose key is taken
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    #[default]
+    Skip,
+    Overwrite,
+    // Pages move to a free path, everything else is skipped
+    Rename,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ImportOptions {
+    // Runs the import and rolls it back, the report says what would happen
+    #[serde(default)]
+    pub dry_run: bool,
+    // Site settings and workflow transitions are only replaced by Overwrite
+    #[serde(default)]
+    pub policy: ConflictPolicy,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ImportItem {
+    // page, translation, user, menu, block, content_type, redirect or settings,
+    // other formats add what they hold besides pages, like attachment or comment
+    pub kind: &'static str,
+    pub key: String,
+    // created, overwritten, renamed, skipped, matched, or unmapped for what a
+    // foreign format has that Magnetite can't hold
+    pub outcome: &'static str,
+    pub detail: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub items: Vec<ImportItem>,
+}
@@ -0,0 +1,617 @@
+/*
+ * database/transfer.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * Reading a whole site for export and writing one back on import. Imports
+ * run in a single transaction: a failure leaves the site as it was and a
+ * dry run is simply rolled back at the end. Authors are remapped to users
+ * of this database by username, then email, and created disabled when
+ * neither matches. Imports don't fire webhooks.
+ */
+
+use super::schema::{self, ConflictPolicy, ImportItem};
+use color_eyre::{eyre::eyre, Result};
+use sqlx::{types::Json, PgConnection, PgPool};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+pub async fn export(pool: &PgPool, site_id: Uuid) -> Result<schema::SiteExport> {
+    // Every read sees the same snapshot
+    let mut transaction = pool.begin().await?;
+    sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+        .execute(&mut *transaction)
+        .await?;
+
+    let site = sqlx::query_as!(schema::Site, "SELECT * FROM sites WHERE id = $1", site_id)
+        .fetch_one(&mut *transaction)
+        .await?;
+
+    // Trashed users too, they may still be the authors of pages
+    let users = sqlx::query_as!(
+        schema::AdminUser,
+        "SELECT id, username, enabled, email, superuser FROM admins ORDER BY username"
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    let content_types = sqlx::query_as!(
+        schema::ContentType,
+        "SELECT site_id, name, label, fields AS \"fields: Json<Vec<schema::FieldDefinition>>\", template
+        FROM content_types WHERE site_id = $1 ORDER BY name",
+        site_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    let pages = sqlx::query_as!(
+        schema::Page,
+        "SELECT site_id, path, created_at, created_by, modified_at, modified_by, published, metadata, body, sort_order, content_type, fields, version
+        FROM pages WHERE site_id = $1 AND deleted_at IS NULL ORDER BY \"path\"",
+        site_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    let translations = sqlx::query_as!(
+        schema::PageTranslation,
+        "SELECT page_translations.* FROM page_translations
+        JOIN pages USING (site_id, \"path\")
+        WHERE site_id = $1 AND deleted_at IS NULL
+        ORDER BY \"path\", locale",
+        site_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    let names = sqlx::query_scalar!(
+        "SELECT name FROM menus WHERE site_id = $1 ORDER BY name",
+        site_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let items = sqlx::query_as!(
+        schema::MenuItem,
+        "SELECT id, menu, sort_order, label, page_path, url FROM menu_items
+        WHERE site_id = $1 AND NOT EXISTS (
+            SELECT 1 FROM pages
+            WHERE pages.site_id = menu_items.site_id AND \"path\" = page_path
+            AND deleted_at IS NOT NULL
+        )
+        ORDER BY menu, sort_order",
+        site_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let menus = names
+        .into_iter()
+        .map(|name| schema::Menu {
+            site_id,
+            items: items
+                .iter()
+                .filter(|item| item.menu == name)
+                .cloned()
+                .collect(),
+            name,
+        })
+        .collect();
+
+    let blocks = sqlx::query_as!(
+        schema::Block,
+        "SELECT * FROM blocks WHERE site_id = $1 ORDER BY name",
+        site_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    let redirects = sqlx::query_as!(
+        schema::Redirect,
+        "SELECT id, site_id, source, target, status_code, is_regex, created_at
+        FROM redirects WHERE site_id = $1 ORDER BY created_at",
+        site_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    let workflow_transitions = sqlx::query_as!(
+        schema::WorkflowTransition,
+        "SELECT from_state, to_state, allowed FROM workflow_transitions
+        WHERE site_id = $1 ORDER BY from_state, to_state",
+        site_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(schema::SiteExport {
+        site: Some(site),
+        users,
+        content_types,
+        pages,
+        translations,
+        menus,
+        blocks,
+        redirects,
+        workflow_transitions,
+    })
+}
+
+fn item(kind: &'static str, key: &str, outcome: &'static str) -> ImportItem {
+    ImportItem {
+        kind,
+        key: key.to_string(),
+        outcome,
+        detail: None,
+    }
+}
+
+pub async fn import(
+    pool: &PgPool,
+    site_id: Uuid,
+    export: schema::SiteExport,
+    options: schema::ImportOptions,
+) -> Result<schema::ImportReport> {
+    let mut transaction = pool.begin().await?;
+    let mut items = Vec::new();
+    let policy = options.policy;
+
+    let users = import_users(&mut transaction, &export.users, &mut items).await?;
+    // Authors missing from the archive's users can't be mapped, so the import fails on them
+    let author = |id: Uuid| users.get(&id).copied().unwrap_or(id);
+
+    match &export.site {
+        Some(site) if policy == ConflictPolicy::Overwrite => {
+            import_settings(
+                &mut transaction,
+                site_id,
+                site,
+                &export.workflow_transitions,
+            )
+            .await?;
+            items.push(item("settings", &site.name, "overwritten"));
+        }
+        Some(site) => items.push(item("settings", &site.name, "skipped")),
+        None => {}
+    }
+
+    for content_type in export.content_types {
+        let exists = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM content_types WHERE site_id = $1 AND name = $2) AS \"exists!\"",
+            site_id,
+            content_type.name
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+
+        if exists && policy != ConflictPolicy::Overwrite {
+            items.push(item("content_type", &content_type.name, "skipped"));
+            continue;
+        }
+
+        sqlx::query!(
+            "INSERT INTO content_types (site_id, name, label, fields, template)
+            VALUES($1, $2, $3, $4, $5)
+            ON CONFLICT (site_id, name) DO UPDATE SET
+            label = EXCLUDED.label,
+            fields = EXCLUDED.fields,
+            template = EXCLUDED.template",
+            site_id,
+            content_type.name,
+            content_type.label,
+            content_type.fields as _,
+            content_type.template
+        )
+        .execute(&mut *transaction)
+        .await?;
+        let outcome = if exists { "overwritten" } else { "created" };
+        items.push(item("content_type", &content_type.name, outcome));
+    }
+
+    // Where each archived page ended up, skipped pages are left out
+    let mut paths: HashMap<String, String> = HashMap::new();
+    for page in export.pages {
+        if let Err(msg) = schema::Page::check_path(&page.path) {
+            items.push(ImportItem {
+                detail: Some(msg.to_string()),
+                ..item("page", &page.path, "skipped")
+            });
+            continue;
+        }
+
+        // Pages in the trash hold on to their path as well
+        let exists = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM pages WHERE site_id = $1 AND \"path\" = $2) AS \"exists!\"",
+            site_id,
+            page.path
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+
+        let path = match (exists, policy) {
+            (true, ConflictPolicy::Skip) => {
+                items.push(item("page", &page.path, "skipped"));
+                continue;
+            }
+            (true, ConflictPolicy::Rename) => {
+                let path = free_path(&mut transaction, site_id, &page.path).await?;
+                schema::Page::check_path(&path).map_err(|msg| eyre!("{}: {}", path, msg))?;
+                path
+            }
+            _ => page.path.clone(),
+        };
+
+        sqlx::query!(
+            "INSERT INTO pages
+            (site_id, \"path\", created_at, created_by, modified_at, modified_by, published, metadata, body, sort_order, content_type, fields)
+            VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+            ON CONFLICT (site_id, \"path\") DO UPDATE SET
+            created_at = EXCLUDED.created_at,
+            created_by = EXCLUDED.created_by,
+            modified_at = EXCLUDED.modified_at,
+            modified_by = EXCLUDED.modified_by,
+            published = EXCLUDED.published,
+            metadata = EXCLUDED.metadata,
+            body = EXCLUDED.body,
+            sort_order = EXCLUDED.sort_order,
+            content_type = EXCLUDED.content_type,
+            fields = EXCLUDED.fields,
+            version = pages.version + 1,
+            deleted_at = NULL",
+            site_id,
+            path,
+            page.created_at,
+            author(page.created_by),
+            page.modified_at,
+            author(page.modified_by),
+            page.published,
+            page.metadata.as_slice(),
+            page.body,
+            page.sort_order,
+            page.content_type,
+            page.fields
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        if path != page.path {
+            items.push(ImportItem {
+                detail: Some(format!("Imported as {}", path)),
+                ..item("page", &page.path, "renamed")
+            });
+        } else {
+            let outcome = if exists { "overwritten" } else { "created" };
+            items.push(item("page", &page.path, outcome));
+        }
+        paths.insert(page.path, path);
+    }
+
+    // Translations go wherever their page went
+    for translation in export.translations {
+        let key = format!("{} ({})", translation.path, translation.locale);
+        let path = match paths.get(&translation.path) {
+            Some(path) => path,
+            None => {
+                items.push(item("translation", &key, "skipped"));
+                continue;
+            }
+        };
+
+        sqlx::query!(
+            "INSERT INTO page_translations
+            (site_id, \"path\", locale, created_at, created_by, modified_at, modified_by, published, metadata, body)
+            VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            ON CONFLICT (site_id, \"path\", locale) DO UPDATE SET
+            created_at = EXCLUDED.created_at,
+            created_by = EXCLUDED.created_by,
+            modified_at = EXCLUDED.modified_at,
+            modified_by = EXCLUDED.modified_by,
+            published = EXCLUDED.published,
+            metadata = EXCLUDED.metadata,
+            body = EXCLUDED.body",
+            site_id,
+            path,
+            translation.locale,
+            translation.created_at,
+            author(translation.created_by),
+            translation.modified_at,
+            author(translation.modified_by),
+            translation.published,
+            translation.metadata.as_slice(),
+            translation.body
+        )
+        .execute(&mut *transaction)
+        .await?;
+        items.push(item("translation", &key, "created"));
+    }
+
+    for menu in export.menus {
+        let exists = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM menus WHERE site_id = $1 AND name = $2) AS \"exists!\"",
+            site_id,
+            menu.name
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+
+        if exists && policy != ConflictPolicy::Overwrite {
+            items.push(item("menu", &menu.name, "skipped"));
+            continue;
+        }
+
+        sqlx::query!(
+            "INSERT INTO menus (site_id, name) VALUES($1, $2) ON CONFLICT DO NOTHING",
+            site_id,
+            menu.name
+        )
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM menu_items WHERE site_id = $1 AND menu = $2",
+            site_id,
+            menu.name
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        let mut dropped = Vec::new();
+        for menu_item in menu.items {
+            // Links follow renamed pages, links to pages that aren't here are dropped
+            let page_path = match &menu_item.page_path {
+                Some(path) => match paths.get(path) {
+                    Some(path) => Some(path.clone()),
+                    None if page_exists(&mut transaction, site_id, path).await? => {
+                        Some(path.clone())
+                    }
+                    None => {
+                        dropped.push(menu_item.label);
+                        continue;
+                    }
+                },
+                None => None,
+            };
+
+            sqlx::query!(
+                "INSERT INTO menu_items
+                (id, site_id, menu, sort_order, label, page_path, url)
+                VALUES($1, $2, $3, $4, $5, $6, $7)",
+                Uuid::now_v7(),
+                site_id,
+                menu.name,
+                menu_item.sort_order,
+                menu_item.label,
+                page_path,
+                menu_item.url
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        items.push(ImportItem {
+            detail: match dropped.is_empty() {
+                true => None,
+                false => Some(format!(
+                    "Dropped links to missing pages: {}",
+                    dropped.join(", ")
+                )),
+            },
+            ..item(
+                "menu",
+                &menu.name,
+                if exists { "overwritten" } else { "created" },
+            )
+        });
+    }
+
+    for block in export.blocks {
+        let exists = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM blocks WHERE site_id = $1 AND name = $2) AS \"exists!\"",
+            site_id,
+            block.name
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+
+        if exists && policy != ConflictPolicy::Overwrite {
+            items.push(item("block", &block.name, "skipped"));
+            continue;
+        }
+
+        sqlx::query!(
+            "INSERT INTO blocks (site_id, name, body, modified_at, modified_by)
+            VALUES($1, $2, $3, $4, $5)
+            ON CONFLICT (site_id, name) DO UPDATE SET
+            body = EXCLUDED.body,
+            modified_at = EXCLUDED.modified_at,
+            modified_by = EXCLUDED.modified_by",
+            site_id,
+            block.name,
+            block.body,
+            block.modified_at,
+            author(block.modified_by)
+        )
+        .execute(&mut *transaction)
+        .await?;
+        let outcome = if exists { "overwritten" } else { "created" };
+        items.push(item("block", &block.name, outcome));
+    }
+
+    for redirect in export.redirects {
+        let exists = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM redirects WHERE site_id = $1 AND source = $2) AS \"exists!\"",
+            site_id,
+            redirect.source
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+
+        if exists && policy != ConflictPolicy::Overwrite {
+            items.push(item("redirect", &redirect.source, "skipped"));
+            continue;
+        }
+
+        sqlx::query!(
+            "INSERT INTO redirects
+            (id, site_id, source, target, status_code, is_regex, created_at)
+            VALUES($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (site_id, source) DO UPDATE SET
+            target = EXCLUDED.target,
+            status_code = EXCLUDED.status_code,
+            is_regex = EXCLUDED.is_regex",
+            Uuid::now_v7(),
+            site_id,
+            redirect.source,
+            redirect.target,
+            redirect.status_code,
+            redirect.is_regex,
+            redirect.created_at
+        )
+        .execute(&mut *transaction)
+        .await?;
+        let outcome = if exists { "overwritten" } else { "created" };
+        items.push(item("redirect", &redirect.source, outcome));
+    }
+
+    if options.dry_run {
+        transaction.rollback().await?;
+    } else {
+        transaction.commit().await?;
+    }
+
+    Ok(schema::ImportReport {
+        dry_run: options.dry_run,
+        items,
+    })
+}
+
+// Maps the archive's user ids to users of this database
+async fn import_users(
+    conn: &mut PgConnection,
+    users: &[schema::AdminUser],
+    items: &mut Vec<ImportItem>,
+) -> Result<HashMap<Uuid, Uuid>> {
+    let mut ids = HashMap::new();
+
+    for user in users {
+        let existing = sqlx::query_scalar!(
+            "SELECT id FROM admins
+            WHERE username = $1 OR (email <> '' AND email = $2)
+            ORDER BY username = $1 DESC
+            LIMIT 1",
+            user.username,
+            user.email
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        let id = match existing {
+            Some(id) => {
+                items.push(item("user", &user.username, "matched"));
+                id
+            }
+            None => {
+                // Without their tokens they couldn't sign in anyway, a superuser enables them
+                let id = Uuid::now_v7();
+                sqlx::query!(
+                    "INSERT INTO admins (id, username, enabled, email, superuser)
+                    VALUES($1, $2, false, $3, false)",
+                    id,
+                    user.username,
+                    user.email
+                )
+                .execute(&mut *conn)
+                .await?;
+                items.push(ImportItem {
+                    detail: Some(String::from("Created disabled")),
+                    ..item("user", &user.username, "created")
+                });
+                id
+            }
+        };
+
+        ids.insert(user.id, id);
+    }
+
+    Ok(ids)
+}
+
+// The host and default flag belong to the target, only the rest is copied
+async fn import_settings(
+    conn: &mut PgConnection,
+    site_id: Uuid,
+    site: &schema::Site,
+    workflow_transitions: &[schema::WorkflowTransition],
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE sites SET name = $2, default_locale = $3, require_review = $4 WHERE id = $1",
+        site_id,
+        site.name,
+        site.default_locale,
+        site.require_review
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM workflow_transitions WHERE site_id = $1",
+        site_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    for transition in workflow_transitions {
+        sqlx::query!(
+            "INSERT INTO workflow_transitions (site_id, from_state, to_state, allowed)
+            VALUES($1, $2, $3, $4)",
+            site_id,
+            transition.from_state,
+            transition.to_state,
+            transition.allowed
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+async fn page_exists(conn: &mut PgConnection, site_id: Uuid, path: &str) -> Result<bool> {
+    let exists = sqlx::query_scalar!(
+        "SELECT EXISTS(
+            SELECT 1 FROM pages WHERE site_id = $1 AND \"path\" = $2 AND deleted_at IS NULL
+        ) AS \"exists!\"",
+        site_id,
+        path
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(exists)
+}
+
+// /about becomes /about-imported, then /about-imported-2 and so on. / becomes /imported.
+async fn free_path(conn: &mut PgConnection, site_id: Uuid, path: &str) -> Result<String> {
+    let base = match path.trim_end_matches('/') {
+        "" => String::from("/imported"),
+        path => format!("{}-imported", path),
+    };
+    let mut candidate = base.clone();
+    let mut n = 1;
+
+    loop {
+        let taken = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM pages WHERE site_id = $1 AND \"path\" = $2) AS \"exists!\"",
+            site_id,
+            candidate
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        if !taken {
+            return Ok(candidate);
+        }
+
+        n += 1;
+        candidate = format!("{}-{}", base, n);
+    }
+}
@@ -0,0 +1,44 @@
+/*
+ * database/trash.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * Background task that empties the trash. Pages and users deleted longer
+ * ago than the retention window are removed for good, users who still
+ * authored something are kept disabled instead.
+ */
+
+use super::Database;
+use crate::util::println;
+use chrono::{TimeDelta, Utc};
+use std::time::Duration;
+use tokio::time::sleep;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub fn spawn_purge(
+    db: Database,
+    retention: TimeDelta,
+    tracker: &TaskTracker,
+    cancel_token: CancellationToken,
+) {
+    tracker.spawn(async move {
+        loop {
+            match db.purge_trash(Utc::now().naive_utc() - retention).await {
+                Ok(0) => {}
+                Ok(count) => println::info(format!("Purged {} items from the trash", count)),
+                Err(err) => println::error(format!("Failed to purge the trash: {}", err)),
+            }
+
+            tokio::select! {
+                _ = sleep(PURGE_INTERVAL) => {}
+                _ = cancel_token.cancelled() => {
+                    println::error("Trash Cancellation Token Received...");
+                    break;
+                }
+            }
+        }
+    });
+}
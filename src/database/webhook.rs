@@ -0,0 +1,600 @@
+/*
+ * database/webhook.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * Outgoing webhooks. Page commands queue a row in webhook_deliveries for
+ * every subscribed webhook, a background task POSTs them as JSON with an
+ * "X-Magnetite-Signature: sha256=<hex HMAC of the body>" header and retries
+ * failures with exponential backoff. Queueing and sending are separate, a
+ * Worker can be driven by hand without the background task.
+ */
+
+use super::schema;
+use crate::util::println;
+use chrono::{TimeDelta, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{net::lookup_host, sync::Notify, task::JoinSet, time::sleep};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use uuid::Uuid;
+
+pub const PAGE_CREATED: &str = "page.created";
+pub const PAGE_UPDATED: &str = "page.updated";
+pub const PAGE_PUBLISHED: &str = "page.published";
+pub const PAGE_UNPUBLISHED: &str = "page.unpublished";
+pub const PAGE_DELETED: &str = "page.deleted";
+
+// A delivery is given up on after this many attempts, 10s * 2^n apart
+const MAX_ATTEMPTS: i32 = 6;
+const BASE_DELAY_SECS: i64 = 10;
+// Deliveries that are due get picked up this often even without a wake up
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const BATCH_SIZE: i64 = 16;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// Claimed deliveries are hidden from other polls for this long
+const CLAIM_SECS: i64 = 60;
+
+pub struct Webhooks {
+    pool: PgPool,
+    wake: Arc<Notify>,
+}
+
+// Sends due deliveries, cheap to clone
+#[derive(Clone)]
+pub struct Worker {
+    pool: PgPool,
+    client: reqwest::Client,
+}
+
+struct DueDelivery {
+    id: Uuid,
+    event: String,
+    payload: Value,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+impl Webhooks {
+    // Only queues deliveries, nothing is sent until a worker is spawned
+    pub fn new(pool: PgPool) -> Webhooks {
+        Webhooks {
+            pool,
+            wake: Arc::new(Notify::new()),
+        }
+    }
+
+    // Sends deliveries in the background, right after they are queued or once due
+    pub fn spawn_worker(
+        &self,
+        worker: Worker,
+        tracker: &TaskTracker,
+        cancel_token: CancellationToken,
+    ) {
+        let wake = self.wake.clone();
+
+        tracker.spawn(async move {
+            loop {
+                // A full batch means more may be waiting
+                if worker.deliver_due().await == BATCH_SIZE as usize {
+                    continue;
+                }
+
+                tokio::select! {
+                    _ = wake.notified() => {}
+                    _ = sleep(POLL_INTERVAL) => {}
+                    _ = cancel_token.cancelled() => {
+                        println::error("Webhook Cancellation Token Received...");
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    // Queues the event for every enabled webhook of the site that wants it.
+    // The change already happened by now, so failing to queue is only logged.
+    pub async fn fire(&self, site_id: Uuid, event: &str, path: &str, page: Option<&schema::Page>) {
+        let now = Utc::now().naive_utc();
+        let payload = json!({
+            "event": event,
+            "site_id": site_id,
+            "path": path,
+            "page": page,
+            "occurred_at": now,
+        });
+
+        let result = sqlx::query!(
+            "INSERT INTO webhook_deliveries (id, webhook_id, event, payload, next_attempt_at, created_at)
+            SELECT gen_random_uuid(), id, $2, $3, $4, $4 FROM webhooks
+            WHERE site_id = $1 AND enabled AND (cardinality(events) = 0 OR $2 = ANY(events))",
+            site_id,
+            event,
+            payload,
+            now
+        )
+        .execute(&self.pool)
+        .await;
+
+        match result {
+            Ok(result) if result.rows_affected() > 0 => self.wake.notify_one(),
+            Ok(_) => {}
+            Err(err) => println::error(format!("Failed to queue {} webhooks: {}", event, err)),
+        }
+    }
+
+    // For deliveries queued elsewhere, like replays
+    pub fn wake(&self) {
+        self.wake.notify_one();
+    }
+}
+
+// The client deliveries are sent with. Redirects aren't followed and host
+// names only resolve to public addresses, so a webhook can't be pointed at
+// the server's own network.
+pub fn client() -> reqwest::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .dns_resolver(PublicResolver)
+        .build()
+}
+
+// Literal addresses never reach the resolver, so they are checked here when a
+// webhook is saved
+pub fn check_url(url: &str) -> Result<(), &'static str> {
+    let url = reqwest::Url::parse(url).map_err(|_| "Invalid webhook URL")?;
+
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err("Webhook URLs must be http or https");
+    }
+
+    let host = url.host_str().ok_or("Webhook URLs need a host")?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let private = match host.parse::<IpAddr>() {
+        Ok(ip) => !is_public(ip),
+        Err(_) => {
+            let host = host.to_ascii_lowercase();
+            host == "localhost" || host.ends_with(".localhost")
+        }
+    };
+
+    if private {
+        return Err("Webhooks can't target private or loopback addresses");
+    }
+
+    Ok(())
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // "This network", carrier-grade NAT, IETF protocol assignments,
+                // benchmarking and reserved
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 192 && b == 0 && c == 0)
+                || (a == 198 && (18..20).contains(&b))
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Unique local, link-local and documentation
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+                || (first == 0x2001 && ip.segments()[1] == 0x0db8))
+        }
+    }
+}
+
+// Drops private addresses from every lookup, a host with nothing else left fails
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+// A replay is a fresh delivery of the same payload, the original stays in the log
+pub async fn replay(
+    pool: &PgPool,
+    site_id: Uuid,
+    id: Uuid,
+) -> sqlx::Result<schema::WebhookDelivery> {
+    let now = Utc::now().naive_utc();
+
+    sqlx::query_as!(
+        schema::WebhookDelivery,
+        "INSERT INTO webhook_deliveries (id, webhook_id, event, payload, next_attempt_at, created_at)
+        SELECT $3, webhook_id, event, payload, $4, $4 FROM webhook_deliveries
+        JOIN webhooks ON webhooks.id = webhook_deliveries.webhook_id
+        WHERE webhooks.site_id = $1 AND webhook_deliveries.id = $2
+        RETURNING *",
+        site_id,
+        id,
+        Uuid::now_v7(),
+        now
+    )
+    .fetch_one(pool)
+    .await
+}
+
+// Hex encoded HMAC-SHA256 of the body, receivers compute the same to verify it
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body.as_bytes());
+
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+impl Worker {
+    pub fn new(pool: PgPool, client: reqwest::Client) -> Worker {
+        Worker { pool, client }
+    }
+
+    // Returns how many deliveries were attempted
+    pub async fn deliver_due(&self) -> usize {
+        let now = Utc::now().naive_utc();
+        let claimed_until = now + TimeDelta::seconds(CLAIM_SECS);
+
+        let due = match sqlx::query_as!(
+            DueDelivery,
+            "UPDATE webhook_deliveries SET next_attempt_at = $2
+            FROM webhooks
+            WHERE webhooks.id = webhook_deliveries.webhook_id
+            AND webhook_deliveries.id IN (
+                SELECT webhook_deliveries.id FROM webhook_deliveries
+                JOIN webhooks ON webhooks.id = webhook_deliveries.webhook_id
+                WHERE status = 'pending' AND next_attempt_at <= $1 AND enabled
+                ORDER BY next_attempt_at
+                LIMIT $3
+                FOR UPDATE OF webhook_deliveries SKIP LOCKED
+            )
+            RETURNING webhook_deliveries.id, event, payload, attempts, url, secret",
+            now,
+            claimed_until,
+            BATCH_SIZE
+        )
+        .fetch_all(&self.pool)
+        .await
+        {
+            Ok(due) => due,
+            Err(err) => {
+                println::error(format!("Failed to load webhook deliveries: {}", err));
+                return 0;
+            }
+        };
+
+        let count = due.len();
+        let mut attempts = JoinSet::new();
+        for delivery in due {
+            let worker = self.clone();
+            attempts.spawn(async move { worker.attempt(delivery).await });
+        }
+        while attempts.join_next().await.is_some() {}
+
+        count
+    }
+
+    async fn attempt(&self, delivery: DueDelivery) {
+        let body = delivery.payload.to_string();
+        let signature = format!("sha256={}", sign(&delivery.secret, &body));
+
+        let result = self
+            .client
+            .post(&delivery.url)
+            .header("Content-Type", "application/json")
+            .header("X-Magnetite-Event", &delivery.event)
+            .header("X-Magnetite-Delivery", delivery.id.to_string())
+            .header("X-Magnetite-Signature", signature)
+            .body(body)
+            .send()
+            .await;
+
+        let attempts = delivery.attempts + 1;
+        let now = Utc::now().naive_utc();
+
+        let (status_code, error) = match result {
+            Ok(response) if response.status().is_success() => {
+                (Some(response.status().as_u16() as i16), None)
+            }
+            Ok(response) => (
+                Some(response.status().as_u16() as i16),
+                Some(format!("Unexpected status {}", response.status())),
+            ),
+            Err(err) => (None, Some(err.to_string())),
+        };
+
+        let (status, next_attempt_at, delivered_at) = match &error {
+            None => ("delivered", now, Some(now)),
+            Some(_) if attempts >= MAX_ATTEMPTS => ("failed", now, None),
+            Some(_) => (
+                "pending",
+                now + TimeDelta::seconds(BASE_DELAY_SECS << (attempts - 1)),
+                None,
+            ),
+        };
+
+        let result = sqlx::query!(
+            "UPDATE webhook_deliveries SET
+            status = $2,
+            attempts = $3,
+            next_attempt_at = $4,
+            last_status_code = $5,
+            last_error = $6,
+            delivered_at = $7
+            WHERE id = $1",
+            delivery.id,
+            status,
+            attempts,
+            next_attempt_at,
+            status_code,
+            error,
+            delivered_at
+        )
+        .execute(&self.pool)
+        .await;
+
+        if let Err(err) = result {
+            println::error(format!(
+                "Failed to record webhook delivery {}: {}",
+                delivery.id, err
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use sqlx::{postgres::PgConnectOptions, Executor};
+    use std::str::FromStr;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    struct Received {
+        headers: String,
+        body: String,
+    }
+
+    // Answers one request per status with that status and reports what it was sent
+    async fn stub(statuses: Vec<u16>) -> (String, mpsc::UnboundedReceiver<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            for status in statuses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                let (headers, body) = loop {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    let Some((headers, body)) = text.split_once("\r\n\r\n") else {
+                        continue;
+                    };
+                    let length = headers
+                        .lines()
+                        .find_map(|line| {
+                            let (name, value) = line.split_once(':')?;
+                            name.eq_ignore_ascii_case("content-length")
+                                .then(|| value.trim().parse::<usize>().ok())?
+                        })
+                        .unwrap_or(0);
+
+                    if body.len() >= length || read == 0 {
+                        break (headers.to_ascii_lowercase(), body.to_string());
+                    }
+                };
+
+                let response = format!(
+                    "HTTP/1.1 {} Stub\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+                let _ = tx.send(Received { headers, body });
+            }
+        });
+
+        (url, rx)
+    }
+
+    // A scratch database with the migrations applied, dropped by the caller
+    pub(crate) async fn scratch_pool(url: &str) -> (PgPool, PgPool, String) {
+        let admin = PgPool::connect(url).await.unwrap();
+        let name = format!("magnetite_test_{}", Uuid::new_v4().simple());
+        admin
+            .execute(format!("CREATE DATABASE {}", name).as_str())
+            .await
+            .unwrap();
+
+        let options = PgConnectOptions::from_str(url).unwrap().database(&name);
+        let pool = PgPool::connect_with(options).await.unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+
+        (admin, pool, name)
+    }
+
+    #[test]
+    fn only_public_addresses_are_public() {
+        for ip in [
+            "93.184.216.34",
+            "2606:2800:220:1::1",
+            "::ffff:93.184.216.34",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "100.64.0.1",
+            "198.18.0.1",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "2001:db8::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn checks_webhook_urls() {
+        assert!(check_url("https://example.com/hook").is_ok());
+        assert!(check_url("http://93.184.216.34:8080/hook").is_ok());
+
+        for url in [
+            "not a url",
+            "ftp://example.com/hook",
+            "file:///etc/passwd",
+            "http://localhost/hook",
+            "http://api.LOCALHOST/hook",
+            "http://127.0.0.1/hook",
+            "http://[::1]/hook",
+            "http://[::ffff:10.0.0.1]/hook",
+            "http://169.254.169.254/latest/meta-data",
+        ] {
+            assert!(check_url(url).is_err(), "{}", url);
+        }
+    }
+
+    // Run with --include-ignored against a Postgres server, CI does
+    #[tokio::test]
+    #[ignore = "needs a Postgres server in DATABASE_URL"]
+    async fn delivers_signed_payloads_and_retries_failures() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set");
+        let (admin, pool, name) = scratch_pool(&url).await;
+
+        let (stub_url, mut received) = stub(vec![500, 200]).await;
+        let site_id: Uuid = sqlx::query_scalar("SELECT id FROM sites WHERE is_default")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO webhooks (id, site_id, url, secret) VALUES ($1, $2, $3, 'hush')")
+            .bind(Uuid::now_v7())
+            .bind(site_id)
+            .bind(&stub_url)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        Webhooks::new(pool.clone())
+            .fire(site_id, PAGE_PUBLISHED, "/hello", None)
+            .await;
+
+        // The stub is on loopback, which the client from client() refuses
+        let worker = Worker::new(pool.clone(), reqwest::Client::new());
+
+        // First attempt gets a 500
+        let before = Utc::now().naive_utc();
+        assert_eq!(worker.deliver_due().await, 1);
+
+        let request = received.recv().await.unwrap();
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"hush").unwrap();
+        mac.update(request.body.as_bytes());
+        let expected: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        assert!(request
+            .headers
+            .contains(&format!("x-magnetite-signature: sha256={}", expected)));
+        assert!(request
+            .headers
+            .contains("x-magnetite-event: page.published"));
+
+        let delivery: schema::WebhookDelivery = sqlx::query_as("SELECT * FROM webhook_deliveries")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(delivery.status, "pending");
+        assert_eq!(delivery.attempts, 1);
+        assert_eq!(delivery.last_status_code, Some(500));
+        assert!(delivery.next_attempt_at >= before + TimeDelta::seconds(BASE_DELAY_SECS));
+
+        // Not due yet, so nothing is sent
+        assert_eq!(worker.deliver_due().await, 0);
+
+        // The retry gets a 200
+        sqlx::query("UPDATE webhook_deliveries SET next_attempt_at = $1")
+            .bind(Utc::now().naive_utc())
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(worker.deliver_due().await, 1);
+        received.recv().await.unwrap();
+
+        let delivery: schema::WebhookDelivery = sqlx::query_as("SELECT * FROM webhook_deliveries")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(delivery.status, "delivered");
+        assert_eq!(delivery.attempts, 2);
+        assert_eq!(delivery.last_status_code, Some(200));
+        assert!(delivery.delivered_at.is_some());
+
+        pool.close().await;
+        admin
+            .execute(format!("DROP DATABASE {} WITH (FORCE)", name).as_str())
+            .await
+            .unwrap();
+    }
+}
@@ -15,14 +15,20 @@
  * - Inactive until error then attempt restart
  */
 
+use chrono::TimeDelta;
 use color_eyre::Result;
 use std::env;
+use std::sync::Arc;
 use tokio::signal;
 use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
-use util::println;
+use util::{println, token};
+use uuid::Uuid;
 
+mod cli;
 mod database;
+mod plugin;
+mod transfer;
 mod util;
 mod web;
 
@@ -33,11 +39,46 @@ async fn shutdown(cancel_token: CancellationToken, tracker: TaskTracker) {
     tracker.wait().await;
 }
 
+// Creates a first admin user when there are none so the admin API is reachable
+async fn bootstrap_admin(db: &database::Database) -> Result<()> {
+    if !db.get_users().await?.is_empty() {
+        return Ok(());
+    }
+
+    let user = database::schema::AdminUser {
+        id: Uuid::now_v7(),
+        username: String::from("admin"),
+        enabled: true,
+        email: env::var("ADMIN_EMAIL").unwrap_or_default(),
+        superuser: true,
+    };
+    let new_token = token::generate();
+
+    db.new_user(user.clone()).await?;
+    db.new_token(user.id, token::hash(&new_token)).await?;
+
+    println::important(format!(
+        "Created admin user \"{}\". API token (shown once): {}",
+        user.username, new_token
+    ));
+
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     color_eyre::install()?;
     dotenvy::dotenv()?;
 
+    let command = match cli::parse(env::args().skip(1)) {
+        Ok(command) => command,
+        Err(msg) => {
+            println::error(msg);
+            println::info(cli::USAGE);
+            return Ok(());
+        }
+    };
+
     println::important("Magnetite CMS Server Starting...");
 
     // Pull environment variables
@@ -57,6 +98,35 @@ async fn main() -> Result<()> {
         }
     };
 
+    // Comma seperated list of path prefixes such as "/news/" that get feeds
+    let feed_prefixes: Vec<String> = match env::var("FEED_PREFIXES") {
+        Ok(var) => var
+            .split(',')
+            .map(|prefix| prefix.trim().trim_matches('/'))
+            .filter(|prefix| !prefix.is_empty())
+            .map(|prefix| format!("/{}/", prefix))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    // Only set behind a reverse proxy, clients can send X-Forwarded-Host themselves
+    let trust_proxy = matches!(
+        env::var("TRUST_PROXY").as_deref().map(str::trim),
+        Ok("true") | Ok("1")
+    );
+
+    // Deleted pages and users are purged after this many days
+    let trash_retention_days = match env::var("TRASH_RETENTION_DAYS") {
+        Ok(var) => match var.trim().parse::<u32>() {
+            Ok(days) => days,
+            Err(_) => {
+                println::warn("Invalid trash retention. Using default of 30 days.");
+                30
+            }
+        },
+        Err(_) => 30,
+    };
+
     // Cancellation Tokens
     let cancel_token = CancellationToken::new();
 
@@ -66,16 +136,55 @@ async fn main() -> Result<()> {
     let tracker = TaskTracker::new();
 
     // Load Plugins
-    println::warn("Plugin Functionality is still being implemented");
+    // Comma seperated list of plugin names to enable
+    let plugin_names: Vec<String> = match env::var("PLUGINS") {
+        Ok(var) => var
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    let plugins = Arc::new(plugin::Plugins::load(&plugin_names));
 
     // Setup database thread
     println::info("Initializing DB");
-    let db = database::Database::new(database_url, &tracker, db_cancel_token).await?;
+    let db = database::Database::new(
+        database_url,
+        TimeDelta::days(trash_retention_days.into()),
+        &tracker,
+        db_cancel_token,
+    )
+    .await?;
     println::info("Sucessfully connected to DB");
+    bootstrap_admin(&db).await?;
+
+    plugins.on_startup(&db, &tracker, &cancel_token);
+
+    // Commands use the database and exit without starting anything else,
+    // plugins are loaded since export-static renders pages through them
+    if let Some(command) = command {
+        let result = cli::run(command, &db, plugins).await;
+        tracker.close();
+        shutdown(cancel_token, tracker).await;
+        return result;
+    }
 
     // Setup actix thread
     println::info(format!("Starting HTTP Server on {}", server_bind));
-    web::start_server(server_bind, db, &tracker, web_cancel_token).await?;
+    for prefix in &feed_prefixes {
+        println::info(format!("Serving RSS and Atom feeds for {}", prefix));
+    }
+    web::start_server(
+        server_bind,
+        db,
+        feed_prefixes,
+        plugins,
+        trust_proxy,
+        &tracker,
+        web_cancel_token,
+    )
+    .await?;
 
     tracker.close();
 
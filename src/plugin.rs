@@ -0,0 +1,139 @@
+/*
+ * plugin.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * Plugins are compiled into the binary and switched on by name through
+ * the PLUGINS env variable. Every hook has a default that does nothing,
+ * so a plugin only implements the ones it needs.
+ */
+
+use crate::{
+    database::{schema, Database},
+    util::println,
+};
+use actix_web::web::ServiceConfig;
+use async_trait::async_trait;
+use color_eyre::Result;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use uuid::Uuid;
+
+mod generator;
+mod wasm;
+
+#[async_trait]
+pub trait Plugin: Send + Sync {
+    // Used to enable the plugin and in log messages
+    fn name(&self) -> &str;
+
+    // Runs once the database is ready. Long running work should be spawned
+    // on the tracker and stop when the token is cancelled.
+    fn on_startup(
+        &self,
+        _db: &Database,
+        _tracker: &TaskTracker,
+        _cancel_token: &CancellationToken,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    // May change the page before it is turned into HTML
+    async fn before_render(&self, _page: &mut schema::Page) {}
+
+    // May change the finished document
+    async fn after_render(&self, _page: &schema::Page, _html: &mut String) {}
+
+    // Runs after a page was created or updated through the admin API
+    async fn on_page_saved(&self, _page: &schema::Page) {}
+
+    // Expands [[name args]] for shortcodes the built-ins don't know.
+    // args is the text after the name as written, None leaves the shortcode alone.
+    async fn render_shortcode(&self, _site_id: Uuid, _name: &str, _args: &str) -> Option<String> {
+        None
+    }
+
+    // Routes are registered ahead of managed pages, so they take precedence
+    fn register_routes(&self, _cfg: &mut ServiceConfig) {}
+}
+
+// Every plugin that can be enabled
+fn available() -> Vec<Box<dyn Plugin>> {
+    vec![
+        Box::new(generator::Generator),
+        Box::new(wasm::WasmPlugins::default()),
+    ]
+}
+
+// The enabled plugins, hooks run in the order they were listed in
+pub struct Plugins {
+    plugins: Vec<Box<dyn Plugin>>,
+}
+
+impl Plugins {
+    pub fn load(names: &[String]) -> Plugins {
+        let mut available = available();
+        let mut plugins = Vec::new();
+
+        for name in names {
+            match available.iter().position(|plugin| plugin.name() == name) {
+                Some(index) => {
+                    println::info(format!("Loaded plugin {}", name));
+                    plugins.push(available.remove(index));
+                }
+                None => println::warn(format!("Unknown plugin {}. Skipping.", name)),
+            }
+        }
+
+        Plugins { plugins }
+    }
+
+    // A failing plugin is reported but doesn't stop the server
+    pub fn on_startup(
+        &self,
+        db: &Database,
+        tracker: &TaskTracker,
+        cancel_token: &CancellationToken,
+    ) {
+        for plugin in &self.plugins {
+            if let Err(err) = plugin.on_startup(db, tracker, cancel_token) {
+                println::error(format!("Plugin {} failed to start: {}", plugin.name(), err));
+            }
+        }
+    }
+
+    pub async fn before_render(&self, page: &mut schema::Page) {
+        for plugin in &self.plugins {
+            plugin.before_render(page).await;
+        }
+    }
+
+    pub async fn after_render(&self, page: &schema::Page, html: &mut String) {
+        for plugin in &self.plugins {
+            plugin.after_render(page, html).await;
+        }
+    }
+
+    pub async fn on_page_saved(&self, page: &schema::Page) {
+        for plugin in &self.plugins {
+            plugin.on_page_saved(page).await;
+        }
+    }
+
+    // The first plugin to return something wins
+    pub async fn render_shortcode(&self, site_id: Uuid, name: &str, args: &str) -> Option<String> {
+        for plugin in &self.plugins {
+            if let Some(html) = plugin.render_shortcode(site_id, name, args).await {
+                return Some(html);
+            }
+        }
+
+        None
+    }
+
+    pub fn register_routes(&self, cfg: &mut ServiceConfig) {
+        for plugin in &self.plugins {
+            plugin.register_routes(cfg);
+        }
+    }
+}
@@ -0,0 +1,27 @@
+/*
+ * plugin/generator.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ */
+
+use super::Plugin;
+use crate::database::schema;
+use async_trait::async_trait;
+
+// Adds a <meta name="generator"> tag to every page
+pub struct Generator;
+
+#[async_trait]
+impl Plugin for Generator {
+    fn name(&self) -> &str {
+        "generator"
+    }
+
+    async fn before_render(&self, page: &mut schema::Page) {
+        page.metadata.push(format!(
+            "<meta name=\"generator\" content=\"Magnetite CMS {}\">",
+            env!("CARGO_PKG_VERSION")
+        ));
+    }
+}
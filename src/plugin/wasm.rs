@@ -0,0 +1,676 @@
+/*
+ * plugin/wasm.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * Runs .wasm (and .wat) modules from PLUGIN_DIR in a sandbox.
+ *
+ * Strings cross the boundary as (pointer, length) pairs in the module's memory.
+ * Functions that return a string pack it into an i64 as pointer << 32 | length,
+ * where 0 means "nothing".
+ *
+ * A module has to export:
+ * - memory
+ * - alloc(len: i32) -> i32, used by the host to hand strings to the module
+ *
+ * And may export:
+ * - init(), called once after loading
+ * - transform_html(ptr, len) -> i64, gets every rendered page
+ * - render_shortcode(name_ptr, name_len, args_ptr, args_len) -> i64
+ *
+ * The host provides, in the "magnetite" namespace:
+ * - log(ptr, len)
+ * - register_shortcode(ptr, len), only allowed during init
+ * - get_page(path_ptr, path_len) -> i64, the published page as JSON
+ *
+ * A call that fails, e.g. by trapping or running out of fuel, starts the
+ * module over from a fresh instance since its memory may be half written.
+ * A module failing MAX_TRAPS calls in a row is unloaded.
+ */
+
+use super::Plugin;
+use crate::{
+    database::{schema, Database},
+    util::println,
+};
+use async_trait::async_trait;
+use color_eyre::{eyre::eyre, Result};
+use std::{env, path::PathBuf, sync::OnceLock};
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use uuid::Uuid;
+use wasmtime::{
+    AsContext, AsContextMut, Caller, Config, Engine, Instance, Linker, Memory, Module, Store,
+    StoreLimits, StoreLimitsBuilder, TypedFunc,
+};
+
+// Linear memory a single module may grow to
+const MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+// Roughly the number of instructions a single call may execute
+const FUEL_PER_CALL: u64 = 50_000_000;
+const MAX_TRAPS: u32 = 3;
+const NAMESPACE: &str = "magnetite";
+
+pub enum WasmCommand {
+    // TransformHtml(site_id, html, reply)
+    // -> String
+    TransformHtml(Uuid, String, oneshot::Sender<String>),
+
+    // RenderShortcode(site_id, name, args, reply)
+    // -> Option<String>
+    RenderShortcode(Uuid, String, String, oneshot::Sender<Option<String>>),
+}
+
+struct HostState {
+    limits: StoreLimits,
+    db: Database,
+    // Site of the call in progress, get_page reads from it
+    site_id: Uuid,
+    initializing: bool,
+    shortcodes: Vec<String>,
+}
+
+// What it takes to start a module, again after a trap
+struct Host {
+    engine: Engine,
+    linker: Linker<HostState>,
+    db: Database,
+}
+
+struct WasmModule {
+    name: String,
+    module: Module,
+    // Failed calls since the last one that went through
+    traps: u32,
+    store: Store<HostState>,
+    instance: Instance,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+}
+
+#[derive(Default)]
+pub struct WasmPlugins {
+    tx: OnceLock<mpsc::Sender<WasmCommand>>,
+}
+
+#[async_trait]
+impl Plugin for WasmPlugins {
+    fn name(&self) -> &str {
+        "wasm"
+    }
+
+    fn on_startup(
+        &self,
+        db: &Database,
+        tracker: &TaskTracker,
+        cancel_token: &CancellationToken,
+    ) -> Result<()> {
+        let dir = PathBuf::from(env::var("PLUGIN_DIR").unwrap_or_else(|_| String::from("plugins")));
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                println::warn(format!(
+                    "No WASM plugins loaded, can't read {}: {}",
+                    dir.display(),
+                    err
+                ));
+                return Ok(());
+            }
+        };
+
+        let host = Host::new(db.clone()).map_err(|err| eyre!("{:#}", err))?;
+
+        let mut modules = Vec::new();
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "wasm" || extension == "wat")
+            })
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            let name = path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+
+            match Module::from_file(&host.engine, &path) {
+                Ok(module) => modules.push((name, module)),
+                Err(err) => println::error(format!("Failed to compile {}: {:#}", name, err)),
+            }
+        }
+
+        let (tx, rx) = mpsc::channel::<WasmCommand>(32);
+        let _ = self.tx.set(tx);
+
+        let cancel_token = cancel_token.clone();
+        tracker.spawn(async move {
+            let mut loaded = Vec::new();
+            for (name, module) in modules {
+                match host.instantiate(module, name.clone()).await {
+                    Ok(module) => {
+                        println::info(format!(
+                            "Loaded WASM plugin {} with shortcodes {:?}",
+                            module.name,
+                            module.store.data().shortcodes
+                        ));
+                        loaded.push(module);
+                    }
+                    Err(err) => println::error(format!("Failed to load {}: {:#}", name, err)),
+                }
+            }
+
+            run(host, loaded, rx, cancel_token).await;
+        });
+
+        Ok(())
+    }
+
+    async fn render_shortcode(&self, site_id: Uuid, name: &str, args: &str) -> Option<String> {
+        let tx = self.tx.get()?;
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let command =
+            WasmCommand::RenderShortcode(site_id, name.to_string(), args.to_string(), reply_tx);
+
+        tx.send(command).await.ok()?;
+        reply_rx.await.ok()?
+    }
+
+    async fn after_render(&self, page: &schema::Page, html: &mut String) {
+        let tx = match self.tx.get() {
+            Some(tx) => tx,
+            None => return,
+        };
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let command = WasmCommand::TransformHtml(page.site_id, html.clone(), reply_tx);
+
+        if tx.send(command).await.is_ok() {
+            if let Ok(transformed) = reply_rx.await {
+                *html = transformed;
+            }
+        }
+    }
+}
+
+fn host_api(engine: &Engine) -> wasmtime::Result<Linker<HostState>> {
+    let mut linker = Linker::new(engine);
+
+    linker.func_wrap(
+        NAMESPACE,
+        "log",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+            let memory = caller_memory(&mut caller)?;
+            let msg = read_string(&mut caller, memory, ptr, len)?;
+            println::info(format!("[wasm] {}", msg));
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        NAMESPACE,
+        "register_shortcode",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+            if !caller.data().initializing {
+                return Err(wasmtime::Error::msg(
+                    "register_shortcode is only allowed during init",
+                ));
+            }
+
+            let memory = caller_memory(&mut caller)?;
+            let name = read_string(&mut caller, memory, ptr, len)?;
+            caller.data_mut().shortcodes.push(name);
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap_async(
+        NAMESPACE,
+        "get_page",
+        |mut caller: Caller<'_, HostState>, (ptr, len): (i32, i32)| {
+            Box::new(async move {
+                let memory = caller_memory(&mut caller)?;
+                let path = read_string(&mut caller, memory, ptr, len)?;
+
+                let site_id = caller.data().site_id;
+                let page = match caller.data().db.get_page(site_id, path, false).await {
+                    Ok(page) if page.published => page,
+                    _ => return Ok(0i64),
+                };
+
+                let alloc = match caller
+                    .get_export("alloc")
+                    .and_then(|alloc| alloc.into_func())
+                {
+                    Some(alloc) => alloc.typed::<i32, i32>(&caller)?,
+                    None => return Err(wasmtime::Error::msg("missing export alloc")),
+                };
+
+                let json = serde_json::to_vec(&page)?;
+                let (ptr, len) = write_bytes(&mut caller, memory, alloc, &json).await?;
+                Ok(pack(ptr, len))
+            })
+        },
+    )?;
+
+    Ok(linker)
+}
+
+impl Host {
+    fn new(db: Database) -> wasmtime::Result<Host> {
+        let mut config = Config::new();
+        config.async_support(true).consume_fuel(true);
+        let engine = Engine::new(&config)?;
+        let linker = host_api(&engine)?;
+
+        Ok(Host { engine, linker, db })
+    }
+
+    async fn instantiate(&self, module: Module, name: String) -> wasmtime::Result<WasmModule> {
+        let state = HostState {
+            limits: StoreLimitsBuilder::new()
+                .memory_size(MEMORY_LIMIT)
+                .instances(1)
+                .build(),
+            db: self.db.clone(),
+            site_id: Uuid::nil(),
+            initializing: true,
+            shortcodes: Vec::new(),
+        };
+
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(FUEL_PER_CALL)?;
+
+        let instance = self.linker.instantiate_async(&mut store, &module).await?;
+        let memory = match instance.get_memory(&mut store, "memory") {
+            Some(memory) => memory,
+            None => return Err(wasmtime::Error::msg("missing export memory")),
+        };
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+
+        if let Ok(init) = instance.get_typed_func::<(), ()>(&mut store, "init") {
+            init.call_async(&mut store, ()).await?;
+        }
+        store.data_mut().initializing = false;
+
+        Ok(WasmModule {
+            name,
+            module,
+            traps: 0,
+            store,
+            instance,
+            memory,
+            alloc,
+        })
+    }
+
+    // After a failed call, swaps the module for a fresh instance or unloads it
+    async fn restart(&self, modules: &mut Vec<WasmModule>, index: usize) {
+        let traps = modules[index].traps + 1;
+        let name = modules[index].name.clone();
+        if traps >= MAX_TRAPS {
+            modules.remove(index);
+            println::error(format!(
+                "Unloaded WASM plugin {} after {} failed calls in a row",
+                name, traps
+            ));
+            return;
+        }
+
+        match self.instantiate(modules[index].module.clone(), name).await {
+            Ok(mut module) => {
+                module.traps = traps;
+                modules[index] = module;
+            }
+            Err(err) => {
+                let module = modules.remove(index);
+                println::error(format!(
+                    "Unloaded WASM plugin {}, restarting it failed: {:#}",
+                    module.name, err
+                ));
+            }
+        }
+    }
+}
+
+// Owns every module, so calls into one module never overlap
+async fn run(
+    host: Host,
+    mut modules: Vec<WasmModule>,
+    mut rx: mpsc::Receiver<WasmCommand>,
+    cancel_token: CancellationToken,
+) {
+    loop {
+        let command = tokio::select! {
+            command = rx.recv() => match command {
+                Some(command) => command,
+                None => break,
+            },
+            _ = cancel_token.cancelled() => {
+                println::error("WASM Host Cancellation Token Received...");
+                break;
+            }
+        };
+
+        match command {
+            WasmCommand::TransformHtml(site_id, mut html, reply) => {
+                let mut failed = Vec::new();
+                for (index, module) in modules.iter_mut().enumerate() {
+                    match module.transform_html(site_id, &html).await {
+                        Ok(transformed) => {
+                            module.traps = 0;
+                            if let Some(transformed) = transformed {
+                                html = transformed;
+                            }
+                        }
+                        Err(err) => {
+                            println::error(format!(
+                                "WASM plugin {} failed in transform_html: {:#}",
+                                module.name, err
+                            ));
+                            failed.push(index);
+                        }
+                    }
+                }
+
+                // Restarting waits until the page has what it needs
+                let _ = reply.send(html);
+                for index in failed.into_iter().rev() {
+                    host.restart(&mut modules, index).await;
+                }
+            }
+            WasmCommand::RenderShortcode(site_id, name, args, reply) => {
+                // The first module to register a name owns it
+                let index = modules
+                    .iter()
+                    .position(|module| module.store.data().shortcodes.contains(&name));
+                let Some(index) = index else {
+                    let _ = reply.send(None);
+                    continue;
+                };
+
+                let module = &mut modules[index];
+                match module.render_shortcode(site_id, &name, &args).await {
+                    Ok(html) => {
+                        module.traps = 0;
+                        let _ = reply.send(html);
+                    }
+                    Err(err) => {
+                        println::error(format!(
+                            "WASM plugin {} failed to render [[{}]]: {:#}",
+                            module.name, name, err
+                        ));
+                        let _ = reply.send(None);
+                        host.restart(&mut modules, index).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl WasmModule {
+    async fn transform_html(
+        &mut self,
+        site_id: Uuid,
+        html: &str,
+    ) -> wasmtime::Result<Option<String>> {
+        let func = match self
+            .instance
+            .get_typed_func::<(i32, i32), i64>(&mut self.store, "transform_html")
+        {
+            Ok(func) => func,
+            Err(_) => return Ok(None),
+        };
+
+        self.prepare_call(site_id)?;
+        let (ptr, len) = write_bytes(
+            &mut self.store,
+            self.memory,
+            self.alloc.clone(),
+            html.as_bytes(),
+        )
+        .await?;
+        let result = func.call_async(&mut self.store, (ptr, len)).await?;
+        self.read_result(result)
+    }
+
+    async fn render_shortcode(
+        &mut self,
+        site_id: Uuid,
+        name: &str,
+        args: &str,
+    ) -> wasmtime::Result<Option<String>> {
+        let func = self
+            .instance
+            .get_typed_func::<(i32, i32, i32, i32), i64>(&mut self.store, "render_shortcode")?;
+
+        self.prepare_call(site_id)?;
+        let (name_ptr, name_len) = write_bytes(
+            &mut self.store,
+            self.memory,
+            self.alloc.clone(),
+            name.as_bytes(),
+        )
+        .await?;
+        let (args_ptr, args_len) = write_bytes(
+            &mut self.store,
+            self.memory,
+            self.alloc.clone(),
+            args.as_bytes(),
+        )
+        .await?;
+        let result = func
+            .call_async(&mut self.store, (name_ptr, name_len, args_ptr, args_len))
+            .await?;
+        self.read_result(result)
+    }
+
+    // Every call starts with a full tank so one heavy page can't starve the next
+    fn prepare_call(&mut self, site_id: Uuid) -> wasmtime::Result<()> {
+        self.store.data_mut().site_id = site_id;
+        self.store.set_fuel(FUEL_PER_CALL)
+    }
+
+    fn read_result(&mut self, result: i64) -> wasmtime::Result<Option<String>> {
+        if result == 0 {
+            return Ok(None);
+        }
+
+        let (ptr, len) = unpack(result);
+        read_string(&mut self.store, self.memory, ptr, len).map(Some)
+    }
+}
+
+fn caller_memory(caller: &mut Caller<'_, HostState>) -> wasmtime::Result<Memory> {
+    match caller
+        .get_export("memory")
+        .and_then(|memory| memory.into_memory())
+    {
+        Some(memory) => Ok(memory),
+        None => Err(wasmtime::Error::msg("missing export memory")),
+    }
+}
+
+fn read_string(
+    store: impl AsContext,
+    memory: Memory,
+    ptr: i32,
+    len: i32,
+) -> wasmtime::Result<String> {
+    // The guest picks ptr and len, they are checked against its memory
+    // before anything is copied so the host never allocates more than it
+    let start = usize::try_from(ptr)?;
+    let len = usize::try_from(len)?;
+    if len > MEMORY_LIMIT {
+        return Err(wasmtime::Error::msg("string longer than the memory limit"));
+    }
+
+    let bytes = start
+        .checked_add(len)
+        .and_then(|end| memory.data(store.as_context()).get(start..end))
+        .ok_or_else(|| wasmtime::Error::msg("string out of bounds"))?;
+    Ok(std::str::from_utf8(bytes)?.to_string())
+}
+
+async fn write_bytes(
+    mut store: impl AsContextMut<Data = HostState>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    bytes: &[u8],
+) -> wasmtime::Result<(i32, i32)> {
+    let len = i32::try_from(bytes.len())?;
+    let ptr = alloc.call_async(&mut store, len).await?;
+    memory.write(&mut store, usize::try_from(ptr)?, bytes)?;
+    Ok((ptr, len))
+}
+
+fn pack(ptr: i32, len: i32) -> i64 {
+    ((ptr as u32 as i64) << 32) | len as u32 as i64
+}
+
+fn unpack(value: i64) -> (i32, i32) {
+    ((value >> 32) as u32 as i32, value as u32 as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Registers [[spin]], which never returns, [[grow]], which tries to grow
+    // memory past the limit, and [[hello]]. A bump allocator starts at 1024.
+    const PLUGIN: &str = r#"
+        (module
+            (import "magnetite" "register_shortcode" (func $register (param i32 i32)))
+            (memory (export "memory") 1)
+            (global $heap (mut i32) (i32.const 1024))
+            ;; spin at 0, grow at 4, hello at 8, denied at 13
+            (data (i32.const 0) "spingrowhellodenied")
+
+            (func (export "alloc") (param $len i32) (result i32)
+                (local $ptr i32)
+                (local.set $ptr (global.get $heap))
+                (global.set $heap (i32.add (global.get $heap) (local.get $len)))
+                (local.get $ptr))
+
+            (func (export "init")
+                (call $register (i32.const 0) (i32.const 4))
+                (call $register (i32.const 4) (i32.const 4))
+                (call $register (i32.const 8) (i32.const 5)))
+
+            (func (export "render_shortcode")
+                (param $name i32) (param $name_len i32) (param $args i32) (param $args_len i32)
+                (result i64)
+                ;; s
+                (if (i32.eq (i32.load8_u (local.get $name)) (i32.const 115))
+                    (then (loop $forever (br $forever))))
+                ;; g, 300 pages are more than 16 MiB
+                (if (i32.eq (i32.load8_u (local.get $name)) (i32.const 103))
+                    (then
+                        (if (i32.eq (memory.grow (i32.const 300)) (i32.const -1))
+                            ;; "denied"
+                            (then (return (i64.const 0xd00000006))))
+                        (return (i64.const 0))))
+                ;; "hello"
+                (i64.const 0x800000005))
+        )
+    "#;
+
+    async fn load(wat: &str) -> (Host, wasmtime::Result<WasmModule>) {
+        let (db, _rx) = Database::stub();
+        let host = Host::new(db).unwrap();
+        let module = Module::new(&host.engine, wat).unwrap();
+        let loaded = host.instantiate(module, String::from("test")).await;
+        (host, loaded)
+    }
+
+    #[tokio::test]
+    async fn registers_and_renders_shortcodes() {
+        let (_host, module) = load(PLUGIN).await;
+        let mut module = module.unwrap();
+
+        assert_eq!(module.store.data().shortcodes, ["spin", "grow", "hello"]);
+        assert!(!module.store.data().initializing);
+        assert_eq!(
+            module
+                .render_shortcode(Uuid::nil(), "hello", "")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("hello")
+        );
+    }
+
+    #[tokio::test]
+    async fn restarts_modules_out_of_fuel_and_unloads_them_eventually() {
+        let (host, module) = load(PLUGIN).await;
+        let mut modules = vec![module.unwrap()];
+
+        for traps in 1..MAX_TRAPS {
+            let err = modules[0]
+                .render_shortcode(Uuid::nil(), "spin", "")
+                .await
+                .unwrap_err();
+            assert_eq!(err.downcast_ref(), Some(&wasmtime::Trap::OutOfFuel));
+
+            host.restart(&mut modules, 0).await;
+            assert_eq!(modules[0].traps, traps);
+            // A fresh instance, with what init left of a full tank
+            assert!(modules[0].store.get_fuel().unwrap() > FUEL_PER_CALL / 2);
+        }
+
+        assert!(modules[0]
+            .render_shortcode(Uuid::nil(), "hello", "")
+            .await
+            .unwrap()
+            .is_some());
+        host.restart(&mut modules, 0).await;
+        assert!(modules.is_empty());
+    }
+
+    #[tokio::test]
+    async fn keeps_memory_within_the_limit() {
+        let (_host, module) = load(PLUGIN).await;
+        let mut module = module.unwrap();
+        assert_eq!(
+            module
+                .render_shortcode(Uuid::nil(), "grow", "")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("denied")
+        );
+
+        let (_host, module) = load(r#"(module (memory (export "memory") 300))"#).await;
+        assert!(module.is_err());
+    }
+
+    #[tokio::test]
+    async fn reads_strings_only_from_guest_memory() {
+        let (_host, module) = load(PLUGIN).await;
+        let module = module.unwrap();
+        let (store, memory) = (&module.store, module.memory);
+        let size = i32::try_from(memory.data_size(store)).unwrap();
+
+        assert_eq!(read_string(store, memory, 8, 5).unwrap(), "hello");
+        assert_eq!(read_string(store, memory, size, 0).unwrap(), "");
+        assert!(read_string(store, memory, size - 2, 3).is_err());
+        assert!(read_string(store, memory, size, 1).is_err());
+        assert!(read_string(store, memory, -1, 1).is_err());
+        assert!(read_string(store, memory, 0, -1).is_err());
+        assert!(read_string(store, memory, 0, i32::MAX).is_err());
+    }
+
+    #[test]
+    fn packs_pointers_and_lengths() {
+        assert_eq!(pack(8, 5), 0x800000005);
+        assert_eq!(unpack(pack(8, 5)), (8, 5));
+        assert_eq!(unpack(pack(i32::MAX, i32::MAX)), (i32::MAX, i32::MAX));
+    }
+}
@@ -0,0 +1,96 @@
+/*
+ * transfer.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * Moving content in and out of Magnetite. The admin API and the command
+ * line both go through here, the database commands do the actual reads
+ * and writes. Other formats are converted into a SiteExport first so they
+ * are imported with the same conflict handling as archives.
+ */
+
+use crate::{
+    database::{schema, Database},
+    web::escape,
+};
+use color_eyre::Result;
+use uuid::Uuid;
+
+pub mod archive;
+pub mod markdown;
+pub mod wxr;
+
+// A foreign format as far as it maps onto Magnetite, and what didn't
+pub struct Converted {
+    pub export: schema::SiteExport,
+    pub unmapped: Vec<schema::ImportItem>,
+}
+
+impl Converted {
+    fn new(users: Vec<schema::AdminUser>) -> Converted {
+        Converted {
+            export: schema::SiteExport {
+                site: None,
+                users,
+                content_types: Vec::new(),
+                pages: Vec::new(),
+                translations: Vec::new(),
+                menus: Vec::new(),
+                blocks: Vec::new(),
+                redirects: Vec::new(),
+                workflow_transitions: Vec::new(),
+            },
+            unmapped: Vec::new(),
+        }
+    }
+
+    fn unmapped<S: Into<String>>(&mut self, kind: &'static str, key: &str, detail: S) {
+        self.unmapped.push(schema::ImportItem {
+            kind,
+            key: key.to_string(),
+            outcome: "unmapped",
+            detail: Some(detail.into()),
+        });
+    }
+
+    // Two sources for the same path can't both be imported, the first one wins
+    fn push_page(&mut self, kind: &'static str, key: &str, page: schema::Page) {
+        if self
+            .export
+            .pages
+            .iter()
+            .any(|other| other.path == page.path)
+        {
+            let detail = format!("{} is already taken by another {}", page.path, kind);
+            self.unmapped(kind, key, detail);
+        } else {
+            self.export.pages.push(page);
+        }
+    }
+}
+
+pub async fn import(
+    db: &Database,
+    site_id: Uuid,
+    converted: Converted,
+    options: schema::ImportOptions,
+) -> Result<schema::ImportReport> {
+    let mut report = db.import_site(site_id, converted.export, options).await?;
+    report.items.extend(converted.unmapped);
+
+    Ok(report)
+}
+
+// The head lines Page::title and Page::summary read back
+fn metadata(title: &str, description: Option<&str>) -> Vec<String> {
+    let mut metadata = vec![format!("<title>{}</title>", escape(title.trim()))];
+    if let Some(description) = description.map(str::trim).filter(|text| !text.is_empty()) {
+        metadata.push(format!(
+            "<meta name=\"description\" content=\"{}\">",
+            escape(description)
+        ));
+    }
+
+    metadata
+}
@@ -0,0 +1,217 @@
+/*
+ * transfer/archive.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * The site archive is a plain tar of JSON files, one per kind of content,
+ * next to a manifest.json naming the format and its version. Archives from
+ * newer versions are refused rather than half understood.
+ */
+
+use crate::database::schema;
+use chrono::{NaiveDateTime, Utc};
+use color_eyre::{eyre::eyre, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{collections::HashMap, io::Read};
+
+const FORMAT: &str = "magnetite-site";
+// Bumped whenever a file changes in a way older importers can't read
+pub const VERSION: u32 = 1;
+const MANIFEST: &str = "manifest.json";
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    format: String,
+    version: u32,
+    exported_at: NaiveDateTime,
+    host: String,
+}
+
+pub fn pack(export: &schema::SiteExport) -> Result<Vec<u8>> {
+    let now = Utc::now();
+    let manifest = Manifest {
+        format: String::from(FORMAT),
+        version: VERSION,
+        exported_at: now.naive_utc(),
+        host: export
+            .site
+            .as_ref()
+            .map(|site| site.host.clone())
+            .unwrap_or_default(),
+    };
+
+    let files = [
+        (MANIFEST, serde_json::to_vec_pretty(&manifest)?),
+        ("site.json", serde_json::to_vec_pretty(&export.site)?),
+        ("users.json", serde_json::to_vec_pretty(&export.users)?),
+        (
+            "content_types.json",
+            serde_json::to_vec_pretty(&export.content_types)?,
+        ),
+        ("pages.json", serde_json::to_vec_pretty(&export.pages)?),
+        (
+            "translations.json",
+            serde_json::to_vec_pretty(&export.translations)?,
+        ),
+        ("menus.json", serde_json::to_vec_pretty(&export.menus)?),
+        ("blocks.json", serde_json::to_vec_pretty(&export.blocks)?),
+        (
+            "redirects.json",
+            serde_json::to_vec_pretty(&export.redirects)?,
+        ),
+        (
+            "workflow_transitions.json",
+            serde_json::to_vec_pretty(&export.workflow_transitions)?,
+        ),
+    ];
+
+    let mut builder = tar::Builder::new(Vec::new());
+    for (name, contents) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(now.timestamp().max(0) as u64);
+        header.set_cksum();
+        builder.append_data(&mut header, name, contents.as_slice())?;
+    }
+
+    Ok(builder.into_inner()?)
+}
+
+pub fn unpack(bytes: &[u8]) -> Result<schema::SiteExport> {
+    let mut files: HashMap<String, Vec<u8>> = HashMap::new();
+    let mut archive = tar::Archive::new(bytes);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+        files.insert(name, contents);
+    }
+
+    let manifest: Manifest = read(&files, MANIFEST)?;
+    if manifest.format != FORMAT {
+        return Err(eyre!("Not a Magnetite site archive"));
+    }
+    if manifest.version > VERSION {
+        return Err(eyre!(
+            "Archive version {} is newer than the supported version {}",
+            manifest.version,
+            VERSION
+        ));
+    }
+
+    Ok(schema::SiteExport {
+        site: read(&files, "site.json")?,
+        users: read(&files, "users.json")?,
+        content_types: read(&files, "content_types.json")?,
+        pages: read(&files, "pages.json")?,
+        translations: read(&files, "translations.json")?,
+        menus: read(&files, "menus.json")?,
+        blocks: read(&files, "blocks.json")?,
+        redirects: read(&files, "redirects.json")?,
+        workflow_transitions: read(&files, "workflow_transitions.json")?,
+    })
+}
+
+fn read<T: DeserializeOwned>(files: &HashMap<String, Vec<u8>>, name: &str) -> Result<T> {
+    let contents = files
+        .get(name)
+        .ok_or_else(|| eyre!("Archive is missing {}", name))?;
+
+    serde_json::from_slice(contents).map_err(|err| eyre!("Invalid {}: {}", name, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    fn export() -> schema::SiteExport {
+        let site_id = Uuid::new_v4();
+        let user_id = Uuid::new_v4();
+        let now = Utc::now().naive_utc();
+
+        schema::SiteExport {
+            site: None,
+            users: Vec::new(),
+            content_types: Vec::new(),
+            pages: vec![schema::Page {
+                site_id,
+                path: String::from("/about"),
+                created_at: now,
+                created_by: user_id,
+                modified_at: now,
+                modified_by: user_id,
+                published: true,
+                metadata: vec![String::from("title=About")],
+                body: String::from("<p>Hi [[block cta]]</p>"),
+                sort_order: 2,
+                content_type: None,
+                fields: json!({}),
+                version: 3,
+            }],
+            translations: Vec::new(),
+            menus: Vec::new(),
+            blocks: vec![schema::Block {
+                site_id,
+                name: String::from("cta"),
+                body: String::from("Buy"),
+                modified_at: now,
+                modified_by: user_id,
+            }],
+            redirects: Vec::new(),
+            workflow_transitions: Vec::new(),
+        }
+    }
+
+    fn tar(files: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, contents.as_slice())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn unpacks_what_it_packs() {
+        let export = export();
+        let unpacked = unpack(&pack(&export).unwrap()).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&unpacked).unwrap(),
+            serde_json::to_value(&export).unwrap()
+        );
+    }
+
+    #[test]
+    fn refuses_foreign_and_newer_archives() {
+        let manifest = |format: &str, version: u32| {
+            serde_json::to_vec(&json!({
+                "format": format,
+                "version": version,
+                "exported_at": "2024-01-01T00:00:00",
+                "host": "example.com",
+            }))
+            .unwrap()
+        };
+
+        let err = unpack(&tar(&[(MANIFEST, manifest("other", VERSION))])).unwrap_err();
+        assert_eq!(err.to_string(), "Not a Magnetite site archive");
+
+        let err = unpack(&tar(&[(MANIFEST, manifest(FORMAT, VERSION + 1))])).unwrap_err();
+        assert!(err.to_string().contains("is newer than"));
+
+        let err = unpack(&tar(&[(MANIFEST, manifest(FORMAT, VERSION))])).unwrap_err();
+        assert_eq!(err.to_string(), "Archive is missing site.json");
+
+        assert!(unpack(b"not a tar").is_err());
+    }
+}
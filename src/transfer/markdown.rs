@@ -0,0 +1,340 @@
+/*
+ * transfer/markdown.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * Reads a directory of Markdown files with YAML front matter, the layout
+ * static site generators like Hugo and Jekyll use. news/post.md becomes
+ * /news/post and index.md stands for its directory. Front matter keys
+ * without a place on a page and files that aren't Markdown are reported.
+ */
+
+use super::{metadata, Converted};
+use crate::database::schema;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use color_eyre::{eyre::eyre, Result};
+use pulldown_cmark::{html, Options, Parser};
+use std::{collections::BTreeMap, fs, path::Path};
+use uuid::Uuid;
+use walkdir::WalkDir;
+use yaml_rust2::{Yaml, YamlLoader};
+
+const EXTENSIONS: [&str; 2] = ["md", "markdown"];
+const INDEX_NAMES: [&str; 2] = ["index", "_index"];
+// The GitHub flavored extensions. Wikilinks and smart punctuation are left out,
+// they would turn shortcodes like [[video title="Clip"]] into links and curly quotes.
+const MARKDOWN_OPTIONS: Options = Options::ENABLE_TABLES
+    .union(Options::ENABLE_FOOTNOTES)
+    .union(Options::ENABLE_STRIKETHROUGH)
+    .union(Options::ENABLE_TASKLISTS);
+
+// Files without an author key, or with an unknown one, fall back to author
+pub fn convert(dir: &Path, site_id: Uuid, author: &schema::AdminUser) -> Result<Converted> {
+    if !dir.is_dir() {
+        return Err(eyre!("{} is not a directory", dir.display()));
+    }
+
+    // Hidden files and directories like .git aren't content
+    let entries = WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.')
+        });
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            files.push(entry.into_path());
+        }
+    }
+
+    // Authors are named in front matter, they are matched up by username on import
+    let mut users = vec![author.clone()];
+    let mut converted = Converted::new(Vec::new());
+    for file in files {
+        let relative = file.strip_prefix(dir)?.to_path_buf();
+        let key = relative.to_string_lossy().into_owned();
+
+        let markdown = file
+            .extension()
+            .is_some_and(|extension| EXTENSIONS.contains(&extension.to_string_lossy().as_ref()));
+        if !markdown {
+            converted.unmapped("file", &key, "Only Markdown files are imported");
+            continue;
+        }
+
+        let contents = match fs::read_to_string(&file) {
+            Ok(contents) => contents,
+            Err(err) => {
+                converted.unmapped("file", &key, format!("Unreadable: {}", err));
+                continue;
+            }
+        };
+        let (mut front_matter, markdown) = match split_front_matter(&contents) {
+            Ok(split) => split,
+            Err(err) => {
+                converted.unmapped("file", &key, format!("Invalid front matter: {}", err));
+                continue;
+            }
+        };
+
+        let modified = fs::metadata(&file)?.modified()?;
+        let modified = DateTime::<chrono::Utc>::from(modified).naive_utc();
+
+        let mut take = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| front_matter.remove(*name))
+                .filter(|value| !value.is_null())
+        };
+        let title = take(&["title"]);
+        let description = take(&["description", "summary"]);
+        let date = take(&["date", "created"]);
+        let lastmod = take(&["lastmod", "updated", "modified"]);
+        let username = take(&["author"]);
+        let draft = take(&["draft"]);
+        let published = take(&["published"]);
+        let path = take(&["path", "url", "permalink"]);
+        let slug = take(&["slug"]);
+        let weight = take(&["weight", "sort_order"]);
+
+        let mut unmapped: Vec<String> = front_matter.into_keys().collect();
+
+        let path = match path.as_ref().and_then(yaml_text) {
+            Some(path) if path.starts_with('/') => clean_path(&path),
+            _ => file_path(&relative, slug.as_ref().and_then(yaml_text)),
+        };
+        let title = match title.as_ref().and_then(yaml_text) {
+            Some(title) => title,
+            None => path.rsplit('/').next().unwrap_or_default().to_string(),
+        };
+
+        let created_at = match date.as_ref().map(parse_date) {
+            Some(Some(date)) => date,
+            Some(None) => {
+                unmapped.push(String::from("date"));
+                modified
+            }
+            None => modified,
+        };
+        let modified_at = match lastmod.as_ref().map(parse_date) {
+            Some(Some(date)) => date,
+            Some(None) => {
+                unmapped.push(String::from("lastmod"));
+                created_at.max(modified)
+            }
+            None => created_at.max(modified),
+        };
+
+        let author_id = match username.as_ref().and_then(yaml_text) {
+            Some(username) => match users.iter().find(|user| user.username == username) {
+                Some(user) => user.id,
+                None => {
+                    // Created disabled on import unless someone has the username already
+                    let user = schema::AdminUser {
+                        id: Uuid::now_v7(),
+                        username,
+                        enabled: false,
+                        email: String::new(),
+                        superuser: false,
+                    };
+                    let id = user.id;
+                    users.push(user);
+                    id
+                }
+            },
+            None => author.id,
+        };
+
+        let published = match (published, draft) {
+            (Some(Yaml::Boolean(published)), _) => published,
+            (_, Some(Yaml::Boolean(draft))) => !draft,
+            _ => true,
+        };
+
+        let mut body = String::new();
+        html::push_html(&mut body, Parser::new_ext(markdown, MARKDOWN_OPTIONS));
+
+        let page = schema::Page {
+            site_id,
+            path: path.clone(),
+            created_at,
+            created_by: author_id,
+            modified_at,
+            modified_by: author_id,
+            published,
+            metadata: metadata(&title, description.as_ref().and_then(yaml_text).as_deref()),
+            body,
+            sort_order: weight.as_ref().and_then(Yaml::as_i64).unwrap_or(0) as i32,
+            content_type: None,
+            fields: serde_json::json!({}),
+            version: 0,
+        };
+
+        if !unmapped.is_empty() {
+            let detail = format!("Front matter not imported: {}", unmapped.join(", "));
+            converted.unmapped("front_matter", &key, detail);
+        }
+        converted.push_page("file", &key, page);
+    }
+
+    converted.export.users = users;
+    Ok(converted)
+}
+
+// Front matter sits between two --- lines at the very start, files without it have none
+fn split_front_matter(contents: &str) -> Result<(BTreeMap<String, Yaml>, &str)> {
+    let contents = contents.trim_start_matches('\u{feff}');
+    let rest = match contents
+        .strip_prefix("---\n")
+        .or_else(|| contents.strip_prefix("---\r\n"))
+    {
+        Some(rest) => rest,
+        None => return Ok((BTreeMap::new(), contents)),
+    };
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if matches!(line.trim_end(), "---" | "...") {
+            let yaml = &rest[..offset];
+            let body = &rest[offset + line.len()..];
+            let mapping = match YamlLoader::load_from_str(yaml)?.into_iter().next() {
+                Some(Yaml::Hash(mapping)) => mapping,
+                None | Some(Yaml::Null) => Default::default(),
+                Some(_) => return Err(eyre!("Not a mapping of keys to values")),
+            };
+
+            let front_matter = mapping
+                .into_iter()
+                .filter_map(|(key, value)| Some((yaml_text(&key)?, value)))
+                .collect();
+            return Ok((front_matter, body));
+        }
+        offset += line.len();
+    }
+
+    Err(eyre!("No closing ---"))
+}
+
+fn yaml_text(value: &Yaml) -> Option<String> {
+    match value {
+        Yaml::String(text) | Yaml::Real(text) => Some(text.clone()),
+        Yaml::Integer(number) => Some(number.to_string()),
+        Yaml::Boolean(boolean) => Some(boolean.to_string()),
+        _ => None,
+    }
+}
+
+// Dates as front matter usually has them, times without a zone are taken as UTC
+fn parse_date(value: &Yaml) -> Option<NaiveDateTime> {
+    let text = yaml_text(value)?;
+    let text = text.trim();
+
+    DateTime::parse_from_rfc3339(text)
+        .map(|date| date.naive_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S"))
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S"))
+        .or_else(|_| {
+            DateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S %z").map(|date| date.naive_utc())
+        })
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .ok()?
+                .and_hms_opt(0, 0, 0)
+        })
+}
+
+// news/post.md -> /news/post, news/index.md -> /news, a slug replaces the file name
+fn file_path(relative: &Path, slug: Option<String>) -> String {
+    let mut segments: Vec<String> = relative
+        .with_extension("")
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect();
+
+    if segments
+        .last()
+        .is_some_and(|name| INDEX_NAMES.contains(&name.as_str()))
+    {
+        segments.pop();
+    } else if let Some(slug) = slug {
+        segments.pop();
+        segments.push(slug);
+    }
+
+    clean_path(&format!("/{}", segments.join("/")))
+}
+
+fn clean_path(path: &str) -> String {
+    match path.trim_end_matches('/') {
+        "" => String::from("/"),
+        path => path.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_front_matter() {
+        let (front_matter, body) =
+            split_front_matter("\u{feff}---\r\ntitle: Hello\ndraft: true\n---\n# Hi\n").unwrap();
+        assert_eq!(front_matter["title"], Yaml::String(String::from("Hello")));
+        assert_eq!(front_matter["draft"], Yaml::Boolean(true));
+        assert_eq!(body, "# Hi\n");
+
+        let (front_matter, body) = split_front_matter("---\n...\nBody").unwrap();
+        assert!(front_matter.is_empty());
+        assert_eq!(body, "Body");
+
+        let (front_matter, body) = split_front_matter("# No front matter\n---\n").unwrap();
+        assert!(front_matter.is_empty());
+        assert_eq!(body, "# No front matter\n---\n");
+
+        assert!(split_front_matter("---\ntitle: Hello\n").is_err());
+        assert!(split_front_matter("---\n- a list\n---\n").is_err());
+    }
+
+    #[test]
+    fn maps_files_to_paths() {
+        assert_eq!(file_path(Path::new("news/post.md"), None), "/news/post");
+        assert_eq!(file_path(Path::new("news/index.md"), None), "/news");
+        assert_eq!(file_path(Path::new("news/_index.markdown"), None), "/news");
+        assert_eq!(file_path(Path::new("index.md"), None), "/");
+        assert_eq!(
+            file_path(Path::new("news/post.md"), Some(String::from("hello"))),
+            "/news/hello"
+        );
+        assert_eq!(
+            file_path(Path::new("news/index.md"), Some(String::from("hello"))),
+            "/news"
+        );
+    }
+
+    #[test]
+    fn parses_front_matter_dates() {
+        let date = |text: &str| parse_date(&Yaml::String(text.to_string()));
+        let expected = NaiveDate::from_ymd_opt(2024, 3, 1)
+            .unwrap()
+            .and_hms_opt(12, 30, 0);
+
+        assert_eq!(date("2024-03-01T12:30:00Z"), expected);
+        assert_eq!(date("2024-03-01T14:30:00+02:00"), expected);
+        assert_eq!(date("2024-03-01T12:30:00"), expected);
+        assert_eq!(date("2024-03-01 12:30:00"), expected);
+        assert_eq!(date("2024-03-01 13:30:00 +0100"), expected);
+        assert_eq!(
+            date(" 2024-03-01 "),
+            NaiveDate::from_ymd_opt(2024, 3, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+        );
+        assert_eq!(date("March 1st"), None);
+        assert_eq!(parse_date(&Yaml::Array(Vec::new())), None);
+    }
+}
@@ -0,0 +1,444 @@
+/*
+ * transfer/wxr.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * Reads a WordPress export (WXR, Tools > Export in WordPress). Posts and
+ * pages become pages at the path of their permalink, the rest of the file
+ * is reported: attachments, menus, comments, categories and tags, and
+ * shortcodes, which Magnetite would show as text.
+ */
+
+use super::{metadata, Converted};
+use crate::database::schema;
+use chrono::{NaiveDateTime, Utc};
+use color_eyre::{eyre::eyre, Result};
+use quick_xml::{events::Event, Reader};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+// Tags that already start a block, text starting with anything else gets a <p>
+const BLOCK_TAGS: [&str; 14] = [
+    "<p",
+    "<div",
+    "<h1",
+    "<h2",
+    "<h3",
+    "<h4",
+    "<h5",
+    "<h6",
+    "<ul",
+    "<ol",
+    "<blockquote",
+    "<pre",
+    "<table",
+    "<figure",
+];
+
+// The children of an <item> or <wp:author> by tag name
+#[derive(Default)]
+struct Element {
+    fields: HashMap<String, String>,
+    // (domain, name) of every <category>, the domain tells categories from tags
+    categories: Vec<(String, String)>,
+    comments: usize,
+}
+
+impl Element {
+    fn get(&self, name: &str) -> &str {
+        self.fields
+            .get(name)
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+}
+
+// Authors of items without a known <dc:creator> fall back to author
+pub fn convert(xml: &str, site_id: Uuid, author: &schema::AdminUser) -> Result<Converted> {
+    let (authors, items) = parse(xml)?;
+
+    // Logins are what items refer to, the ids are only used inside this import
+    let mut users: Vec<schema::AdminUser> = authors
+        .iter()
+        .filter(|wp_author| !wp_author.get("wp:author_login").is_empty())
+        .map(|wp_author| schema::AdminUser {
+            id: Uuid::now_v7(),
+            username: wp_author.get("wp:author_login").to_string(),
+            enabled: false,
+            email: wp_author.get("wp:author_email").to_string(),
+            superuser: false,
+        })
+        .collect();
+    if !users.iter().any(|user| user.username == author.username) {
+        users.push(author.clone());
+    }
+    let logins: HashMap<String, Uuid> = users
+        .iter()
+        .map(|user| (user.username.clone(), user.id))
+        .collect();
+
+    let mut converted = Converted::new(users);
+    for item in items {
+        let title = item.get("title").to_string();
+        let key = match title.is_empty() {
+            true => format!("#{}", item.get("wp:post_id")),
+            false => title.clone(),
+        };
+
+        match item.get("wp:post_type") {
+            "post" | "page" => {}
+            "attachment" => {
+                let detail = format!(
+                    "Media isn't imported, the file is still at {}",
+                    item.get("wp:attachment_url")
+                );
+                converted.unmapped("attachment", &key, detail);
+                continue;
+            }
+            "nav_menu_item" => {
+                converted.unmapped("menu_item", &key, "Menus have to be rebuilt by hand");
+                continue;
+            }
+            post_type => {
+                let detail = format!("Items of type {} aren't imported", post_type);
+                converted.unmapped("item", &key, detail);
+                continue;
+            }
+        }
+
+        let published = match item.get("wp:status") {
+            "publish" if item.get("wp:post_password").is_empty() => true,
+            "publish" => {
+                let detail = "Password protection isn't supported, imported unpublished";
+                converted.unmapped("page", &key, detail);
+                false
+            }
+            "draft" | "pending" | "private" | "future" => false,
+            status => {
+                let detail = format!("Items with status {} aren't imported", status);
+                converted.unmapped("page", &key, detail);
+                continue;
+            }
+        };
+
+        let path = item_path(&item);
+        let now = Utc::now().naive_utc();
+        let created_at = date(&item, "wp:post_date_gmt", "wp:post_date").unwrap_or(now);
+        let modified_at =
+            date(&item, "wp:post_modified_gmt", "wp:post_modified").unwrap_or(created_at);
+        let author_id = logins
+            .get(item.get("dc:creator"))
+            .copied()
+            .unwrap_or(author.id);
+
+        let excerpt = item.get("excerpt:encoded");
+        let page = schema::Page {
+            site_id,
+            path: path.clone(),
+            created_at,
+            created_by: author_id,
+            modified_at,
+            modified_by: author_id,
+            published,
+            metadata: metadata(&title, Some(excerpt)),
+            body: autop(item.get("content:encoded")),
+            sort_order: item.get("wp:menu_order").parse().unwrap_or(0),
+            content_type: None,
+            fields: serde_json::json!({}),
+            version: 0,
+        };
+
+        let terms: Vec<String> = item
+            .categories
+            .iter()
+            .map(|(domain, name)| match domain.as_str() {
+                "post_tag" => format!("tag {}", name),
+                "category" => format!("category {}", name),
+                domain => format!("{} {}", domain, name),
+            })
+            .collect();
+        if !terms.is_empty() {
+            let detail = format!("Not imported: {}", terms.join(", "));
+            converted.unmapped("taxonomy", &path, detail);
+        }
+        if item.comments > 0 {
+            let detail = format!("Comments aren't imported: {}", item.comments);
+            converted.unmapped("comment", &path, detail);
+        }
+        let shortcodes = shortcodes(&page.body);
+        if !shortcodes.is_empty() {
+            let detail = format!("Left as text: {}", shortcodes.join(", "));
+            converted.unmapped("shortcode", &path, detail);
+        }
+
+        converted.push_page("page", &key, page);
+    }
+
+    Ok(converted)
+}
+
+// The <wp:author> and <item> elements of the channel
+fn parse(xml: &str) -> Result<(Vec<Element>, Vec<Element>)> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+
+    let mut authors = Vec::new();
+    let mut items = Vec::new();
+    let mut current: Option<Element> = None;
+    let mut stack: Vec<String> = Vec::new();
+    let mut text = String::new();
+    let mut domain = String::new();
+    let mut channel = false;
+
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|err| eyre!("Invalid WXR at {}: {}", reader.buffer_position(), err))?;
+
+        match event {
+            Event::Start(start) => {
+                let name = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+                channel |= name == "channel";
+                if name == "item" || name == "wp:author" {
+                    current = Some(Element::default());
+                }
+                if name == "category" {
+                    domain = match start.try_get_attribute("domain")? {
+                        Some(attribute) => attribute.unescape_value()?.into_owned(),
+                        None => String::new(),
+                    };
+                }
+                stack.push(name);
+                text.clear();
+            }
+            // HTML entities outside of CDATA aren't XML ones, they are kept as they are
+            Event::Text(content) => match content.unescape() {
+                Ok(unescaped) => text.push_str(&unescaped),
+                Err(_) => text.push_str(&String::from_utf8_lossy(&content)),
+            },
+            Event::CData(content) => text.push_str(&String::from_utf8_lossy(&content)),
+            Event::End(_) => {
+                let name = stack.pop().unwrap_or_default();
+                let parent = stack.last().map(String::as_str).unwrap_or_default();
+
+                match (parent, name.as_str(), current.as_mut()) {
+                    (_, "item", Some(_)) => items.extend(current.take()),
+                    (_, "wp:author", Some(_)) if parent == "channel" => {
+                        authors.extend(current.take())
+                    }
+                    ("item", "category", Some(element)) => element
+                        .categories
+                        .push((domain.clone(), text.trim().to_string())),
+                    ("item", "wp:comment", Some(element)) => element.comments += 1,
+                    ("item" | "wp:author", _, Some(element)) => {
+                        element.fields.insert(name, text.trim().to_string());
+                    }
+                    _ => {}
+                }
+                text.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    match channel {
+        true => Ok((authors, items)),
+        false => Err(eyre!("Not a WordPress export")),
+    }
+}
+
+// The path of the permalink, plain permalinks like /?p=12 fall back to the slug
+fn item_path(item: &Element) -> String {
+    let link = item.get("link");
+    let path = link
+        .split_once("://")
+        .map(|(_, rest)| rest.find('/').map(|index| &rest[index..]).unwrap_or("/"))
+        .unwrap_or(link);
+
+    if path.starts_with('/') && !path.contains(['?', '#']) {
+        let path = path.trim_end_matches('/');
+        return match path.is_empty() {
+            true => String::from("/"),
+            false => path.to_string(),
+        };
+    }
+
+    match item.get("wp:post_name") {
+        "" => format!("/{}", item.get("wp:post_id")),
+        slug => format!("/{}", slug),
+    }
+}
+
+// Drafts have no GMT date yet, WordPress writes zeros instead
+fn date(item: &Element, gmt: &str, local: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(item.get(gmt), DATE_FORMAT)
+        .or_else(|_| NaiveDateTime::parse_from_str(item.get(local), DATE_FORMAT))
+        .ok()
+}
+
+// WordPress stores classic posts without paragraphs and adds them when showing them
+fn autop(content: &str) -> String {
+    let content = content.replace("\r\n", "\n");
+
+    content
+        .split("\n\n")
+        .map(str::trim)
+        .filter(|paragraph| !paragraph.is_empty())
+        .map(|paragraph| {
+            if paragraph.starts_with("<!--")
+                || BLOCK_TAGS.iter().any(|tag| paragraph.starts_with(tag))
+            {
+                paragraph.to_string()
+            } else {
+                format!("<p>{}</p>", paragraph.replace('\n', "<br>\n"))
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// Names of [shortcode] tags, Magnetite's own [[shortcodes]] are skipped
+fn shortcodes(body: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut rest = body;
+
+    while let Some(index) = rest.find('[') {
+        rest = &rest[index + 1..];
+        if rest.starts_with('[') {
+            rest = &rest[1..];
+            continue;
+        }
+
+        let name: String = rest
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+            .collect();
+        let closed = rest[name.len()..].starts_with([']', ' ']);
+        let word = name.starts_with(|c: char| c.is_ascii_alphabetic());
+        if word && closed && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:content="http://purl.org/rss/1.0/modules/content/"
+    xmlns:excerpt="http://wordpress.org/export/1.2/excerpt/"
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:wp="http://wordpress.org/export/1.2/">
+<channel>
+    <wp:author>
+        <wp:author_login><![CDATA[jane]]></wp:author_login>
+        <wp:author_email><![CDATA[jane@example.com]]></wp:author_email>
+    </wp:author>
+    <item>
+        <title>Hello &amp; welcome</title>
+        <link>https://example.com/2024/03/hello/</link>
+        <dc:creator><![CDATA[jane]]></dc:creator>
+        <content:encoded><![CDATA[First line
+second line
+
+[gallery ids="1,2"]
+
+<h2>Heading</h2>]]></content:encoded>
+        <excerpt:encoded><![CDATA[Short]]></excerpt:encoded>
+        <wp:post_id>12</wp:post_id>
+        <wp:post_date_gmt>2024-03-01 12:30:00</wp:post_date_gmt>
+        <wp:post_modified_gmt>2024-03-02 08:00:00</wp:post_modified_gmt>
+        <wp:status>publish</wp:status>
+        <wp:post_type>post</wp:post_type>
+        <category domain="post_tag" nicename="news"><![CDATA[News]]></category>
+        <wp:comment><wp:comment_id>1</wp:comment_id></wp:comment>
+    </item>
+    <item>
+        <title>Draft</title>
+        <link>https://example.com/?page_id=13</link>
+        <dc:creator><![CDATA[ghost]]></dc:creator>
+        <content:encoded><![CDATA[Soon]]></content:encoded>
+        <wp:post_id>13</wp:post_id>
+        <wp:post_date_gmt>0000-00-00 00:00:00</wp:post_date_gmt>
+        <wp:post_date>2024-04-01 09:00:00</wp:post_date>
+        <wp:post_name>coming-soon</wp:post_name>
+        <wp:status>draft</wp:status>
+        <wp:post_type>page</wp:post_type>
+    </item>
+    <item>
+        <title>photo.jpg</title>
+        <wp:post_type>attachment</wp:post_type>
+        <wp:attachment_url>https://example.com/photo.jpg</wp:attachment_url>
+    </item>
+</channel>
+</rss>"#;
+
+    fn author() -> schema::AdminUser {
+        schema::AdminUser {
+            id: Uuid::new_v4(),
+            username: String::from("admin"),
+            enabled: true,
+            email: String::new(),
+            superuser: true,
+        }
+    }
+
+    #[test]
+    fn converts_posts_pages_and_reports_the_rest() {
+        let author = author();
+        let converted = convert(EXPORT, Uuid::nil(), &author).unwrap();
+
+        let users = &converted.export.users;
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].username, "jane");
+        assert!(!users[0].enabled);
+        assert_eq!(users[1].id, author.id);
+
+        let pages = &converted.export.pages;
+        assert_eq!(pages.len(), 2);
+
+        let post = &pages[0];
+        assert_eq!(post.path, "/2024/03/hello");
+        assert_eq!(post.title().as_deref(), Some("Hello &amp; welcome"));
+        assert!(post.published);
+        assert_eq!(post.created_by, users[0].id);
+        assert_eq!(post.created_at.to_string(), "2024-03-01 12:30:00");
+        assert_eq!(post.modified_at.to_string(), "2024-03-02 08:00:00");
+        assert_eq!(
+            post.body,
+            "<p>First line<br>\nsecond line</p>\n<p>[gallery ids=\"1,2\"]</p>\n<h2>Heading</h2>"
+        );
+
+        let draft = &pages[1];
+        assert_eq!(draft.path, "/coming-soon");
+        assert!(!draft.published);
+        assert_eq!(draft.created_by, author.id);
+        assert_eq!(draft.created_at.to_string(), "2024-04-01 09:00:00");
+
+        let unmapped: Vec<(&str, &str)> = converted
+            .unmapped
+            .iter()
+            .map(|item| (item.kind, item.key.as_str()))
+            .collect();
+        assert_eq!(
+            unmapped,
+            [
+                ("taxonomy", "/2024/03/hello"),
+                ("comment", "/2024/03/hello"),
+                ("shortcode", "/2024/03/hello"),
+                ("attachment", "photo.jpg"),
+            ]
+        );
+    }
+
+    #[test]
+    fn refuses_other_xml() {
+        assert!(convert("<rss></rss>", Uuid::nil(), &author()).is_err());
+    }
+}
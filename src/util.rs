@@ -24,3 +24,18 @@ pub mod println {
         println!("{}", msg.into().italic());
     }
 }
+
+pub mod token {
+    use sha2::{Digest, Sha256};
+    use uuid::Uuid;
+
+    // Two random v4 UUIDs give 244 bits of randomness
+    pub fn generate() -> String {
+        format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+    }
+
+    // Only the hash of a token is ever stored in the database
+    pub fn hash(token: &str) -> String {
+        format!("{:x}", Sha256::digest(token.as_bytes()))
+    }
+}
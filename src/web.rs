@@ -5,21 +5,137 @@
  * See the file "LICENSE" in the root of this project.
  */
 
-use crate::{database::Database, util::println};
-use actix_web::{get, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use crate::{
+    database::{schema, Database},
+    plugin::Plugins,
+    util::println,
+};
+use actix_web::{
+    get,
+    http::{
+        header::{self, HeaderValue},
+        StatusCode,
+    },
+    web, App, HttpRequest, HttpResponse, HttpServer, Responder,
+};
 use color_eyre::Result;
-use html::page_to_response;
+use html::{builtin_error_page, html_response, page_to_response, render_page, RenderContext};
+use std::sync::Arc;
 use tokio_util::{sync::CancellationToken, task::TaskTracker};
 
+pub use html::escape;
+
+mod admin_api;
+mod admin_ui;
+mod content_api;
+mod feed;
+mod graphql;
 mod html;
+mod i18n;
+mod search;
+mod shortcode;
+pub mod static_export;
+
+// Admins can override error pages by creating e.g. /_errors/404
+const ERROR_PAGE_PREFIX: &str = "/_errors/";
 
 struct AppState {
     db: Database,
+    plugins: Arc<Plugins>,
+    // Believe X-Forwarded-* and Forwarded headers, only safe behind a proxy that sets them
+    trust_proxy: bool,
+}
+
+// The host a request was sent to, port included. Any client can send proxy
+// headers, so without trust_proxy only the request itself counts.
+fn request_host(req: &HttpRequest, data: &AppState) -> String {
+    if data.trust_proxy {
+        return req.connection_info().host().to_string();
+    }
+
+    req.uri()
+        .authority()
+        .map(|authority| authority.as_str())
+        .or_else(|| {
+            req.headers()
+                .get(header::HOST)
+                .and_then(|host| host.to_str().ok())
+        })
+        .unwrap_or_default()
+        .to_string()
+}
+
+// Scheme and host, e.g. https://example.com
+fn base_url(req: &HttpRequest, data: &AppState) -> String {
+    let scheme = match (data.trust_proxy, req.app_config().secure()) {
+        (true, _) => req.connection_info().scheme().to_string(),
+        (false, true) => String::from("https"),
+        (false, false) => String::from("http"),
+    };
+
+    format!("{}://{}", scheme, request_host(req, data))
+}
+
+// The address of the client, or the one the proxy reports with trust_proxy
+fn client_ip(req: &HttpRequest, data: &AppState) -> Option<String> {
+    match data.trust_proxy {
+        true => req
+            .connection_info()
+            .realip_remote_addr()
+            .map(str::to_string),
+        false => req.peer_addr().map(|addr| addr.ip().to_string()),
+    }
 }
 
-#[get("/admin")]
-async fn admin() -> impl Responder {
-    HttpResponse::Ok().body("Admin Page")
+// Strips the port off a Host header while leaving IPv6 literals intact
+fn host_without_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if !port.ends_with(']') && port.chars().all(|c| c.is_ascii_digit()) => {
+            name
+        }
+        _ => host,
+    }
+}
+
+async fn resolve_site(req: &HttpRequest, data: &AppState) -> color_eyre::Result<schema::Site> {
+    let host = host_without_port(&request_host(req, data)).to_lowercase();
+    data.db.get_site_by_host(host).await
+}
+
+// Without a site there is no admin defined page to fall back on
+async fn error_page(
+    status: StatusCode,
+    site: Option<&schema::Site>,
+    data: &AppState,
+) -> HttpResponse {
+    let site = match site {
+        Some(site) => site,
+        None => return builtin_error_page(status),
+    };
+    let path = format!("{}{}", ERROR_PAGE_PREFIX, status.as_u16());
+
+    match data.db.get_page(site.id, path, false).await {
+        Ok(page) if page.published => {
+            let context = RenderContext::menus_only(site, &data.db).await;
+            page_to_response(page, status, context, data).await
+        }
+        _ => builtin_error_page(status),
+    }
+}
+
+async fn redirect_or_not_found(site: &schema::Site, path: String, data: &AppState) -> HttpResponse {
+    match data.db.get_redirect(site.id, path).await {
+        Ok(Some(redirect)) => {
+            let status = StatusCode::from_u16(redirect.status_code as u16)
+                .unwrap_or(StatusCode::MOVED_PERMANENTLY);
+
+            HttpResponse::build(status)
+                .insert_header((header::LOCATION, redirect.target))
+                .finish()
+        }
+        Ok(None) => error_page(StatusCode::NOT_FOUND, Some(site), data).await,
+        Err(_) => error_page(StatusCode::INTERNAL_SERVER_ERROR, Some(site), data).await,
+    }
 }
 
 #[get("/{tail:.*}")]
@@ -29,30 +145,168 @@ async fn managed_pages(req: HttpRequest, data: web::Data<AppState>) -> impl Resp
         None => return HttpResponse::BadRequest().body("No Tailing String"),
     };
 
-    let page = match data.db.get_page(tail, false).await {
+    let site = match resolve_site(&req, &data).await {
+        Ok(site) => site,
+        Err(err) => match err.downcast_ref::<sqlx::Error>() {
+            Some(sqlx::Error::RowNotFound) => return builtin_error_page(StatusCode::NOT_FOUND),
+            _ => return builtin_error_page(StatusCode::INTERNAL_SERVER_ERROR),
+        },
+    };
+
+    // Error pages are only ever served in place of a failed request
+    if tail.starts_with(ERROR_PAGE_PREFIX) {
+        return error_page(StatusCode::NOT_FOUND, Some(&site), &data).await;
+    }
+
+    // An explicit locale prefix wins, unless nothing is published in that locale.
+    // Then the path is looked up as is, so pages like /de can still exist.
+    if let Some((locale, path)) = i18n::split_prefix(&tail) {
+        if let Ok(page) = data.db.get_page(site.id, &path, false).await {
+            // Translations of an unpublished page go with it
+            if !page.published {
+                return redirect_or_not_found(&site, tail, &data).await;
+            }
+
+            let translations = data
+                .db
+                .get_page_translations(site.id, &path)
+                .await
+                .unwrap_or_default();
+
+            if let Some(page) = i18n::localize(page, &site, &translations, locale) {
+                return localized_response(&req, &site, page, locale, &translations, &data).await;
+            }
+        }
+    }
+
+    let page = match data.db.get_page(site.id, &tail, false).await {
         Ok(page) => page,
         Err(err) => match err.downcast_ref::<sqlx::Error>() {
             Some(sqlx::Error::RowNotFound) => {
-                return HttpResponse::NotFound().body("404 Not Found")
+                return redirect_or_not_found(&site, tail, &data).await
             }
-            _ => return HttpResponse::InternalServerError().body("500 Internal Server Error"),
+            _ => return error_page(StatusCode::INTERNAL_SERVER_ERROR, Some(&site), &data).await,
         },
     };
 
-    page_to_response(page).await
+    // Drafts and pages waiting for review look like they don't exist yet
+    if !page.published {
+        return redirect_or_not_found(&site, tail, &data).await;
+    }
+
+    let translations = data
+        .db
+        .get_page_translations(site.id, &tail)
+        .await
+        .unwrap_or_default();
+
+    let accept_language = req
+        .headers()
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let locale = i18n::negotiate(
+        accept_language,
+        &i18n::available_locales(&site, &translations),
+    )
+    .unwrap_or(&site.default_locale)
+    .to_string();
+
+    let page = match i18n::localize(page, &site, &translations, &locale) {
+        Some(page) => page,
+        None => return error_page(StatusCode::INTERNAL_SERVER_ERROR, Some(&site), &data).await,
+    };
+
+    let mut response = localized_response(&req, &site, page, &locale, &translations, &data).await;
+    if !translations.is_empty() {
+        response
+            .headers_mut()
+            .insert(header::VARY, HeaderValue::from_static("Accept-Language"));
+    }
+    response
+}
+
+async fn localized_response(
+    req: &HttpRequest,
+    site: &schema::Site,
+    page: schema::Page,
+    locale: &str,
+    translations: &[schema::PageTranslation],
+    data: &AppState,
+) -> HttpResponse {
+    let base = base_url(req, data);
+
+    if let Ok(Some(rendered)) = data
+        .db
+        .get_rendered_page(site.id, page.path.as_str(), locale)
+        .await
+    {
+        if rendered.base == base {
+            return html_response(StatusCode::OK, rendered.locale, rendered.html);
+        }
+    }
+
+    let mut context = RenderContext::load(site, &page, &data.db).await;
+    context.locale = locale.to_string();
+    context.alternates = i18n::alternates(&base, site, &page.path, translations);
+
+    let site_id = page.site_id;
+    let path = page.path.clone();
+    let rendered = render_page(page, &context, data).await;
+
+    let _ = data
+        .db
+        .set_rendered_page(schema::RenderedPage {
+            site_id,
+            path,
+            locale: context.locale.clone(),
+            base,
+            html: rendered.html.clone(),
+            blocks: rendered.blocks,
+            pages: rendered.pages,
+        })
+        .await;
+
+    html_response(StatusCode::OK, context.locale, rendered.html)
 }
 
 pub async fn start_server(
     bind: String,
     db: Database,
+    feed_prefixes: Vec<String>,
+    plugins: Arc<Plugins>,
+    trust_proxy: bool,
     tracker: &TaskTracker,
     cancel_token: CancellationToken,
 ) -> Result<()> {
     let server = HttpServer::new(move || {
-        App::new()
-            .app_data(web::Data::new(AppState { db: db.clone() }))
-            .service(admin)
-            .service(managed_pages)
+        let mut app = App::new()
+            .app_data(web::Data::new(AppState {
+                db: db.clone(),
+                plugins: plugins.clone(),
+                trust_proxy,
+            }))
+            .configure(admin_ui::config)
+            .configure(admin_api::config)
+            .configure(content_api::config)
+            .configure(graphql::config)
+            .service(search::search)
+            .configure(|cfg| plugins.register_routes(cfg));
+
+        // Feeds have to be registered before the managed_pages catch-all
+        for prefix in &feed_prefixes {
+            app = app
+                .route(
+                    &format!("{}{}", prefix, feed::RSS_FILE),
+                    web::get().to(feed::rss),
+                )
+                .route(
+                    &format!("{}{}", prefix, feed::ATOM_FILE),
+                    web::get().to(feed::atom),
+                );
+        }
+
+        app.service(managed_pages)
     })
     .bind(bind)?
     .run();
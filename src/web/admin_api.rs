@@ -0,0 +1,1929 @@
+/*
+ * web/admin_api.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * JSON admin API mounted under /admin/api.
+ * Every request has to carry an "Authorization: Bearer <token>" header.
+ */
+
+use super::{client_ip, resolve_site, AppState};
+use crate::{
+    database::{check_webhook_url, schema, Actor, Database, PageOp, PathInTrash, VersionConflict},
+    transfer::{self, archive, wxr},
+    util::token,
+};
+use actix_web::{
+    delete,
+    dev::Payload,
+    error::{
+        ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorNotFound, ErrorUnauthorized,
+    },
+    get,
+    http::header::{self, ETag, EntityTag, Header, IfMatch},
+    post, put, web, FromRequest, HttpRequest, HttpResponse, Responder,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::json;
+use std::{future::Future, pin::Pin};
+use uuid::Uuid;
+
+// Clients pick the site they are editing with this header.
+// Without it the site is resolved from the Host header like public requests.
+const SITE_HEADER: &str = "X-Site-Id";
+const MAX_IMPORT_BYTES: usize = 256 * 1024 * 1024;
+
+// An enabled admin user with access to the site the request operates on
+pub struct AdminSession {
+    pub user: schema::AdminUser,
+    pub site: schema::Site,
+    // Changes made through this land in the audit log under the user
+    pub db: Database,
+}
+
+// An enabled superuser, required for managing sites and users
+pub struct SuperuserSession {
+    pub user: schema::AdminUser,
+    pub db: Database,
+}
+
+fn audited_db(req: &HttpRequest, data: &AppState, user: &schema::AdminUser) -> Database {
+    data.db.as_actor(Actor {
+        user_id: user.id,
+        ip: client_ip(req, data),
+    })
+}
+
+async fn authenticate(
+    req: &HttpRequest,
+) -> Result<(web::Data<AppState>, schema::AdminUser), actix_web::Error> {
+    let data = match req.app_data::<web::Data<AppState>>() {
+        Some(data) => data.clone(),
+        None => return Err(ErrorInternalServerError("500 Internal Server Error")),
+    };
+
+    let token_hash = match req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        Some(value) => token::hash(value.trim()),
+        None => return Err(ErrorUnauthorized("401 Unauthorized")),
+    };
+
+    match data.db.get_user_by_token(token_hash).await {
+        Ok(user) if user.enabled => Ok((data, user)),
+        Ok(_) => Err(ErrorUnauthorized("401 Unauthorized")),
+        Err(err) => match err.downcast_ref::<sqlx::Error>() {
+            Some(sqlx::Error::RowNotFound) => Err(ErrorUnauthorized("401 Unauthorized")),
+            _ => Err(ErrorInternalServerError("500 Internal Server Error")),
+        },
+    }
+}
+
+impl FromRequest for AdminSession {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move {
+            let (data, user) = authenticate(&req).await?;
+
+            let site = match req.headers().get(SITE_HEADER) {
+                Some(value) => match value.to_str().ok().and_then(|id| id.parse().ok()) {
+                    Some(id) => data.db.get_site(id).await,
+                    None => return Err(ErrorBadRequest("400 Bad Request")),
+                },
+                None => resolve_site(&req, &data).await,
+            };
+
+            let site = match site {
+                Ok(site) => site,
+                Err(err) => match err.downcast_ref::<sqlx::Error>() {
+                    Some(sqlx::Error::RowNotFound) => return Err(ErrorNotFound("404 Not Found")),
+                    _ => return Err(ErrorInternalServerError("500 Internal Server Error")),
+                },
+            };
+
+            if !user.superuser {
+                match data.db.get_site_grants(user.id).await {
+                    Ok(grants) if grants.contains(&site.id) => {}
+                    Ok(_) => return Err(ErrorForbidden("403 Forbidden")),
+                    Err(_) => return Err(ErrorInternalServerError("500 Internal Server Error")),
+                }
+            }
+
+            let db = audited_db(&req, &data, &user);
+            Ok(AdminSession { user, site, db })
+        })
+    }
+}
+
+impl FromRequest for SuperuserSession {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move {
+            let (data, user) = authenticate(&req).await?;
+
+            if user.superuser {
+                let db = audited_db(&req, &data, &user);
+                Ok(SuperuserSession { user, db })
+            } else {
+                Err(ErrorForbidden("403 Forbidden"))
+            }
+        })
+    }
+}
+
+pub(super) fn error_json<S: Into<String>>(msg: S) -> serde_json::Value {
+    json!({ "error": msg.into() })
+}
+
+// Maps database errors onto the closest HTTP status
+pub(super) fn error_response(err: color_eyre::Report) -> HttpResponse {
+    if err.downcast_ref::<VersionConflict>().is_some()
+        || err.downcast_ref::<PathInTrash>().is_some()
+    {
+        return HttpResponse::Conflict().json(error_json(err.to_string()));
+    }
+
+    match err.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(error_json("Not Found")),
+        Some(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+            HttpResponse::Conflict().json(error_json(db_err.message()))
+        }
+        // 2201B is raised for redirects with an invalid regex
+        Some(sqlx::Error::Database(db_err))
+            if db_err.is_check_violation()
+                || db_err.is_foreign_key_violation()
+                || db_err.code().as_deref() == Some("2201B") =>
+        {
+            HttpResponse::BadRequest().json(error_json(db_err.message()))
+        }
+        _ => HttpResponse::InternalServerError().json(error_json("Internal Server Error")),
+    }
+}
+
+fn tail_to_path(req: &HttpRequest) -> String {
+    format!("/{}", req.match_info().get("path").unwrap_or_default())
+}
+
+// Pages are tagged with their version, PUT takes it back in If-Match
+fn page_etag(page: &schema::Page) -> ETag {
+    ETag(EntityTag::new_strong(page.version.to_string()))
+}
+
+// A malformed If-Match never matches
+fn if_match(req: &HttpRequest, page: &schema::Page) -> bool {
+    let tag = EntityTag::new_strong(page.version.to_string());
+    match IfMatch::parse(req) {
+        Ok(IfMatch::Any) => true,
+        Ok(IfMatch::Items(items)) => items.iter().any(|item| item.strong_eq(&tag)),
+        Err(_) => false,
+    }
+}
+
+#[derive(Deserialize)]
+struct PageInput {
+    path: String,
+    published: bool,
+    #[serde(default)]
+    metadata: Vec<String>,
+    body: String,
+    #[serde(default)]
+    sort_order: i32,
+    #[serde(default)]
+    content_type: Option<String>,
+    #[serde(default)]
+    fields: serde_json::Map<String, serde_json::Value>,
+}
+
+// Checks page fields against the content type, Err holds the response to send instead
+async fn validate_fields(
+    data: &AppState,
+    site_id: Uuid,
+    content_type: Option<&str>,
+    fields: &serde_json::Value,
+) -> Result<(), HttpResponse> {
+    let content_type = match content_type {
+        Some(content_type) => content_type,
+        None if fields.as_object().is_some_and(|fields| fields.is_empty()) => return Ok(()),
+        None => {
+            return Err(HttpResponse::BadRequest().json(error_json("Fields need a content type")))
+        }
+    };
+
+    let content_type = match data.db.get_content_type(site_id, content_type).await {
+        Ok(content_type) => content_type,
+        Err(err) => match err.downcast_ref::<sqlx::Error>() {
+            Some(sqlx::Error::RowNotFound) => {
+                return Err(HttpResponse::BadRequest().json(error_json("Unknown content type")))
+            }
+            _ => return Err(error_response(err)),
+        },
+    };
+
+    if let Err(msg) = content_type.validate(fields) {
+        return Err(HttpResponse::BadRequest().json(error_json(msg)));
+    }
+
+    let references: Vec<String> = content_type
+        .references(fields)
+        .into_iter()
+        .map(String::from)
+        .collect();
+    if references.is_empty() {
+        return Ok(());
+    }
+
+    let pages = match data.db.get_pages_by_path(site_id, references.clone()).await {
+        Ok(pages) => pages,
+        Err(err) => return Err(error_response(err)),
+    };
+    for path in references {
+        if !pages.iter().any(|page| page.path == path) {
+            return Err(HttpResponse::BadRequest().json(error_json(format!(
+                "Referenced page {} does not exist",
+                path
+            ))));
+        }
+    }
+
+    Ok(())
+}
+
+// Newest first by ?sort=created_at (default) or modified_at, with the filters of
+// schema::PageListQuery. next holds the after_at and after_path for the next request.
+#[get("/pages")]
+async fn list_pages(
+    session: AdminSession,
+    query: web::Query<schema::PageListQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let mut query = query.into_inner();
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+    query.limit = Some(limit);
+    let sort = query.sort;
+
+    match data.db.list_pages(session.site.id, query).await {
+        Ok(pages) => {
+            let next = match pages.last() {
+                Some(page) if pages.len() as i64 == limit => Some(json!({
+                    "after_at": match sort {
+                        schema::PageSort::CreatedAt => page.created_at,
+                        schema::PageSort::ModifiedAt => page.modified_at,
+                    },
+                    "after_path": page.path,
+                })),
+                _ => None,
+            };
+
+            HttpResponse::Ok().json(json!({ "pages": pages, "next": next }))
+        }
+        Err(err) => error_response(err),
+    }
+}
+
+#[get("/pages/{path:.*}")]
+async fn get_page(
+    req: HttpRequest,
+    session: AdminSession,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    match data
+        .db
+        .get_page(session.site.id, tail_to_path(&req), true)
+        .await
+    {
+        Ok(page) => HttpResponse::Ok()
+            .insert_header(page_etag(&page))
+            .json(page),
+        Err(err) => error_response(err),
+    }
+}
+
+#[post("/pages")]
+async fn new_page(
+    session: AdminSession,
+    input: web::Json<PageInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let input = input.into_inner();
+    if let Err(msg) = schema::Page::check_path(&input.path) {
+        return HttpResponse::BadRequest().json(error_json(msg));
+    }
+    if input.published && !bypasses_review(&session) {
+        return review_required();
+    }
+
+    let fields = serde_json::Value::Object(input.fields);
+    if let Err(response) = validate_fields(
+        &data,
+        session.site.id,
+        input.content_type.as_deref(),
+        &fields,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let now = Utc::now().naive_utc();
+    let page = schema::Page {
+        site_id: session.site.id,
+        path: input.path,
+        created_at: now,
+        created_by: session.user.id,
+        modified_at: now,
+        modified_by: session.user.id,
+        published: input.published,
+        metadata: input.metadata,
+        body: input.body,
+        sort_order: input.sort_order,
+        content_type: input.content_type,
+        fields,
+        version: 1,
+    };
+
+    match session.db.new_page(page.clone()).await {
+        Ok(()) => {
+            data.plugins.on_page_saved(&page).await;
+            HttpResponse::Created()
+                .insert_header(page_etag(&page))
+                .json(page)
+        }
+        Err(err) => error_response(err),
+    }
+}
+
+// A different path in the body renames the page and leaves a 301 behind.
+// Saves need the ETag from GET in If-Match and fail with 412 if someone else saved in between.
+#[put("/pages/{path:.*}")]
+async fn set_page(
+    req: HttpRequest,
+    session: AdminSession,
+    input: web::Json<PageInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    // Without it two clients would silently overwrite each other
+    if !req.headers().contains_key(header::IF_MATCH) {
+        return HttpResponse::PreconditionRequired().json(error_json(
+            "Saving a page needs the ETag from GET in If-Match",
+        ));
+    }
+
+    let input = input.into_inner();
+    if let Err(msg) = schema::Page::check_path(&input.path) {
+        return HttpResponse::BadRequest().json(error_json(msg));
+    }
+
+    let fields = serde_json::Value::Object(input.fields);
+    if let Err(response) = validate_fields(
+        &data,
+        session.site.id,
+        input.content_type.as_deref(),
+        &fields,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let path = tail_to_path(&req);
+    let mut page = match data.db.get_page(session.site.id, &path, true).await {
+        Ok(page) => page,
+        Err(err) => return error_response(err),
+    };
+    if (page.published || input.published) && !bypasses_review(&session) {
+        return review_required();
+    }
+    if !if_match(&req, &page) {
+        return HttpResponse::PreconditionFailed()
+            .insert_header(page_etag(&page))
+            .json(error_json("The page was changed by someone else"));
+    }
+
+    let renamed = input.path != path;
+    page.path = input.path;
+    page.modified_at = Utc::now().naive_utc();
+    page.modified_by = session.user.id;
+    page.published = input.published;
+    page.metadata = input.metadata;
+    page.body = input.body;
+    page.sort_order = input.sort_order;
+    page.content_type = input.content_type;
+    page.fields = fields;
+
+    // A rename goes in together with the save, so a failed save keeps the old path
+    let result = if renamed {
+        let ops = vec![
+            PageOp::Rename(session.site.id, path, page.path.clone()),
+            PageOp::Set(page.clone()),
+        ];
+        session.db.batch(ops).await.map(|()| page.version + 1)
+    } else {
+        session.db.set_page(page.clone()).await
+    };
+
+    match result {
+        Ok(version) => {
+            page.version = version;
+            data.plugins.on_page_saved(&page).await;
+            HttpResponse::Ok()
+                .insert_header(page_etag(&page))
+                .json(page)
+        }
+        Err(err) => error_response(err),
+    }
+}
+
+#[derive(Deserialize)]
+struct BulkPagesInput {
+    paths: Vec<String>,
+    // publish, unpublish or delete
+    action: String,
+}
+
+// Applies the action to all of the pages or, if any of them fails, to none
+#[post("/bulk-pages")]
+async fn bulk_pages(
+    session: AdminSession,
+    input: web::Json<BulkPagesInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let input = input.into_inner();
+    let published = match input.action.as_str() {
+        "publish" => Some(true),
+        "unpublish" => Some(false),
+        "delete" => None,
+        _ => {
+            return HttpResponse::BadRequest().json(error_json(
+                "The action has to be publish, unpublish or delete",
+            ))
+        }
+    };
+
+    let now = Utc::now().naive_utc();
+    let mut pages = Vec::with_capacity(input.paths.len());
+    for path in &input.paths {
+        let page = match data.db.get_page(session.site.id, path, true).await {
+            Ok(page) => page,
+            Err(err) => return error_response(err),
+        };
+        if page.published && !bypasses_review(&session) {
+            return review_required();
+        }
+
+        pages.push(schema::Page {
+            modified_at: now,
+            modified_by: session.user.id,
+            published: published.unwrap_or(page.published),
+            ..page
+        });
+    }
+    if published == Some(true) && !bypasses_review(&session) {
+        return review_required();
+    }
+
+    let ops = pages
+        .iter()
+        .map(|page| match published {
+            Some(_) => PageOp::Set(page.clone()),
+            None => PageOp::Delete(page.site_id, page.path.clone()),
+        })
+        .collect();
+    if let Err(err) = session.db.batch(ops).await {
+        return error_response(err);
+    }
+
+    if published.is_none() {
+        return HttpResponse::NoContent().finish();
+    }
+
+    for page in &mut pages {
+        page.version += 1;
+        data.plugins.on_page_saved(page).await;
+    }
+    HttpResponse::Ok().json(pages)
+}
+
+// Moves the page to the trash, see /trash/pages
+#[delete("/pages/{path:.*}")]
+async fn delete_page(
+    req: HttpRequest,
+    session: AdminSession,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let path = tail_to_path(&req);
+    if !bypasses_review(&session) {
+        match data.db.get_page(session.site.id, &path, true).await {
+            Ok(page) if page.published => return review_required(),
+            Ok(_) => {}
+            Err(err) => return error_response(err),
+        }
+    }
+
+    match session.db.delete_page(session.site.id, path).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    }
+}
+
+// Most recently deleted first
+#[get("/trash/pages")]
+async fn get_page_trash(session: AdminSession, data: web::Data<AppState>) -> impl Responder {
+    match data.db.get_page_trash(session.site.id).await {
+        Ok(pages) => HttpResponse::Ok().json(pages),
+        Err(err) => error_response(err),
+    }
+}
+
+#[post("/trash/pages/{path:.*}")]
+async fn restore_page(
+    req: HttpRequest,
+    session: AdminSession,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    // A published page would go live again as it was
+    let path = tail_to_path(&req);
+    if !bypasses_review(&session) {
+        match data.db.get_page_trash(session.site.id).await {
+            Ok(pages) if pages.iter().any(|page| page.path == path && page.published) => {
+                return review_required()
+            }
+            Ok(_) => {}
+            Err(err) => return error_response(err),
+        }
+    }
+
+    match session.db.restore_page(session.site.id, path).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    }
+}
+
+// Deletes a trashed page for good without waiting for the purge task
+#[delete("/trash/pages/{path:.*}")]
+async fn purge_page(req: HttpRequest, session: AdminSession) -> impl Responder {
+    match session
+        .db
+        .purge_page(session.site.id, tail_to_path(&req))
+        .await
+    {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    }
+}
+
+// Sites that require review only let superusers change public pages directly
+fn bypasses_review(session: &AdminSession) -> bool {
+    !session.site.require_review || session.user.superuser
+}
+
+fn review_required() -> HttpResponse {
+    HttpResponse::Forbidden().json(error_json(
+        "Published pages on this site change through reviewed drafts",
+    ))
+}
+
+#[derive(Deserialize)]
+struct PageDraftInput {
+    #[serde(default)]
+    metadata: Vec<String>,
+    body: String,
+    #[serde(default)]
+    sort_order: i32,
+    #[serde(default)]
+    content_type: Option<String>,
+    #[serde(default)]
+    fields: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct TransitionInput {
+    to_state: String,
+}
+
+#[derive(Deserialize)]
+struct ReviewerInput {
+    reviewer_id: Option<Uuid>,
+}
+
+#[derive(Deserialize)]
+struct CommentInput {
+    body: String,
+}
+
+// Most recently changed first
+#[get("/drafts")]
+async fn get_page_drafts(session: AdminSession, data: web::Data<AppState>) -> impl Responder {
+    match data.db.get_page_drafts(session.site.id).await {
+        Ok(drafts) => HttpResponse::Ok().json(drafts),
+        Err(err) => error_response(err),
+    }
+}
+
+#[get("/drafts/{path:.*}")]
+async fn get_page_draft(
+    req: HttpRequest,
+    session: AdminSession,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    match data
+        .db
+        .get_page_draft(session.site.id, tail_to_path(&req))
+        .await
+    {
+        Ok(draft) => HttpResponse::Ok().json(draft),
+        Err(err) => error_response(err),
+    }
+}
+
+// Creates the draft or changes it while it is still in the draft state.
+// The public page, if there is one, stays as it is until the draft is published.
+#[put("/drafts/{path:.*}")]
+async fn set_page_draft(
+    req: HttpRequest,
+    session: AdminSession,
+    input: web::Json<PageDraftInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let input = input.into_inner();
+    let path = tail_to_path(&req);
+
+    let fields = serde_json::Value::Object(input.fields);
+    if let Err(response) = validate_fields(
+        &data,
+        session.site.id,
+        input.content_type.as_deref(),
+        &fields,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let now = Utc::now().naive_utc();
+    let (draft, created) = match data.db.get_page_draft(session.site.id, &path).await {
+        Ok(draft) if draft.state != "draft" => {
+            return HttpResponse::Conflict().json(error_json(format!(
+                "The draft is {}, move it back to draft to change it",
+                draft.state
+            )))
+        }
+        Ok(draft) => (draft, false),
+        Err(err) => match err.downcast_ref::<sqlx::Error>() {
+            Some(sqlx::Error::RowNotFound) => (
+                schema::PageDraft {
+                    site_id: session.site.id,
+                    path,
+                    state: String::from("draft"),
+                    reviewer_id: None,
+                    created_at: now,
+                    created_by: session.user.id,
+                    modified_at: now,
+                    modified_by: session.user.id,
+                    metadata: Vec::new(),
+                    body: String::new(),
+                    sort_order: 0,
+                    content_type: None,
+                    fields: serde_json::Value::Null,
+                },
+                true,
+            ),
+            _ => return error_response(err),
+        },
+    };
+
+    let draft = schema::PageDraft {
+        modified_at: now,
+        modified_by: session.user.id,
+        metadata: input.metadata,
+        body: input.body,
+        sort_order: input.sort_order,
+        content_type: input.content_type,
+        fields,
+        ..draft
+    };
+
+    match session.db.set_page_draft(draft.clone()).await {
+        Ok(()) if created => HttpResponse::Created().json(draft),
+        Ok(()) => HttpResponse::Ok().json(draft),
+        Err(err) => error_response(err),
+    }
+}
+
+// Throws the draft away, the public page is left alone
+#[delete("/drafts/{path:.*}")]
+async fn delete_page_draft(req: HttpRequest, session: AdminSession) -> impl Responder {
+    match session
+        .db
+        .delete_page_draft(session.site.id, tail_to_path(&req))
+        .await
+    {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    }
+}
+
+// Moves the draft along one of the site's workflow transitions.
+// Moving it to published replaces the public page and returns it.
+#[post("/draft-transitions/{path:.*}")]
+async fn transition_page_draft(
+    req: HttpRequest,
+    session: AdminSession,
+    input: web::Json<TransitionInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let path = tail_to_path(&req);
+    let to_state = input.into_inner().to_state;
+
+    let draft = match data.db.get_page_draft(session.site.id, &path).await {
+        Ok(draft) => draft,
+        Err(err) => return error_response(err),
+    };
+
+    let transitions = match data.db.get_workflow_transitions(session.site.id).await {
+        Ok(transitions) => transitions,
+        Err(err) => return error_response(err),
+    };
+    let transition = match transitions
+        .iter()
+        .find(|transition| transition.from_state == draft.state && transition.to_state == to_state)
+    {
+        Some(transition) => transition,
+        None => {
+            return HttpResponse::Conflict().json(error_json(format!(
+                "No transition from {} to {}",
+                draft.state, to_state
+            )))
+        }
+    };
+
+    let allowed = session.user.superuser
+        || match transition.allowed.as_str() {
+            "editor" => true,
+            "reviewer" => draft.reviewer_id == Some(session.user.id),
+            _ => false,
+        };
+    if !allowed {
+        return HttpResponse::Forbidden().json(error_json(format!(
+            "Only the {} may move this draft to {}",
+            transition.allowed, to_state
+        )));
+    }
+
+    if let Err(err) = session
+        .db
+        .transition_page_draft(session.site.id, path.clone(), draft.state, to_state.clone())
+        .await
+    {
+        return error_response(err);
+    }
+
+    if to_state != "published" {
+        return HttpResponse::Ok().json(schema::PageDraft {
+            state: to_state,
+            ..draft
+        });
+    }
+
+    match data.db.get_page(session.site.id, &path, true).await {
+        Ok(page) => {
+            data.plugins.on_page_saved(&page).await;
+            HttpResponse::Ok().json(page)
+        }
+        Err(err) => error_response(err),
+    }
+}
+
+// The reviewer has to be able to edit the site, null unassigns
+#[put("/draft-reviewers/{path:.*}")]
+async fn set_page_draft_reviewer(
+    req: HttpRequest,
+    session: AdminSession,
+    input: web::Json<ReviewerInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let reviewer_id = input.into_inner().reviewer_id;
+
+    if let Some(reviewer_id) = reviewer_id {
+        let reviewer = match data.db.get_user(reviewer_id, true).await {
+            Ok(reviewer) => reviewer,
+            Err(err) => return error_response(err),
+        };
+
+        let can_edit = reviewer.enabled
+            && (reviewer.superuser
+                || match data.db.get_site_grants(reviewer.id).await {
+                    Ok(grants) => grants.contains(&session.site.id),
+                    Err(err) => return error_response(err),
+                });
+        if !can_edit {
+            return HttpResponse::BadRequest()
+                .json(error_json("The reviewer has no access to this site"));
+        }
+    }
+
+    match session
+        .db
+        .set_page_draft_reviewer(session.site.id, tail_to_path(&req), reviewer_id)
+        .await
+    {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    }
+}
+
+// Review comments on a path, oldest first. They stay after the draft is published.
+#[get("/comments/{path:.*}")]
+async fn get_workflow_comments(
+    req: HttpRequest,
+    session: AdminSession,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    match data
+        .db
+        .get_workflow_comments(session.site.id, tail_to_path(&req))
+        .await
+    {
+        Ok(comments) => HttpResponse::Ok().json(comments),
+        Err(err) => error_response(err),
+    }
+}
+
+#[post("/comments/{path:.*}")]
+async fn new_workflow_comment(
+    req: HttpRequest,
+    session: AdminSession,
+    input: web::Json<CommentInput>,
+) -> impl Responder {
+    let body = input.into_inner().body;
+    if body.trim().is_empty() {
+        return HttpResponse::BadRequest().json(error_json("Comments can't be empty"));
+    }
+
+    let comment = schema::WorkflowComment {
+        id: Uuid::now_v7(),
+        site_id: session.site.id,
+        path: tail_to_path(&req),
+        author_id: session.user.id,
+        body,
+        created_at: Utc::now().naive_utc(),
+    };
+
+    match session.db.new_workflow_comment(comment.clone()).await {
+        Ok(()) => HttpResponse::Created().json(comment),
+        Err(err) => error_response(err),
+    }
+}
+
+#[get("/workflow-transitions")]
+async fn get_workflow_transitions(
+    session: AdminSession,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    match data.db.get_workflow_transitions(session.site.id).await {
+        Ok(transitions) => HttpResponse::Ok().json(transitions),
+        Err(err) => error_response(err),
+    }
+}
+
+// Replaces every transition of the site, only superusers change the workflow
+#[put("/workflow-transitions")]
+async fn set_workflow_transitions(
+    session: AdminSession,
+    input: web::Json<Vec<schema::WorkflowTransition>>,
+) -> impl Responder {
+    if !session.user.superuser {
+        return HttpResponse::Forbidden().json(error_json("Forbidden"));
+    }
+
+    let transitions = input.into_inner();
+    match session
+        .db
+        .set_workflow_transitions(session.site.id, transitions.clone())
+        .await
+    {
+        Ok(()) => HttpResponse::Ok().json(transitions),
+        Err(err) => error_response(err),
+    }
+}
+
+// Direct children of a page in sort order, for building the page tree
+#[get("/children/{path:.*}")]
+async fn get_page_children(
+    req: HttpRequest,
+    session: AdminSession,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    match data
+        .db
+        .get_page_children(session.site.id, tail_to_path(&req))
+        .await
+    {
+        Ok(pages) => HttpResponse::Ok().json(pages),
+        Err(err) => error_response(err),
+    }
+}
+
+#[derive(Deserialize)]
+struct TranslationInput {
+    locale: String,
+    published: bool,
+    #[serde(default)]
+    metadata: Vec<String>,
+    body: String,
+}
+
+#[derive(Deserialize)]
+struct LocaleQuery {
+    locale: String,
+}
+
+#[get("/translations/{path:.*}")]
+async fn get_page_translations(
+    req: HttpRequest,
+    session: AdminSession,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    match data
+        .db
+        .get_page_translations(session.site.id, tail_to_path(&req))
+        .await
+    {
+        Ok(translations) => HttpResponse::Ok().json(translations),
+        Err(err) => error_response(err),
+    }
+}
+
+// Published translations are served as they are, like published pages they only
+// change through review
+async fn check_translation_unpublished(
+    session: &AdminSession,
+    data: &AppState,
+    path: &str,
+    locale: &str,
+) -> Result<(), HttpResponse> {
+    let translations = data
+        .db
+        .get_page_translations(session.site.id, path)
+        .await
+        .map_err(error_response)?;
+
+    match translations
+        .iter()
+        .any(|translation| translation.locale == locale && translation.published)
+    {
+        true => Err(review_required()),
+        false => Ok(()),
+    }
+}
+
+// Creates the translation or replaces its contents
+#[put("/translations/{path:.*}")]
+async fn set_page_translation(
+    req: HttpRequest,
+    session: AdminSession,
+    input: web::Json<TranslationInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let input = input.into_inner();
+    let locale = input.locale.to_lowercase();
+
+    // The page itself is the default locale variant
+    if locale == session.site.default_locale {
+        return HttpResponse::BadRequest().json(error_json("Locale is the site's default locale"));
+    }
+
+    let path = tail_to_path(&req);
+    if !bypasses_review(&session) {
+        if input.published {
+            return review_required();
+        }
+        if let Err(response) = check_translation_unpublished(&session, &data, &path, &locale).await
+        {
+            return response;
+        }
+    }
+
+    let now = Utc::now().naive_utc();
+    let translation = schema::PageTranslation {
+        site_id: session.site.id,
+        path,
+        locale,
+        created_at: now,
+        created_by: session.user.id,
+        modified_at: now,
+        modified_by: session.user.id,
+        published: input.published,
+        metadata: input.metadata,
+        body: input.body,
+    };
+
+    match session.db.set_page_translation(translation.clone()).await {
+        Ok(()) => HttpResponse::Ok().json(translation),
+        Err(err) => error_response(err),
+    }
+}
+
+#[delete("/translations/{path:.*}")]
+async fn delete_page_translation(
+    req: HttpRequest,
+    session: AdminSession,
+    query: web::Query<LocaleQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let path = tail_to_path(&req);
+    let locale = query.into_inner().locale.to_lowercase();
+    if !bypasses_review(&session) {
+        if let Err(response) = check_translation_unpublished(&session, &data, &path, &locale).await
+        {
+            return response;
+        }
+    }
+
+    match session
+        .db
+        .delete_page_translation(session.site.id, path, locale)
+        .await
+    {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    }
+}
+
+fn default_status_code() -> i16 {
+    301
+}
+
+#[derive(Deserialize)]
+struct RedirectInput {
+    source: String,
+    target: String,
+    #[serde(default = "default_status_code")]
+    status_code: i16,
+    #[serde(default)]
+    is_regex: bool,
+}
+
+#[get("/redirects")]
+async fn get_redirects(session: AdminSession, data: web::Data<AppState>) -> impl Responder {
+    match data.db.get_redirects(session.site.id).await {
+        Ok(redirects) => HttpResponse::Ok().json(redirects),
+        Err(err) => error_response(err),
+    }
+}
+
+#[post("/redirects")]
+async fn new_redirect(session: AdminSession, input: web::Json<RedirectInput>) -> impl Responder {
+    let input = input.into_inner();
+    let redirect = schema::Redirect {
+        id: Uuid::now_v7(),
+        site_id: session.site.id,
+        source: input.source,
+        target: input.target,
+        status_code: input.status_code,
+        is_regex: input.is_regex,
+        created_at: Utc::now().naive_utc(),
+    };
+
+    match session.db.new_redirect(redirect.clone()).await {
+        Ok(()) => HttpResponse::Created().json(redirect),
+        Err(err) => error_response(err),
+    }
+}
+
+#[delete("/redirects/{id}")]
+async fn delete_redirect(id: web::Path<Uuid>, session: AdminSession) -> impl Responder {
+    match session
+        .db
+        .delete_redirect(session.site.id, id.into_inner())
+        .await
+    {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    }
+}
+
+#[derive(Deserialize)]
+struct MenuItemInput {
+    label: String,
+    page_path: Option<String>,
+    url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MenuInput {
+    items: Vec<MenuItemInput>,
+}
+
+#[get("/menus")]
+async fn get_menus(session: AdminSession, data: web::Data<AppState>) -> impl Responder {
+    match data.db.get_menus(session.site.id).await {
+        Ok(menus) => HttpResponse::Ok().json(menus),
+        Err(err) => error_response(err),
+    }
+}
+
+// Creates the menu or replaces all of its items, in the order given
+#[put("/menus/{name}")]
+async fn set_menu(
+    name: web::Path<String>,
+    session: AdminSession,
+    input: web::Json<MenuInput>,
+) -> impl Responder {
+    let name = name.into_inner();
+    let menu = schema::Menu {
+        site_id: session.site.id,
+        items: input
+            .into_inner()
+            .items
+            .into_iter()
+            .enumerate()
+            .map(|(index, item)| schema::MenuItem {
+                id: Uuid::now_v7(),
+                menu: name.clone(),
+                sort_order: index as i32,
+                label: item.label,
+                page_path: item.page_path,
+                url: item.url,
+            })
+            .collect(),
+        name,
+    };
+
+    match session.db.set_menu(menu.clone()).await {
+        Ok(()) => HttpResponse::Ok().json(menu),
+        Err(err) => error_response(err),
+    }
+}
+
+#[delete("/menus/{name}")]
+async fn delete_menu(name: web::Path<String>, session: AdminSession) -> impl Responder {
+    match session
+        .db
+        .delete_menu(session.site.id, name.into_inner())
+        .await
+    {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    }
+}
+
+#[derive(Deserialize)]
+struct BlockInput {
+    body: String,
+}
+
+#[get("/blocks")]
+async fn get_blocks(session: AdminSession, data: web::Data<AppState>) -> impl Responder {
+    match data.db.get_blocks(session.site.id).await {
+        Ok(blocks) => HttpResponse::Ok().json(blocks),
+        Err(err) => error_response(err),
+    }
+}
+
+#[get("/blocks/{name}")]
+async fn get_block(
+    name: web::Path<String>,
+    session: AdminSession,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    match data.db.get_block(session.site.id, name.into_inner()).await {
+        Ok(block) => HttpResponse::Ok().json(block),
+        Err(err) => error_response(err),
+    }
+}
+
+// Creates or replaces the block, pages using it are rendered again on their next request
+#[put("/blocks/{name}")]
+async fn set_block(
+    name: web::Path<String>,
+    session: AdminSession,
+    input: web::Json<BlockInput>,
+) -> impl Responder {
+    let block = schema::Block {
+        site_id: session.site.id,
+        name: name.into_inner(),
+        body: input.into_inner().body,
+        modified_at: Utc::now().naive_utc(),
+        modified_by: session.user.id,
+    };
+
+    match session.db.set_block(block.clone()).await {
+        Ok(()) => HttpResponse::Ok().json(block),
+        Err(err) => error_response(err),
+    }
+}
+
+#[delete("/blocks/{name}")]
+async fn delete_block(name: web::Path<String>, session: AdminSession) -> impl Responder {
+    match session
+        .db
+        .delete_block(session.site.id, name.into_inner())
+        .await
+    {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    }
+}
+
+#[derive(Deserialize)]
+struct ContentTypeInput {
+    label: String,
+    fields: Vec<schema::FieldDefinition>,
+    #[serde(default)]
+    template: String,
+}
+
+#[get("/content-types")]
+async fn get_content_types(session: AdminSession, data: web::Data<AppState>) -> impl Responder {
+    match data.db.get_content_types(session.site.id).await {
+        Ok(content_types) => HttpResponse::Ok().json(content_types),
+        Err(err) => error_response(err),
+    }
+}
+
+#[get("/content-types/{name}")]
+async fn get_content_type(
+    name: web::Path<String>,
+    session: AdminSession,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    match data
+        .db
+        .get_content_type(session.site.id, name.into_inner())
+        .await
+    {
+        Ok(content_type) => HttpResponse::Ok().json(content_type),
+        Err(err) => error_response(err),
+    }
+}
+
+// Creates or replaces the type, pages that no longer match keep their values until saved again
+#[put("/content-types/{name}")]
+async fn set_content_type(
+    name: web::Path<String>,
+    session: AdminSession,
+    input: web::Json<ContentTypeInput>,
+) -> impl Responder {
+    let input = input.into_inner();
+
+    let mut fields: Vec<schema::FieldDefinition> = Vec::with_capacity(input.fields.len());
+    for mut field in input.fields {
+        if field.name.is_empty()
+            || !field
+                .name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        {
+            return HttpResponse::BadRequest().json(error_json(
+                "Field names may only contain lowercase letters, digits and _",
+            ));
+        }
+        if fields.iter().any(|other| other.name == field.name) {
+            return HttpResponse::BadRequest()
+                .json(error_json(format!("Duplicate field {}", field.name)));
+        }
+
+        if field.label.is_empty() {
+            field.label = field.name.clone();
+        }
+        fields.push(field);
+    }
+
+    let content_type = schema::ContentType {
+        site_id: session.site.id,
+        name: name.into_inner(),
+        label: input.label,
+        fields: sqlx::types::Json(fields),
+        template: input.template,
+    };
+
+    match session.db.set_content_type(content_type.clone()).await {
+        Ok(()) => HttpResponse::Ok().json(content_type),
+        Err(err) => error_response(err),
+    }
+}
+
+// Fails while pages still use the type
+#[delete("/content-types/{name}")]
+async fn delete_content_type(name: web::Path<String>, session: AdminSession) -> impl Responder {
+    match session
+        .db
+        .delete_content_type(session.site.id, name.into_inner())
+        .await
+    {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    }
+}
+
+#[derive(Deserialize)]
+struct ApiKeyInput {
+    name: String,
+}
+
+#[get("/api-keys")]
+async fn get_api_keys(session: AdminSession, data: web::Data<AppState>) -> impl Responder {
+    match data.db.get_api_keys(session.site.id).await {
+        Ok(api_keys) => HttpResponse::Ok().json(api_keys),
+        Err(err) => error_response(err),
+    }
+}
+
+// Like user tokens the plain key is only ever returned here
+#[post("/api-keys")]
+async fn new_api_key(session: AdminSession, input: web::Json<ApiKeyInput>) -> impl Responder {
+    let key = token::generate();
+    let api_key = schema::ApiKey {
+        id: Uuid::now_v7(),
+        site_id: session.site.id,
+        name: input.into_inner().name,
+        created_at: Utc::now().naive_utc(),
+    };
+
+    match session
+        .db
+        .new_api_key(api_key.clone(), token::hash(&key))
+        .await
+    {
+        Ok(()) => HttpResponse::Created().json(json!({ "key": key, "api_key": api_key })),
+        Err(err) => error_response(err),
+    }
+}
+
+#[delete("/api-keys/{id}")]
+async fn delete_api_key(id: web::Path<Uuid>, session: AdminSession) -> impl Responder {
+    match session
+        .db
+        .delete_api_key(session.site.id, id.into_inner())
+        .await
+    {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+struct WebhookInput {
+    url: String,
+    // Empty subscribes to every event
+    #[serde(default)]
+    events: Vec<String>,
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+}
+
+#[derive(Deserialize)]
+struct DeliveriesQuery {
+    limit: Option<i64>,
+}
+
+#[get("/webhooks")]
+async fn get_webhooks(session: AdminSession, data: web::Data<AppState>) -> impl Responder {
+    match data.db.get_webhooks(session.site.id).await {
+        Ok(webhooks) => HttpResponse::Ok().json(webhooks),
+        Err(err) => error_response(err),
+    }
+}
+
+// The secret is generated and only ever returned here, receivers use it to check
+// X-Magnetite-Signature
+#[post("/webhooks")]
+async fn new_webhook(session: AdminSession, input: web::Json<WebhookInput>) -> impl Responder {
+    let input = input.into_inner();
+    if let Err(msg) = check_webhook_url(&input.url) {
+        return HttpResponse::BadRequest().json(error_json(msg));
+    }
+
+    let webhook = schema::Webhook {
+        id: Uuid::now_v7(),
+        site_id: session.site.id,
+        url: input.url,
+        secret: token::generate(),
+        events: input.events,
+        enabled: input.enabled,
+        created_at: Utc::now().naive_utc(),
+    };
+
+    match session.db.new_webhook(webhook.clone()).await {
+        Ok(()) => {
+            HttpResponse::Created().json(json!({ "secret": webhook.secret, "webhook": webhook }))
+        }
+        Err(err) => error_response(err),
+    }
+}
+
+#[put("/webhooks/{id}")]
+async fn set_webhook(
+    id: web::Path<Uuid>,
+    session: AdminSession,
+    input: web::Json<WebhookInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let input = input.into_inner();
+    if let Err(msg) = check_webhook_url(&input.url) {
+        return HttpResponse::BadRequest().json(error_json(msg));
+    }
+
+    let id = id.into_inner();
+    let webhooks = match data.db.get_webhooks(session.site.id).await {
+        Ok(webhooks) => webhooks,
+        Err(err) => return error_response(err),
+    };
+    let mut webhook = match webhooks.into_iter().find(|webhook| webhook.id == id) {
+        Some(webhook) => webhook,
+        None => return HttpResponse::NotFound().json(error_json("Not Found")),
+    };
+
+    webhook.url = input.url;
+    webhook.events = input.events;
+    webhook.enabled = input.enabled;
+
+    match session.db.set_webhook(webhook.clone()).await {
+        Ok(()) => HttpResponse::Ok().json(webhook),
+        Err(err) => error_response(err),
+    }
+}
+
+// Pending deliveries of the webhook are dropped with it
+#[delete("/webhooks/{id}")]
+async fn delete_webhook(id: web::Path<Uuid>, session: AdminSession) -> impl Responder {
+    match session
+        .db
+        .delete_webhook(session.site.id, id.into_inner())
+        .await
+    {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    }
+}
+
+// Newest first
+#[get("/webhooks/{id}/deliveries")]
+async fn get_webhook_deliveries(
+    id: web::Path<Uuid>,
+    session: AdminSession,
+    query: web::Query<DeliveriesQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+
+    match data
+        .db
+        .get_webhook_deliveries(session.site.id, id.into_inner(), limit)
+        .await
+    {
+        Ok(deliveries) => HttpResponse::Ok().json(deliveries),
+        Err(err) => error_response(err),
+    }
+}
+
+// Sends the same payload again as a new delivery
+#[post("/webhook-deliveries/{id}/replay")]
+async fn replay_webhook_delivery(id: web::Path<Uuid>, session: AdminSession) -> impl Responder {
+    match session
+        .db
+        .replay_webhook_delivery(session.site.id, id.into_inner())
+        .await
+    {
+        Ok(delivery) => HttpResponse::Created().json(delivery),
+        Err(err) => error_response(err),
+    }
+}
+
+// Newest first, next_before continues from the oldest entry returned
+#[get("/audit-log")]
+async fn get_audit_log(
+    session: SuperuserSession,
+    query: web::Query<schema::AuditLogQuery>,
+) -> impl Responder {
+    let mut query = query.into_inner();
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    query.limit = Some(limit);
+
+    match session.db.get_audit_log(query).await {
+        Ok(entries) => {
+            let next_before = match entries.last() {
+                Some(entry) if entries.len() as i64 == limit => Some(entry.id),
+                _ => None,
+            };
+
+            HttpResponse::Ok().json(json!({ "entries": entries, "next_before": next_before }))
+        }
+        Err(err) => error_response(err),
+    }
+}
+
+#[derive(Deserialize)]
+struct UserInput {
+    username: String,
+    email: String,
+    enabled: bool,
+    #[serde(default)]
+    superuser: bool,
+}
+
+#[get("/users")]
+async fn get_users(_: SuperuserSession, data: web::Data<AppState>) -> impl Responder {
+    match data.db.get_users().await {
+        Ok(users) => HttpResponse::Ok().json(users),
+        Err(err) => error_response(err),
+    }
+}
+
+#[get("/users/{id}")]
+async fn get_user(
+    id: web::Path<Uuid>,
+    _: SuperuserSession,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    match data.db.get_user(id.into_inner(), true).await {
+        Ok(user) => HttpResponse::Ok().json(user),
+        Err(err) => error_response(err),
+    }
+}
+
+#[post("/users")]
+async fn new_user(session: SuperuserSession, input: web::Json<UserInput>) -> impl Responder {
+    let input = input.into_inner();
+    let user = schema::AdminUser {
+        id: Uuid::now_v7(),
+        username: input.username,
+        enabled: input.enabled,
+        email: input.email,
+        superuser: input.superuser,
+    };
+
+    match session.db.new_user(user.clone()).await {
+        Ok(()) => HttpResponse::Created().json(user),
+        Err(err) => error_response(err),
+    }
+}
+
+#[put("/users/{id}")]
+async fn set_user(
+    id: web::Path<Uuid>,
+    session: SuperuserSession,
+    input: web::Json<UserInput>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let input = input.into_inner();
+    let mut user = match data.db.get_user(id.into_inner(), true).await {
+        Ok(user) => user,
+        Err(err) => return error_response(err),
+    };
+
+    user.username = input.username;
+    user.enabled = input.enabled;
+    user.email = input.email;
+    user.superuser = input.superuser;
+
+    match session.db.set_user(user.clone()).await {
+        Ok(()) => HttpResponse::Ok().json(user),
+        Err(err) => error_response(err),
+    }
+}
+
+#[delete("/users/{id}")]
+async fn delete_user(id: web::Path<Uuid>, session: SuperuserSession) -> impl Responder {
+    let id = id.into_inner();
+
+    // Stops the last superuser from locking everyone out
+    if id == session.user.id {
+        return HttpResponse::BadRequest().json(error_json("Cannot delete yourself"));
+    }
+
+    match session.db.delete_user(id).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    }
+}
+
+// Users that authored anything can't be purged, they stay disabled in the trash
+#[get("/trash/users")]
+async fn get_user_trash(_: SuperuserSession, data: web::Data<AppState>) -> impl Responder {
+    match data.db.get_user_trash().await {
+        Ok(users) => HttpResponse::Ok().json(users),
+        Err(err) => error_response(err),
+    }
+}
+
+// The user comes back disabled and without tokens
+#[post("/trash/users/{id}")]
+async fn restore_user(id: web::Path<Uuid>, session: SuperuserSession) -> impl Responder {
+    match session.db.restore_user(id.into_inner()).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    }
+}
+
+#[delete("/trash/users/{id}")]
+async fn purge_user(id: web::Path<Uuid>, session: SuperuserSession) -> impl Responder {
+    match session.db.purge_user(id.into_inner()).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    }
+}
+
+// The plain token is only ever returned here, the database keeps a hash
+#[post("/users/{id}/tokens")]
+async fn new_token(id: web::Path<Uuid>, session: SuperuserSession) -> impl Responder {
+    let new_token = token::generate();
+
+    match session
+        .db
+        .new_token(id.into_inner(), token::hash(&new_token))
+        .await
+    {
+        Ok(()) => HttpResponse::Created().json(json!({ "token": new_token })),
+        Err(err) => error_response(err),
+    }
+}
+
+fn default_locale() -> String {
+    String::from("en")
+}
+
+#[derive(Deserialize)]
+struct SiteInput {
+    host: String,
+    name: String,
+    #[serde(default)]
+    is_default: bool,
+    #[serde(default = "default_locale")]
+    default_locale: String,
+    #[serde(default)]
+    require_review: bool,
+}
+
+#[get("/sites")]
+async fn get_sites(_: SuperuserSession, data: web::Data<AppState>) -> impl Responder {
+    match data.db.get_sites().await {
+        Ok(sites) => HttpResponse::Ok().json(sites),
+        Err(err) => error_response(err),
+    }
+}
+
+#[post("/sites")]
+async fn new_site(session: SuperuserSession, input: web::Json<SiteInput>) -> impl Responder {
+    let input = input.into_inner();
+    let site = schema::Site {
+        id: Uuid::now_v7(),
+        host: input.host.to_lowercase(),
+        name: input.name,
+        is_default: input.is_default,
+        default_locale: input.default_locale.to_lowercase(),
+        require_review: input.require_review,
+    };
+
+    match session.db.new_site(site.clone()).await {
+        Ok(()) => HttpResponse::Created().json(site),
+        Err(err) => error_response(err),
+    }
+}
+
+#[put("/sites/{id}")]
+async fn set_site(
+    id: web::Path<Uuid>,
+    session: SuperuserSession,
+    input: web::Json<SiteInput>,
+) -> impl Responder {
+    let input = input.into_inner();
+    let site = schema::Site {
+        id: id.into_inner(),
+        host: input.host.to_lowercase(),
+        name: input.name,
+        is_default: input.is_default,
+        default_locale: input.default_locale.to_lowercase(),
+        require_review: input.require_review,
+    };
+
+    match session.db.set_site(site.clone()).await {
+        Ok(()) => HttpResponse::Ok().json(site),
+        Err(err) => error_response(err),
+    }
+}
+
+// Deleting a site deletes all of its pages, menus and redirects with it
+#[delete("/sites/{id}")]
+async fn delete_site(id: web::Path<Uuid>, session: SuperuserSession) -> impl Responder {
+    match session.db.delete_site(id.into_inner()).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    }
+}
+
+#[get("/users/{id}/sites")]
+async fn get_site_grants(
+    id: web::Path<Uuid>,
+    _: SuperuserSession,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    match data.db.get_site_grants(id.into_inner()).await {
+        Ok(site_ids) => HttpResponse::Ok().json(site_ids),
+        Err(err) => error_response(err),
+    }
+}
+
+#[put("/users/{id}/sites/{site_id}")]
+async fn new_site_grant(
+    path: web::Path<(Uuid, Uuid)>,
+    session: SuperuserSession,
+) -> impl Responder {
+    let (id, site_id) = path.into_inner();
+
+    match session.db.new_site_grant(id, site_id).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    }
+}
+
+#[delete("/users/{id}/sites/{site_id}")]
+async fn delete_site_grant(
+    path: web::Path<(Uuid, Uuid)>,
+    session: SuperuserSession,
+) -> impl Responder {
+    let (id, site_id) = path.into_inner();
+
+    match session.db.delete_site_grant(id, site_id).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    }
+}
+
+// The whole site as a tar archive, see transfer::archive
+#[get("/export")]
+async fn export_site(session: AdminSession, data: web::Data<AppState>) -> impl Responder {
+    if !session.user.superuser {
+        return HttpResponse::Forbidden().json(error_json("Forbidden"));
+    }
+
+    let export = match data.db.export_site(session.site.id).await {
+        Ok(export) => export,
+        Err(err) => return error_response(err),
+    };
+
+    match archive::pack(&export) {
+        Ok(bytes) => HttpResponse::Ok()
+            .content_type("application/x-tar")
+            .insert_header((
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"{}-{}.tar\"",
+                    session.site.host,
+                    Utc::now().format("%Y%m%d%H%M%S")
+                ),
+            ))
+            .body(bytes),
+        Err(err) => error_response(err),
+    }
+}
+
+// Takes an archive from /export as the raw body and answers with what was done
+#[post("/import")]
+async fn import_site(
+    session: AdminSession,
+    options: web::Query<schema::ImportOptions>,
+    body: web::Bytes,
+) -> impl Responder {
+    if !session.user.superuser {
+        return HttpResponse::Forbidden().json(error_json("Forbidden"));
+    }
+
+    let export = match archive::unpack(&body) {
+        Ok(export) => export,
+        Err(err) => return HttpResponse::BadRequest().json(error_json(err.to_string())),
+    };
+
+    match session
+        .db
+        .import_site(session.site.id, export, options.into_inner())
+        .await
+    {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(err) => error_response(err),
+    }
+}
+
+// A WordPress export (WXR) as the raw body, items without a known author are the user's
+#[post("/import/wordpress")]
+async fn import_wordpress(
+    session: AdminSession,
+    options: web::Query<schema::ImportOptions>,
+    body: web::Bytes,
+) -> impl Responder {
+    if !session.user.superuser {
+        return HttpResponse::Forbidden().json(error_json("Forbidden"));
+    }
+
+    let xml = match std::str::from_utf8(&body) {
+        Ok(xml) => xml,
+        Err(_) => return HttpResponse::BadRequest().json(error_json("WXR has to be UTF-8")),
+    };
+    let converted = match wxr::convert(xml, session.site.id, &session.user) {
+        Ok(converted) => converted,
+        Err(err) => return HttpResponse::BadRequest().json(error_json(err.to_string())),
+    };
+
+    match transfer::import(
+        &session.db,
+        session.site.id,
+        converted,
+        options.into_inner(),
+    )
+    .await
+    {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(err) => error_response(err),
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/admin/api")
+            .service(list_pages)
+            .service(get_page)
+            .service(new_page)
+            .service(set_page)
+            .service(delete_page)
+            .service(get_page_trash)
+            .service(restore_page)
+            .service(purge_page)
+            .service(bulk_pages)
+            .service(get_page_drafts)
+            .service(get_page_draft)
+            .service(set_page_draft)
+            .service(delete_page_draft)
+            .service(transition_page_draft)
+            .service(set_page_draft_reviewer)
+            .service(get_workflow_comments)
+            .service(new_workflow_comment)
+            .service(get_workflow_transitions)
+            .service(set_workflow_transitions)
+            .service(get_page_children)
+            .service(get_page_translations)
+            .service(set_page_translation)
+            .service(delete_page_translation)
+            .service(get_redirects)
+            .service(new_redirect)
+            .service(delete_redirect)
+            .service(get_menus)
+            .service(set_menu)
+            .service(delete_menu)
+            .service(get_blocks)
+            .service(get_block)
+            .service(set_block)
+            .service(delete_block)
+            .service(get_content_types)
+            .service(get_content_type)
+            .service(set_content_type)
+            .service(delete_content_type)
+            .service(get_api_keys)
+            .service(new_api_key)
+            .service(delete_api_key)
+            .service(get_webhooks)
+            .service(new_webhook)
+            .service(set_webhook)
+            .service(delete_webhook)
+            .service(get_webhook_deliveries)
+            .service(replay_webhook_delivery)
+            .service(get_audit_log)
+            .service(get_users)
+            .service(get_user)
+            .service(new_user)
+            .service(set_user)
+            .service(delete_user)
+            .service(get_user_trash)
+            .service(restore_user)
+            .service(purge_user)
+            .service(new_token)
+            .service(get_sites)
+            .service(new_site)
+            .service(set_site)
+            .service(delete_site)
+            .service(get_site_grants)
+            .service(new_site_grant)
+            .service(delete_site_grant)
+            .service(export_site)
+            .service(import_site)
+            .service(import_wordpress)
+            // Archives and exports sent to the import endpoints are far larger than other bodies
+            .app_data(web::PayloadConfig::new(MAX_IMPORT_BYTES)),
+    );
+}
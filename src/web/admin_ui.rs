@@ -0,0 +1,73 @@
+/*
+ * web/admin_ui.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * The admin single page app. Its files are compiled into the binary,
+ * everything it does goes through the admin API.
+ */
+
+use actix_web::{get, http::header, web, HttpResponse, Responder};
+
+const INDEX_HTML: &str = include_str!("../../static/admin/index.html");
+const APP_JS: &str = include_str!("../../static/admin/app.js");
+const APP_CSS: &str = include_str!("../../static/admin/app.css");
+// The app holds an admin's token, so nothing but its own script may run. Page
+// bodies in the editor and preview bring inline styles and images from anywhere.
+const CONTENT_SECURITY_POLICY: &str = "default-src 'self'; script-src 'self'; \
+    style-src 'self' 'unsafe-inline'; img-src 'self' data: https: http:; \
+    frame-src 'self'; object-src 'none'; base-uri 'none'; form-action 'self'; \
+    frame-ancestors 'none'";
+
+fn asset(content_type: &str, body: &'static str) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(content_type)
+        // Files change with the binary, so browsers have to revalidate
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .insert_header((header::X_FRAME_OPTIONS, "DENY"))
+        .insert_header((header::CONTENT_SECURITY_POLICY, CONTENT_SECURITY_POLICY))
+        .body(body)
+}
+
+#[get("/admin")]
+async fn index() -> impl Responder {
+    asset("text/html; charset=utf-8", INDEX_HTML)
+}
+
+#[get("/admin/app.js")]
+async fn app_js() -> impl Responder {
+    asset("text/javascript; charset=utf-8", APP_JS)
+}
+
+#[get("/admin/app.css")]
+async fn app_css() -> impl Responder {
+    asset("text/css; charset=utf-8", APP_CSS)
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(index).service(app_js).service(app_css);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    #[actix_web::test]
+    async fn serves_assets_with_a_content_security_policy() {
+        let app = test::init_service(App::new().configure(config)).await;
+
+        for uri in ["/admin", "/admin/app.js", "/admin/app.css"] {
+            let response =
+                test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert!(response.status().is_success(), "{}", uri);
+
+            let policy = response
+                .headers()
+                .get(header::CONTENT_SECURITY_POLICY)
+                .unwrap();
+            assert!(policy.to_str().unwrap().contains("script-src 'self';"));
+        }
+    }
+}
@@ -0,0 +1,208 @@
+/*
+ * web/content_api.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * Read-only JSON API mounted under /api/v1 for using Magnetite headless.
+ * Published pages are public, an "X-Api-Key: <key>" header for the site
+ * makes unpublished pages readable too.
+ */
+
+use super::{
+    admin_api::{error_json, error_response},
+    resolve_site, AppState, ERROR_PAGE_PREFIX,
+};
+use crate::{database::schema, util::token};
+use actix_web::{
+    dev::Payload,
+    error::{ErrorInternalServerError, ErrorNotFound, ErrorUnauthorized},
+    get, web, FromRequest, HttpRequest, HttpResponse, Responder,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{future::Future, pin::Pin};
+
+const API_KEY_HEADER: &str = "X-Api-Key";
+const DEFAULT_LIMIT: i64 = 20;
+pub const MAX_LIMIT: i64 = 100;
+
+// The site a request reads from and whether it carried a valid key for it
+pub struct ApiClient {
+    pub site: schema::Site,
+    pub private: bool,
+}
+
+impl FromRequest for ApiClient {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move {
+            let data = match req.app_data::<web::Data<AppState>>() {
+                Some(data) => data.clone(),
+                None => return Err(ErrorInternalServerError("500 Internal Server Error")),
+            };
+
+            let site = match resolve_site(&req, &data).await {
+                Ok(site) => site,
+                Err(err) => match err.downcast_ref::<sqlx::Error>() {
+                    Some(sqlx::Error::RowNotFound) => return Err(ErrorNotFound("404 Not Found")),
+                    _ => return Err(ErrorInternalServerError("500 Internal Server Error")),
+                },
+            };
+
+            let key = match req.headers().get(API_KEY_HEADER) {
+                Some(value) => match value.to_str() {
+                    Ok(value) => value.trim().to_string(),
+                    Err(_) => return Err(ErrorUnauthorized("401 Unauthorized")),
+                },
+                None => {
+                    return Ok(ApiClient {
+                        site,
+                        private: false,
+                    })
+                }
+            };
+
+            // A key that is sent has to be valid, silently falling back to public would hide mistakes
+            match data.db.get_api_key_by_hash(token::hash(&key)).await {
+                Ok(api_key) if api_key.site_id == site.id => Ok(ApiClient {
+                    site,
+                    private: true,
+                }),
+                Ok(_) => Err(ErrorUnauthorized("401 Unauthorized")),
+                Err(err) => match err.downcast_ref::<sqlx::Error>() {
+                    Some(sqlx::Error::RowNotFound) => Err(ErrorUnauthorized("401 Unauthorized")),
+                    _ => Err(ErrorInternalServerError("500 Internal Server Error")),
+                },
+            }
+        })
+    }
+}
+
+fn default_prefix() -> String {
+    String::from("/")
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    #[serde(default = "default_prefix")]
+    prefix: String,
+    limit: Option<i64>,
+    #[serde(default)]
+    offset: i64,
+    // Comma separated, e.g. fields=path,metadata
+    fields: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PageQuery {
+    fields: Option<String>,
+}
+
+// Serializes the page keeping only the requested keys, Err names a key pages don't have
+fn select_fields(page: &schema::Page, fields: Option<&str>) -> Result<Value, String> {
+    let value = json!(page);
+    let fields = match fields {
+        Some(fields) if !fields.trim().is_empty() => fields,
+        _ => return Ok(value),
+    };
+
+    let object = match value {
+        Value::Object(object) => object,
+        value => return Ok(value),
+    };
+
+    let mut selected = serde_json::Map::new();
+    for field in fields.split(',').map(str::trim) {
+        match object.get(field) {
+            Some(value) => {
+                selected.insert(field.to_string(), value.clone());
+            }
+            None => return Err(format!("Unknown field {}", field)),
+        }
+    }
+
+    Ok(Value::Object(selected))
+}
+
+// Pages in path order, next_offset is null on the last page of results
+#[get("/pages")]
+async fn list_pages(
+    client: ApiClient,
+    query: web::Query<ListQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = query.offset.max(0);
+
+    // One extra row tells whether there is a next page
+    let mut pages = match data
+        .db
+        .get_pages_by_prefix(
+            client.site.id,
+            query.prefix.as_str(),
+            client.private,
+            limit + 1,
+            offset,
+        )
+        .await
+    {
+        Ok(pages) => pages,
+        Err(err) => return error_response(err),
+    };
+
+    let next_offset = if pages.len() as i64 > limit {
+        pages.truncate(limit as usize);
+        Some(offset + limit)
+    } else {
+        None
+    };
+
+    let mut results = Vec::with_capacity(pages.len());
+    for page in &pages {
+        match select_fields(page, query.fields.as_deref()) {
+            Ok(value) => results.push(value),
+            Err(msg) => return HttpResponse::BadRequest().json(error_json(msg)),
+        }
+    }
+
+    HttpResponse::Ok().json(json!({
+        "pages": results,
+        "limit": limit,
+        "offset": offset,
+        "next_offset": next_offset,
+    }))
+}
+
+#[get("/pages/{path:.*}")]
+async fn get_page(
+    req: HttpRequest,
+    client: ApiClient,
+    query: web::Query<PageQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let path = format!("/{}", req.match_info().get("path").unwrap_or_default());
+
+    let page = match data.db.get_page(client.site.id, &path, false).await {
+        Ok(page) => page,
+        Err(err) => return error_response(err),
+    };
+
+    // Unpublished pages look the same as missing ones without a key
+    if !client.private && (!page.published || page.path.starts_with(ERROR_PAGE_PREFIX)) {
+        return HttpResponse::NotFound().json(error_json("Not Found"));
+    }
+
+    match select_fields(&page, query.fields.as_deref()) {
+        Ok(value) => HttpResponse::Ok().json(value),
+        Err(msg) => HttpResponse::BadRequest().json(error_json(msg)),
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/api/v1").service(list_pages).service(get_page));
+}
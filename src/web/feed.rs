@@ -0,0 +1,219 @@
+/*
+ * web/feed.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * RSS 2.0 and Atom 1.0 feeds for every page under a configured prefix.
+ * Feeds are served at <prefix>rss.xml and <prefix>atom.xml.
+ */
+
+use super::{base_url, error_page, html::escape, resolve_site, AppState};
+use crate::database::schema;
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const FEED_LIMIT: i64 = 50;
+
+pub const RSS_FILE: &str = "rss.xml";
+pub const ATOM_FILE: &str = "atom.xml";
+
+struct Feed {
+    base_url: String,
+    prefix: String,
+    title: String,
+    entries: Vec<(schema::Page, String)>,
+}
+
+async fn load_feed(
+    req: &HttpRequest,
+    site: &schema::Site,
+    data: &AppState,
+    file: &str,
+) -> Option<Feed> {
+    let prefix = req.path().strip_suffix(file)?.to_string();
+
+    let pages = data
+        .db
+        .list_pages(
+            site.id,
+            schema::PageListQuery {
+                prefix: Some(prefix.clone()),
+                published: Some(true),
+                limit: Some(FEED_LIMIT),
+                ..Default::default()
+            },
+        )
+        .await
+        .ok()?;
+
+    // Authors are loaded in one query instead of one per entry
+    let mut ids: Vec<Uuid> = pages.iter().map(|page| page.created_by).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    let authors: HashMap<Uuid, String> = data
+        .db
+        .get_users_by_id(ids)
+        .await
+        .ok()?
+        .into_iter()
+        .map(|user| (user.id, user.username))
+        .collect();
+
+    let entries = pages
+        .into_iter()
+        .map(|page| {
+            let author = match authors.get(&page.created_by) {
+                Some(username) => username.clone(),
+                None => String::from("Unknown"),
+            };
+            (page, author)
+        })
+        .collect();
+
+    // The page sitting at the prefix itself (e.g. "/news") names the feed
+    let title = match data
+        .db
+        .get_page(site.id, prefix.trim_end_matches('/'), false)
+        .await
+    {
+        Ok(page) if page.published => page.title(),
+        _ => None,
+    }
+    .unwrap_or_else(|| prefix.clone());
+
+    Some(Feed {
+        base_url: base_url(req, data),
+        prefix,
+        title,
+        entries,
+    })
+}
+
+pub async fn rss(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    let site = match resolve_site(&req, &data).await {
+        Ok(site) => site,
+        Err(_) => return error_page(StatusCode::NOT_FOUND, None, &data).await,
+    };
+    let feed = match load_feed(&req, &site, &data, RSS_FILE).await {
+        Some(feed) => feed,
+        None => return error_page(StatusCode::INTERNAL_SERVER_ERROR, Some(&site), &data).await,
+    };
+
+    let mut items = String::new();
+    for (page, author) in &feed.entries {
+        let link = escape(&format!("{}{}", feed.base_url, page.path));
+        let title = escape(&page.title().unwrap_or_else(|| page.path.clone()));
+        let summary = escape(&page.summary().unwrap_or_default());
+
+        items.push_str(&format!(
+            "
+        <item>
+            <title>{}</title>
+            <link>{}</link>
+            <guid isPermaLink=\"true\">{}</guid>
+            <description>{}</description>
+            <dc:creator>{}</dc:creator>
+            <pubDate>{}</pubDate>
+        </item>",
+            title,
+            link,
+            link,
+            summary,
+            escape(author),
+            page.created_at.and_utc().to_rfc2822()
+        ));
+    }
+
+    let xml_string = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">
+    <channel>
+        <title>{}</title>
+        <link>{}</link>
+        <description>{}</description>
+        <atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\" />{}
+    </channel>
+</rss>
+",
+        escape(&feed.title),
+        escape(&format!("{}{}", feed.base_url, feed.prefix)),
+        escape(&feed.title),
+        escape(&format!("{}{}", feed.base_url, req.path())),
+        items
+    );
+
+    HttpResponse::Ok()
+        .content_type("application/rss+xml; charset=utf-8")
+        .body(xml_string)
+}
+
+pub async fn atom(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    let site = match resolve_site(&req, &data).await {
+        Ok(site) => site,
+        Err(_) => return error_page(StatusCode::NOT_FOUND, None, &data).await,
+    };
+    let feed = match load_feed(&req, &site, &data, ATOM_FILE).await {
+        Some(feed) => feed,
+        None => return error_page(StatusCode::INTERNAL_SERVER_ERROR, Some(&site), &data).await,
+    };
+
+    let updated = feed
+        .entries
+        .iter()
+        .map(|(page, _)| page.modified_at)
+        .max()
+        .unwrap_or_else(|| Utc::now().naive_utc());
+
+    let mut entries = String::new();
+    for (page, author) in &feed.entries {
+        let link = escape(&format!("{}{}", feed.base_url, page.path));
+        let title = escape(&page.title().unwrap_or_else(|| page.path.clone()));
+        let summary = escape(&page.summary().unwrap_or_default());
+
+        entries.push_str(&format!(
+            "
+    <entry>
+        <title>{}</title>
+        <link href=\"{}\" />
+        <id>{}</id>
+        <author><name>{}</name></author>
+        <published>{}</published>
+        <updated>{}</updated>
+        <summary>{}</summary>
+    </entry>",
+            title,
+            link,
+            link,
+            escape(author),
+            page.created_at.and_utc().to_rfc3339(),
+            page.modified_at.and_utc().to_rfc3339(),
+            summary
+        ));
+    }
+
+    let self_link = escape(&format!("{}{}", feed.base_url, req.path()));
+    let xml_string = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<feed xmlns=\"http://www.w3.org/2005/Atom\">
+    <title>{}</title>
+    <link href=\"{}\" />
+    <link href=\"{}\" rel=\"self\" />
+    <id>{}</id>
+    <updated>{}</updated>{}
+</feed>
+",
+        escape(&feed.title),
+        escape(&format!("{}{}", feed.base_url, feed.prefix)),
+        self_link,
+        self_link,
+        updated.and_utc().to_rfc3339(),
+        entries
+    );
+
+    HttpResponse::Ok()
+        .content_type("application/atom+xml; charset=utf-8")
+        .body(xml_string)
+}
@@ -0,0 +1,385 @@
+/*
+ * web/graphql.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * Read-only GraphQL schema served at /api/graphql.
+ * Access rules are the same as the JSON API in content_api.rs.
+ * Pages and users are fetched through per-request data loaders, so every
+ * page or author a query touches at one level costs a single database command.
+ */
+
+use super::{
+    content_api::{ApiClient, MAX_LIMIT},
+    AppState, ERROR_PAGE_PREFIX,
+};
+use crate::database::{schema, Database};
+use actix_web::{web, HttpResponse, Responder};
+use async_graphql::{
+    dataloader::{DataLoader, Loader},
+    http::GraphiQLSource,
+    Context, EmptyMutation, EmptySubscription, Error, Json, Object, Result, Schema,
+};
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
+use chrono::NaiveDateTime;
+use serde_json::Value;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// Keeps deeply nested parent/children queries from walking the whole site
+const MAX_DEPTH: usize = 12;
+
+pub type ContentSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+fn visible(page: &schema::Page, private: bool) -> bool {
+    private || (page.published && !page.path.starts_with(ERROR_PAGE_PREFIX))
+}
+
+pub struct PageLoader {
+    db: Database,
+    site_id: Uuid,
+    private: bool,
+}
+
+impl Loader<String> for PageLoader {
+    type Value = schema::Page;
+    type Error = Error;
+
+    async fn load(&self, paths: &[String]) -> Result<HashMap<String, schema::Page>> {
+        let pages = self
+            .db
+            .get_pages_by_path(self.site_id, paths.to_vec())
+            .await
+            .map_err(|err| Error::new(err.to_string()))?;
+
+        Ok(pages
+            .into_iter()
+            .filter(|page| visible(page, self.private))
+            .map(|page| (page.path.clone(), page))
+            .collect())
+    }
+}
+
+// Children of every requested parent in sort order, keyed by the parent's path
+pub struct ChildrenLoader {
+    db: Database,
+    site_id: Uuid,
+    private: bool,
+}
+
+impl Loader<String> for ChildrenLoader {
+    type Value = Vec<schema::Page>;
+    type Error = Error;
+
+    async fn load(&self, paths: &[String]) -> Result<HashMap<String, Vec<schema::Page>>> {
+        let children = self
+            .db
+            .get_children_of_pages(self.site_id, paths.to_vec())
+            .await
+            .map_err(|err| Error::new(err.to_string()))?;
+
+        // Children name their parent without a trailing slash, the root as "/"
+        let keys: HashMap<String, &String> = paths
+            .iter()
+            .map(|path| match path.trim_end_matches('/') {
+                "" => (String::from("/"), path),
+                trimmed => (trimmed.to_string(), path),
+            })
+            .collect();
+
+        // Parents without children still get an entry
+        let mut parents: HashMap<String, Vec<schema::Page>> = paths
+            .iter()
+            .map(|path| (path.clone(), Vec::new()))
+            .collect();
+        for page in children {
+            if !visible(&page, self.private) {
+                continue;
+            }
+
+            if let Some(siblings) = page
+                .ancestor_paths()
+                .pop()
+                .and_then(|parent| keys.get(&parent))
+                .and_then(|key| parents.get_mut(*key))
+            {
+                siblings.push(page);
+            }
+        }
+
+        Ok(parents)
+    }
+}
+
+pub struct UserLoader {
+    db: Database,
+}
+
+impl Loader<Uuid> for UserLoader {
+    type Value = schema::AdminUser;
+    type Error = Error;
+
+    async fn load(&self, ids: &[Uuid]) -> Result<HashMap<Uuid, schema::AdminUser>> {
+        let users = self
+            .db
+            .get_users_by_id(ids.to_vec())
+            .await
+            .map_err(|err| Error::new(err.to_string()))?;
+
+        Ok(users.into_iter().map(|user| (user.id, user)).collect())
+    }
+}
+
+pub struct Page(schema::Page);
+
+#[Object]
+impl Page {
+    async fn path(&self) -> &str {
+        &self.0.path
+    }
+
+    async fn published(&self) -> bool {
+        self.0.published
+    }
+
+    async fn title(&self) -> Option<String> {
+        self.0.title()
+    }
+
+    async fn summary(&self) -> Option<String> {
+        self.0.summary()
+    }
+
+    async fn metadata(&self) -> &[String] {
+        &self.0.metadata
+    }
+
+    async fn body(&self) -> &str {
+        &self.0.body
+    }
+
+    async fn sort_order(&self) -> i32 {
+        self.0.sort_order
+    }
+
+    async fn created_at(&self) -> NaiveDateTime {
+        self.0.created_at
+    }
+
+    async fn modified_at(&self) -> NaiveDateTime {
+        self.0.modified_at
+    }
+
+    async fn content_type(&self) -> Option<&str> {
+        self.0.content_type.as_deref()
+    }
+
+    async fn fields(&self) -> Json<Value> {
+        Json(self.0.fields.clone())
+    }
+
+    async fn author(&self, ctx: &Context<'_>) -> Result<Option<User>> {
+        let users = ctx.data::<DataLoader<UserLoader>>()?;
+        Ok(users.load_one(self.0.created_by).await?.map(User))
+    }
+
+    async fn modified_by(&self, ctx: &Context<'_>) -> Result<Option<User>> {
+        let users = ctx.data::<DataLoader<UserLoader>>()?;
+        Ok(users.load_one(self.0.modified_by).await?.map(User))
+    }
+
+    async fn parent(&self, ctx: &Context<'_>) -> Result<Option<Page>> {
+        let path = match self.0.ancestor_paths().pop() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        let pages = ctx.data::<DataLoader<PageLoader>>()?;
+        Ok(pages.load_one(path).await?.map(Page))
+    }
+
+    // From the root down, for breadcrumbs. Missing and hidden ancestors are skipped.
+    async fn ancestors(&self, ctx: &Context<'_>) -> Result<Vec<Page>> {
+        let paths = self.0.ancestor_paths();
+        let pages = ctx.data::<DataLoader<PageLoader>>()?;
+        let mut found = pages.load_many(paths.iter().cloned()).await?;
+
+        Ok(paths
+            .iter()
+            .filter_map(|path| found.remove(path))
+            .map(Page)
+            .collect())
+    }
+
+    // In sort order
+    async fn children(&self, ctx: &Context<'_>) -> Result<Vec<Page>> {
+        let children = ctx.data::<DataLoader<ChildrenLoader>>()?;
+        let children = children.load_one(self.0.path.clone()).await?;
+
+        Ok(children.unwrap_or_default().into_iter().map(Page).collect())
+    }
+}
+
+// Only what is safe to show next to public content, no email addresses
+pub struct User(schema::AdminUser);
+
+#[Object]
+impl User {
+    async fn id(&self) -> Uuid {
+        self.0.id
+    }
+
+    async fn username(&self) -> &str {
+        &self.0.username
+    }
+}
+
+pub struct Menu(schema::Menu);
+
+#[Object]
+impl Menu {
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn items(&self) -> Vec<MenuItem> {
+        self.0.items.iter().cloned().map(MenuItem).collect()
+    }
+}
+
+pub struct MenuItem(schema::MenuItem);
+
+#[Object]
+impl MenuItem {
+    async fn label(&self) -> &str {
+        &self.0.label
+    }
+
+    async fn href(&self) -> &str {
+        self.0.href()
+    }
+
+    // The linked page, null for external links and pages that can't be seen
+    async fn page(&self, ctx: &Context<'_>) -> Result<Option<Page>> {
+        let path = match &self.0.page_path {
+            Some(path) => path.clone(),
+            None => return Ok(None),
+        };
+
+        let pages = ctx.data::<DataLoader<PageLoader>>()?;
+        Ok(pages.load_one(path).await?.map(Page))
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn page(&self, ctx: &Context<'_>, path: String) -> Result<Option<Page>> {
+        let pages = ctx.data::<DataLoader<PageLoader>>()?;
+        Ok(pages.load_one(path).await?.map(Page))
+    }
+
+    // In path order, like GET /api/v1/pages
+    async fn pages(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = "/")] prefix: String,
+        #[graphql(default = 20)] limit: i64,
+        #[graphql(default = 0)] offset: i64,
+    ) -> Result<Vec<Page>> {
+        let client = ctx.data::<ApiClient>()?;
+        let pages = ctx
+            .data::<Database>()?
+            .get_pages_by_prefix(
+                client.site.id,
+                prefix,
+                client.private,
+                limit.clamp(1, MAX_LIMIT),
+                offset.max(0),
+            )
+            .await
+            .map_err(|err| Error::new(err.to_string()))?;
+
+        Ok(pages.into_iter().map(Page).collect())
+    }
+
+    async fn menus(&self, ctx: &Context<'_>) -> Result<Vec<Menu>> {
+        let client = ctx.data::<ApiClient>()?;
+        let menus = ctx
+            .data::<Database>()?
+            .get_menus(client.site.id)
+            .await
+            .map_err(|err| Error::new(err.to_string()))?;
+
+        Ok(menus.into_iter().map(Menu).collect())
+    }
+
+    async fn menu(&self, ctx: &Context<'_>, name: String) -> Result<Option<Menu>> {
+        Ok(self
+            .menus(ctx)
+            .await?
+            .into_iter()
+            .find(|menu| menu.0.name == name))
+    }
+}
+
+async fn graphql(
+    client: ApiClient,
+    request: GraphQLRequest,
+    content_schema: web::Data<ContentSchema>,
+    data: web::Data<AppState>,
+) -> GraphQLResponse {
+    // Loaders live for one request so private pages never leak into public ones
+    let pages = DataLoader::new(
+        PageLoader {
+            db: data.db.clone(),
+            site_id: client.site.id,
+            private: client.private,
+        },
+        tokio::spawn,
+    );
+    let children = DataLoader::new(
+        ChildrenLoader {
+            db: data.db.clone(),
+            site_id: client.site.id,
+            private: client.private,
+        },
+        tokio::spawn,
+    );
+    let users = DataLoader::new(
+        UserLoader {
+            db: data.db.clone(),
+        },
+        tokio::spawn,
+    );
+
+    let request = request
+        .into_inner()
+        .data(pages)
+        .data(children)
+        .data(users)
+        .data(data.db.clone())
+        .data(client);
+
+    content_schema.execute(request).await.into()
+}
+
+async fn graphiql() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(GraphiQLSource::build().endpoint("/api/graphql").finish())
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    let content_schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_DEPTH)
+        .finish();
+
+    cfg.app_data(web::Data::new(content_schema)).service(
+        web::resource("/api/graphql")
+            .route(web::post().to(graphql))
+            .route(web::get().to(graphiql)),
+    );
+}
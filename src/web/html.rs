@@ -5,27 +5,321 @@
  * See the file "LICENSE" in the root of this project.
  */
 
-use crate::database::schema;
-use actix_web::{http::header::ContentType, HttpResponse};
+use super::{shortcode, AppState};
+use crate::database::{schema, Database};
+use actix_web::{
+    http::{
+        header::{self, ContentType},
+        StatusCode,
+    },
+    HttpResponse,
+};
 
-pub async fn page_to_response(page: schema::Page) -> HttpResponse {
-    let metadata = page.metadata.join("\n");
-    let html_string = format!(
+// Menus with these names are placed in the header and footer of every page
+const HEADER_MENU: &str = "main";
+const FOOTER_MENU: &str = "footer";
+// Same for blocks
+const HEADER_BLOCK: &str = "header";
+const FOOTER_BLOCK: &str = "footer";
+
+pub struct Breadcrumb {
+    pub label: String,
+    // None for the current page and for ancestors that aren't published
+    pub href: Option<String>,
+}
+
+// A link to the same page in another locale
+pub struct Alternate {
+    // A locale, or "x-default" for the negotiated bare path
+    pub hreflang: String,
+    pub href: String,
+}
+
+// Everything the layout needs besides the page itself
+pub struct RenderContext {
+    pub breadcrumbs: Vec<Breadcrumb>,
+    pub menus: Vec<schema::Menu>,
+    pub locale: String,
+    pub alternates: Vec<Alternate>,
+}
+
+impl RenderContext {
+    // Navigation is decoration, so database errors leave it empty instead of failing the page
+    pub async fn load(site: &schema::Site, page: &schema::Page, db: &Database) -> RenderContext {
+        let mut context = RenderContext::menus_only(site, db).await;
+
+        let ancestor_paths = page.ancestor_paths();
+        if ancestor_paths.is_empty() {
+            return context;
+        }
+
+        let ancestors = db
+            .get_pages_by_path(page.site_id, ancestor_paths.clone())
+            .await
+            .unwrap_or_default();
+
+        for path in ancestor_paths {
+            let ancestor = ancestors
+                .iter()
+                .find(|ancestor| ancestor.path == path && ancestor.published);
+
+            let label = match ancestor.and_then(|ancestor| ancestor.title()) {
+                Some(title) => title,
+                None => path.rsplit('/').next().unwrap_or_default().to_string(),
+            };
+
+            context.breadcrumbs.push(Breadcrumb {
+                label: if label.is_empty() {
+                    String::from("Home")
+                } else {
+                    label
+                },
+                href: ancestor.map(|_| path),
+            });
+        }
+
+        context.breadcrumbs.push(Breadcrumb {
+            label: page.title().unwrap_or_else(|| page.path.clone()),
+            href: None,
+        });
+
+        context
+    }
+
+    pub async fn menus_only(site: &schema::Site, db: &Database) -> RenderContext {
+        RenderContext {
+            breadcrumbs: Vec::new(),
+            menus: db.get_menus(site.id).await.unwrap_or_default(),
+            locale: site.default_locale.clone(),
+            alternates: Vec::new(),
+        }
+    }
+
+    fn menu(&self, name: &str) -> Option<&schema::Menu> {
+        self.menus.iter().find(|menu| menu.name == name)
+    }
+}
+
+// A finished page and what has to change for it to be rendered again
+pub struct Rendered {
+    pub html: String,
+    pub blocks: Vec<String>,
+    pub pages: Vec<String>,
+}
+
+pub fn render_menu(menu: &schema::Menu, current_path: &str) -> String {
+    let mut items = String::new();
+    for item in &menu.items {
+        let current = if item.page_path.as_deref() == Some(current_path) {
+            " aria-current=\"page\""
+        } else {
+            ""
+        };
+
+        items.push_str(&format!(
+            "<li><a href=\"{}\"{}>{}</a></li>",
+            escape(item.href()),
+            current,
+            escape(&item.label)
+        ));
+    }
+
+    format!(
+        "<nav class=\"menu menu-{}\"><ul>{}</ul></nav>",
+        escape(&menu.name),
+        items
+    )
+}
+
+pub fn render_breadcrumbs(breadcrumbs: &[Breadcrumb]) -> String {
+    if breadcrumbs.is_empty() {
+        return String::new();
+    }
+
+    let mut items = String::new();
+    for breadcrumb in breadcrumbs {
+        match &breadcrumb.href {
+            Some(href) => items.push_str(&format!(
+                "<li><a href=\"{}\">{}</a></li>",
+                escape(href),
+                escape(&breadcrumb.label)
+            )),
+            None => items.push_str(&format!("<li>{}</li>", escape(&breadcrumb.label))),
+        }
+    }
+
+    format!(
+        "<nav class=\"breadcrumbs\" aria-label=\"Breadcrumb\"><ol>{}</ol></nav>",
+        items
+    )
+}
+
+pub async fn page_to_response(
+    page: schema::Page,
+    status: StatusCode,
+    context: RenderContext,
+    data: &AppState,
+) -> HttpResponse {
+    let rendered = render_page(page, &context, data).await;
+    html_response(status, context.locale, rendered.html)
+}
+
+pub fn html_response(status: StatusCode, locale: String, html: String) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type(ContentType::html())
+        .insert_header((header::CONTENT_LANGUAGE, locale))
+        .body(html)
+}
+
+pub async fn render_page(
+    mut page: schema::Page,
+    context: &RenderContext,
+    data: &AppState,
+) -> Rendered {
+    data.plugins.before_render(&mut page).await;
+
+    let mut expansion = shortcode::Expansion::new(data, page.site_id, &page.path);
+    if let Some(name) = &page.content_type {
+        if let Ok(content_type) = data.db.get_content_type(page.site_id, name).await {
+            if !content_type.template.is_empty() {
+                page.body = content_type.template.replace("[[body]]", &page.body);
+            }
+            expansion = expansion.with_fields(content_type, page.fields.clone());
+        }
+    }
+    page.body = expansion.expand(&page.body).await;
+    let header_block = expansion.block(HEADER_BLOCK).await;
+    let footer_block = expansion.block(FOOTER_BLOCK).await;
+
+    let mut metadata = page.metadata.join("\n");
+    for alternate in &context.alternates {
+        metadata.push_str(&format!(
+            "\n<link rel=\"alternate\" hreflang=\"{}\" href=\"{}\">",
+            escape(&alternate.hreflang),
+            escape(&alternate.href)
+        ));
+    }
+
+    let mut header = header_block;
+    if let Some(menu) = context.menu(HEADER_MENU) {
+        header.push_str(&render_menu(menu, &page.path));
+    }
+    header.push_str(&render_breadcrumbs(&context.breadcrumbs));
+    if !header.is_empty() {
+        header = format!("<header>{}</header>", header);
+    }
+
+    let mut footer = String::new();
+    if let Some(menu) = context.menu(FOOTER_MENU) {
+        footer.push_str(&render_menu(menu, &page.path));
+    }
+    footer.push_str(&footer_block);
+    if !footer.is_empty() {
+        footer = format!("<footer>{}</footer>", footer);
+    }
+
+    let mut html_string = format!(
         "
     <!DOCTYPE html>
-    <html>
+    <html lang=\"{}\">
         <head>
             {}
         </head>
         <body>
             {}
+            {}
+            {}
         </body>
     </html>
     ",
-        metadata, page.body
+        escape(&context.locale),
+        metadata,
+        header,
+        page.body,
+        footer
+    );
+
+    data.plugins.after_render(&page, &mut html_string).await;
+
+    // Breadcrumbs show the titles of the ancestors
+    let mut pages = expansion.pages;
+    pages.extend(page.ancestor_paths());
+
+    Rendered {
+        html: html_string,
+        blocks: expansion.blocks,
+        pages,
+    }
+}
+
+// Used when no published page exists under /_errors/ for the status
+pub fn builtin_error_page(status: StatusCode) -> HttpResponse {
+    let title = format!(
+        "{} {}",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or("Error")
     );
+    let message = if status.is_client_error() {
+        "The page you were looking for could not be found."
+    } else {
+        "Something went wrong on our end. Please try again later."
+    };
 
-    HttpResponse::Ok()
+    let html_string = format!(
+        "
+    <!DOCTYPE html>
+    <html lang=\"en\">
+        <head>
+            <meta charset=\"utf-8\">
+            <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">
+            <title>{}</title>
+            <style>
+                body {{
+                    margin: 0;
+                    min-height: 100vh;
+                    display: flex;
+                    align-items: center;
+                    justify-content: center;
+                    font-family: system-ui, sans-serif;
+                    background: #f4f4f5;
+                    color: #27272a;
+                }}
+                main {{ text-align: center; padding: 2rem; }}
+                h1 {{ font-size: 3rem; margin: 0 0 1rem; }}
+                a {{ color: #52525b; }}
+            </style>
+        </head>
+        <body>
+            <main>
+                <h1>{}</h1>
+                <p>{}</p>
+                <p><a href=\"/\">Return home</a></p>
+            </main>
+        </body>
+    </html>
+    ",
+        title, title, message
+    );
+
+    HttpResponse::build(status)
         .content_type(ContentType::html())
         .body(html_string)
 }
+
+// Escapes the characters that are significant in both HTML and XML
+pub fn escape(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+
+    for c in input.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#39;"),
+            _ => output.push(c),
+        }
+    }
+
+    output
+}
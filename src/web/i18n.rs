@@ -0,0 +1,211 @@
+/*
+ * web/i18n.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * Pages are written in their site's default locale and may have translations.
+ * A translation of /about in "es" is served at /es/about, the bare path picks
+ * a locale from the Accept-Language header.
+ */
+
+use super::html::Alternate;
+use crate::database::schema;
+
+// Same rule as the CHECK constraint on page_translations.locale
+pub fn is_locale(tag: &str) -> bool {
+    let mut parts = tag.split('-');
+
+    let language = parts.next().unwrap_or_default();
+    if !(2..=3).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_lowercase()) {
+        return false;
+    }
+
+    parts.all(|part| {
+        (2..=8).contains(&part.len())
+            && part
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+    })
+}
+
+// /es/about -> ("es", "/about"), /es -> ("es", "/")
+pub fn split_prefix(path: &str) -> Option<(&str, String)> {
+    let trimmed = path.strip_prefix('/')?;
+    let (locale, rest) = match trimmed.find('/') {
+        Some(index) => (&trimmed[..index], &trimmed[index..]),
+        None => (trimmed, "/"),
+    };
+
+    if is_locale(locale) {
+        Some((locale, rest.to_string()))
+    } else {
+        None
+    }
+}
+
+pub fn localized_path(locale: &str, path: &str) -> String {
+    if path == "/" {
+        format!("/{}/", locale)
+    } else {
+        format!("/{}{}", locale, path)
+    }
+}
+
+// The default locale followed by every published translation
+pub fn available_locales<'a>(
+    site: &'a schema::Site,
+    translations: &'a [schema::PageTranslation],
+) -> Vec<&'a str> {
+    let mut locales = vec![site.default_locale.as_str()];
+    locales.extend(
+        translations
+            .iter()
+            .filter(|translation| translation.published)
+            .map(|translation| translation.locale.as_str()),
+    );
+    locales
+}
+
+// Picks the best available locale for an Accept-Language header.
+// "es-mx" falls back to "es" and "es" matches "es-mx" when nothing closer exists.
+pub fn negotiate<'a>(accept_language: &str, available: &[&'a str]) -> Option<&'a str> {
+    let mut wanted: Vec<(String, f32)> = accept_language
+        .split(',')
+        .filter_map(|entry| {
+            let mut params = entry.split(';');
+            let tag = params.next()?.trim().to_lowercase();
+            let quality = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+
+            if tag.is_empty() || tag == "*" || quality <= 0.0 {
+                None
+            } else {
+                Some((tag, quality))
+            }
+        })
+        .collect();
+
+    // sort_by is stable, so equal weights keep the client's order
+    wanted.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    for (tag, _) in &wanted {
+        if let Some(locale) = available.iter().find(|locale| **locale == tag) {
+            return Some(locale);
+        }
+
+        let language = tag.split('-').next().unwrap_or_default();
+        if let Some(locale) = available
+            .iter()
+            .find(|locale| **locale == language || locale.starts_with(&format!("{}-", language)))
+        {
+            return Some(locale);
+        }
+    }
+
+    None
+}
+
+// The page as served in the given locale, None when it has no published variant in it
+pub fn localize(
+    page: schema::Page,
+    site: &schema::Site,
+    translations: &[schema::PageTranslation],
+    locale: &str,
+) -> Option<schema::Page> {
+    if locale == site.default_locale {
+        return Some(page);
+    }
+
+    translations
+        .iter()
+        .find(|translation| translation.locale == locale && translation.published)
+        .map(|translation| page.translated(translation))
+}
+
+// hreflang links for every locale a page is published in.
+// Search engines want absolute URLs, so base is the scheme and host.
+pub fn alternates(
+    base: &str,
+    site: &schema::Site,
+    path: &str,
+    translations: &[schema::PageTranslation],
+) -> Vec<Alternate> {
+    let locales = available_locales(site, translations);
+    if locales.len() < 2 {
+        return Vec::new();
+    }
+
+    let mut alternates: Vec<Alternate> = locales
+        .into_iter()
+        .map(|locale| Alternate {
+            hreflang: locale.to_string(),
+            href: format!("{}{}", base, localized_path(locale, path)),
+        })
+        .collect();
+
+    alternates.push(Alternate {
+        hreflang: String::from("x-default"),
+        href: format!("{}{}", base, path),
+    });
+
+    alternates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_locales() {
+        for tag in ["en", "es", "haw", "es-mx", "zh-hant-tw", "de-1996"] {
+            assert!(is_locale(tag), "{}", tag);
+        }
+        for tag in [
+            "",
+            "e",
+            "english",
+            "EN",
+            "es-",
+            "es_mx",
+            "es-m",
+            "es-abcdefghi",
+        ] {
+            assert!(!is_locale(tag), "{}", tag);
+        }
+    }
+
+    #[test]
+    fn splits_locale_prefixes() {
+        assert_eq!(
+            split_prefix("/es/about"),
+            Some(("es", String::from("/about")))
+        );
+        assert_eq!(
+            split_prefix("/es-mx/a/b"),
+            Some(("es-mx", String::from("/a/b")))
+        );
+        assert_eq!(split_prefix("/es"), Some(("es", String::from("/"))));
+        assert_eq!(split_prefix("/es/"), Some(("es", String::from("/"))));
+        assert_eq!(split_prefix("/about"), None);
+        assert_eq!(split_prefix("/"), None);
+        assert_eq!(split_prefix("es/about"), None);
+
+        assert_eq!(localized_path("es", "/"), "/es/");
+        assert_eq!(localized_path("es", "/about"), "/es/about");
+    }
+
+    #[test]
+    fn negotiates_by_quality_and_language() {
+        let available = ["en", "es-mx", "fr"];
+
+        assert_eq!(negotiate("fr, en", &available), Some("fr"));
+        assert_eq!(negotiate("fr;q=0.5, en;q=0.8", &available), Some("en"));
+        assert_eq!(negotiate("es-ar, en;q=0.1", &available), Some("es-mx"));
+        assert_eq!(negotiate("EN-gb", &available), Some("en"));
+        assert_eq!(negotiate("de, fr;q=0, *", &available), None);
+        assert_eq!(negotiate("fr;q=nope, en;q=0.2", &available), Some("en"));
+        assert_eq!(negotiate("", &available), None);
+    }
+}
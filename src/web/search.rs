@@ -0,0 +1,123 @@
+/*
+ * web/search.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ */
+
+use super::{error_page, html::escape, resolve_site, AppState};
+use actix_web::{
+    get,
+    http::{header::ContentType, StatusCode},
+    web, HttpRequest, HttpResponse, Responder,
+};
+use quick_xml::escape::{resolve_html5_entity, unescape_with};
+use serde::Deserialize;
+
+const SEARCH_LIMIT: i64 = 20;
+// What SearchPages wraps matches in
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: Option<String>,
+}
+
+#[get("/search")]
+async fn search(
+    req: HttpRequest,
+    query: web::Query<SearchQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let q = query.q.clone().unwrap_or_default();
+    let q = q.trim();
+
+    let site = match resolve_site(&req, &data).await {
+        Ok(site) => site,
+        Err(_) => return error_page(StatusCode::NOT_FOUND, None, &data).await,
+    };
+
+    let results = if q.is_empty() {
+        Vec::new()
+    } else {
+        match data.db.search_pages(site.id, q, SEARCH_LIMIT).await {
+            Ok(results) => results,
+            Err(_) => {
+                return error_page(StatusCode::INTERNAL_SERVER_ERROR, Some(&site), &data).await
+            }
+        }
+    };
+
+    let mut list = String::new();
+    for result in &results {
+        list.push_str(&format!(
+            "
+                <li>
+                    <a href=\"{}\">{}</a>
+                    <p>{}</p>
+                </li>",
+            escape(&result.path),
+            escape(result.title.as_deref().unwrap_or(&result.path)),
+            snippet_html(&result.snippet)
+        ));
+    }
+
+    let summary = if q.is_empty() {
+        String::new()
+    } else {
+        format!("<p>{} results for \"{}\"</p>", results.len(), escape(q))
+    };
+
+    let html_string = format!(
+        "
+    <!DOCTYPE html>
+    <html lang=\"{}\">
+        <head>
+            <title>Search</title>
+        </head>
+        <body>
+            <form action=\"/search\" method=\"get\">
+                <input type=\"search\" name=\"q\" value=\"{}\" />
+                <button type=\"submit\">Search</button>
+            </form>
+            {}
+            <ol>{}
+            </ol>
+        </body>
+    </html>
+    ",
+        escape(&site.default_locale),
+        escape(q),
+        summary,
+        list
+    );
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(html_string)
+}
+
+// Snippets are cut out of page HTML, so the text between matches is decoded
+// and escaped as a whole. The only markup that makes it through is <mark>.
+fn snippet_html(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len());
+    let mut rest = snippet;
+
+    loop {
+        let end = rest.find([MATCH_START, MATCH_END]).unwrap_or(rest.len());
+        let text = &rest[..end];
+        html.push_str(&escape(
+            &unescape_with(text, resolve_html5_entity).unwrap_or(text.into()),
+        ));
+
+        match rest[end..].chars().next() {
+            Some(MATCH_START) => html.push_str("<mark>"),
+            Some(_) => html.push_str("</mark>"),
+            None => break,
+        }
+        rest = &rest[end + 1..];
+    }
+
+    html
+}
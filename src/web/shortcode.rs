@@ -0,0 +1,533 @@
+/*
+ * web/shortcode.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * Expands [[name args]] in page bodies, e.g.
+ * [[youtube dQw4w9WgXcQ]], [[gallery /a.jpg /b.jpg columns=2 id=team]], [[include /footer]],
+ * [[block cta]] or [[field start_date]].
+ * A gallery lists its images itself, its id only becomes the id of the element
+ * so a gallery without images turns into a comment rather than being looked up.
+ * Arguments are separated by spaces and either positional or key=value,
+ * values containing spaces can be wrapped in double quotes.
+ * Names nobody knows are left in the body untouched.
+ */
+
+use super::{html::escape, AppState};
+use crate::database::schema::{self, FieldKind};
+use serde_json::Value;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// How deep includes may nest before the innermost one is dropped
+const MAX_INCLUDE_DEPTH: usize = 8;
+// Upper bound on shortcodes expanded for a single page, includes count too
+const MAX_EXPANSIONS: usize = 256;
+
+type Builtin = fn(&Args) -> Option<String>;
+
+// Built-in shortcodes that don't need the database, include, block and field are handled separately
+const BUILTINS: &[(&str, Builtin)] = &[("youtube", youtube), ("gallery", gallery)];
+
+#[derive(Default)]
+struct Args {
+    positional: Vec<String>,
+    named: HashMap<String, String>,
+}
+
+impl Args {
+    // A named argument, falling back to the first positional one
+    fn get(&self, key: &str) -> Option<&str> {
+        self.named
+            .get(key)
+            .or(self.positional.first())
+            .map(String::as_str)
+    }
+}
+
+struct Shortcode<'a> {
+    name: &'a str,
+    // Everything after the name, as written
+    raw_args: &'a str,
+    args: Args,
+}
+
+// Keeps track of what is being expanded across nested includes
+// and which blocks and pages the output ended up depending on
+pub struct Expansion<'a> {
+    data: &'a AppState,
+    site_id: Uuid,
+    include_stack: Vec<String>,
+    block_stack: Vec<String>,
+    remaining: usize,
+    // Content type and field values of the page being rendered
+    content: Option<(schema::ContentType, Value)>,
+    pub blocks: Vec<String>,
+    pub pages: Vec<String>,
+}
+
+impl<'a> Expansion<'a> {
+    pub fn new(data: &'a AppState, site_id: Uuid, path: &str) -> Expansion<'a> {
+        Expansion {
+            data,
+            site_id,
+            include_stack: vec![path.to_string()],
+            block_stack: Vec::new(),
+            remaining: MAX_EXPANSIONS,
+            content: None,
+            blocks: Vec::new(),
+            pages: Vec::new(),
+        }
+    }
+
+    // Makes [[field name]] resolve against the page's values
+    pub fn with_fields(
+        mut self,
+        content_type: schema::ContentType,
+        fields: Value,
+    ) -> Expansion<'a> {
+        self.content = Some((content_type, fields));
+        self
+    }
+
+    pub async fn expand(&mut self, body: &str) -> String {
+        expand_body(body, self).await
+    }
+
+    // The expanded body of a block, empty when the block doesn't exist (yet)
+    pub async fn block(&mut self, name: &str) -> String {
+        if !self.blocks.iter().any(|block| block == name) {
+            self.blocks.push(name.to_string());
+        }
+
+        if self.block_stack.iter().any(|parent| parent == name) {
+            return format!("<!-- block cycle: {} -->", escape(name));
+        }
+
+        let block = match self.data.db.get_block(self.site_id, name).await {
+            Ok(block) => block,
+            Err(_) => return String::new(),
+        };
+
+        self.block_stack.push(block.name);
+        let html = Box::pin(expand_body(&block.body, self)).await;
+        self.block_stack.pop();
+
+        html
+    }
+}
+
+async fn expand_body(body: &str, expansion: &mut Expansion<'_>) -> String {
+    let mut output = String::with_capacity(body.len());
+    let mut rest = body;
+
+    while let Some(start) = rest.find("[[") {
+        let end = match rest[start..].find("]]") {
+            Some(end) => start + end,
+            None => break,
+        };
+
+        output.push_str(&rest[..start]);
+        let original = &rest[start..end + 2];
+        rest = &rest[end + 2..];
+
+        let shortcode = match parse(&original[2..original.len() - 2]) {
+            Some(shortcode) => shortcode,
+            None => {
+                output.push_str(original);
+                continue;
+            }
+        };
+
+        if expansion.remaining == 0 {
+            output.push_str("<!-- shortcode limit reached -->");
+            continue;
+        }
+        expansion.remaining -= 1;
+
+        match render(&shortcode, expansion).await {
+            Some(html) => output.push_str(&html),
+            None => output.push_str(original),
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+async fn render(shortcode: &Shortcode<'_>, expansion: &mut Expansion<'_>) -> Option<String> {
+    if shortcode.name == "include" {
+        return Some(include(shortcode.args.get("path")?, expansion).await);
+    }
+    if shortcode.name == "block" {
+        return Some(expansion.block(shortcode.args.get("name")?).await);
+    }
+    if shortcode.name == "field" {
+        return field(&shortcode.args, expansion).await;
+    }
+
+    if let Some((_, builtin)) = BUILTINS.iter().find(|(name, _)| *name == shortcode.name) {
+        return builtin(&shortcode.args);
+    }
+
+    expansion
+        .data
+        .plugins
+        .render_shortcode(expansion.site_id, shortcode.name, shortcode.raw_args)
+        .await
+}
+
+// Inserts the body of another published page of the same site
+async fn include(path: &str, expansion: &mut Expansion<'_>) -> String {
+    // Recorded before the lookup so creating or publishing the page shows up too
+    if !expansion.pages.iter().any(|page| page == path) {
+        expansion.pages.push(path.to_string());
+    }
+    if expansion.include_stack.iter().any(|parent| parent == path) {
+        return format!("<!-- include cycle: {} -->", escape(path));
+    }
+    if expansion.include_stack.len() > MAX_INCLUDE_DEPTH {
+        return format!("<!-- include too deep: {} -->", escape(path));
+    }
+
+    let page = match expansion
+        .data
+        .db
+        .get_page(expansion.site_id, path, false)
+        .await
+    {
+        Ok(page) if page.published => page,
+        _ => return format!("<!-- include not found: {} -->", escape(path)),
+    };
+
+    expansion.include_stack.push(page.path);
+    let html = Box::pin(expand_body(&page.body, expansion)).await;
+    expansion.include_stack.pop();
+
+    html
+}
+
+// A value of the page's content type, None for fields the type doesn't have
+async fn field(args: &Args, expansion: &mut Expansion<'_>) -> Option<String> {
+    let name = args.get("name")?;
+    let (content_type, fields) = expansion.content.as_ref()?;
+    let kind = content_type.field(name)?.kind;
+
+    let value = match fields.get(name) {
+        Some(Value::Null) | None => return Some(String::new()),
+        Some(Value::String(value)) => value.clone(),
+        Some(value) => value.to_string(),
+    };
+
+    Some(match kind {
+        FieldKind::Text | FieldKind::Number => escape(&value),
+        FieldKind::RichText => value,
+        FieldKind::Date => format!("<time datetime=\"{0}\">{0}</time>", escape(&value)),
+        FieldKind::Media => format!(
+            "<img src=\"{}\" alt=\"{}\" loading=\"lazy\">",
+            escape(&value),
+            escape(args.named.get("alt").map_or("", String::as_str))
+        ),
+        FieldKind::Reference => reference(&value, expansion).await,
+    })
+}
+
+// Links to another page by its title, nothing while it isn't published
+async fn reference(path: &str, expansion: &mut Expansion<'_>) -> String {
+    if !expansion.pages.iter().any(|page| page == path) {
+        expansion.pages.push(path.to_string());
+    }
+
+    match expansion
+        .data
+        .db
+        .get_page(expansion.site_id, path, false)
+        .await
+    {
+        Ok(page) if page.published => format!(
+            "<a href=\"{}\">{}</a>",
+            escape(&page.path),
+            escape(&page.title().unwrap_or_else(|| page.path.clone()))
+        ),
+        _ => String::new(),
+    }
+}
+
+// Splits "name a b=c d=\"e f\"", returns None when it doesn't look like a shortcode
+fn parse(inner: &str) -> Option<Shortcode<'_>> {
+    let inner = inner.trim();
+    let (name, raw_args) = match inner.find(char::is_whitespace) {
+        Some(index) => (&inner[..index], inner[index..].trim()),
+        None => (inner, ""),
+    };
+
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+    {
+        return None;
+    }
+
+    let mut args = Args::default();
+    let mut chars = raw_args.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut key = None;
+        let mut token = String::new();
+        let mut quoted = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => quoted = !quoted,
+                '=' if !quoted && key.is_none() => key = Some(std::mem::take(&mut token)),
+                c if c.is_whitespace() && !quoted => break,
+                '\\' if quoted => {
+                    if let Some(escaped) = chars.next() {
+                        token.push(escaped);
+                    }
+                }
+                c => token.push(c),
+            }
+        }
+
+        match key {
+            Some(key) => {
+                args.named.insert(key, token);
+            }
+            None => args.positional.push(token),
+        }
+    }
+
+    Some(Shortcode {
+        name,
+        raw_args,
+        args,
+    })
+}
+
+fn youtube(args: &Args) -> Option<String> {
+    let id = args.get("id")?;
+    if id.is_empty()
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return None;
+    }
+
+    let title = args
+        .named
+        .get("title")
+        .map_or("YouTube video", String::as_str);
+
+    Some(format!(
+        "<iframe class=\"youtube\" width=\"560\" height=\"315\" \
+        src=\"https://www.youtube-nocookie.com/embed/{}\" title=\"{}\" \
+        frameborder=\"0\" allowfullscreen loading=\"lazy\"></iframe>",
+        id,
+        escape(title)
+    ))
+}
+
+fn gallery(args: &Args) -> Option<String> {
+    if args.positional.is_empty() {
+        return Some("<!-- gallery without images -->".to_string());
+    }
+
+    let id = match args.named.get("id") {
+        Some(id) => format!(" id=\"{}\"", escape(id)),
+        None => String::new(),
+    };
+    let columns = args
+        .named
+        .get("columns")
+        .and_then(|columns| columns.parse::<u8>().ok())
+        .filter(|columns| *columns > 0)
+        .unwrap_or(3);
+
+    let mut images = String::new();
+    for src in &args.positional {
+        images.push_str(&format!(
+            "<figure><img src=\"{}\" alt=\"\" loading=\"lazy\"></figure>",
+            escape(src)
+        ));
+    }
+
+    Some(format!(
+        "<div class=\"gallery\"{} style=\"display: grid; \
+        grid-template-columns: repeat({}, 1fr); gap: 1rem;\">{}</div>",
+        id, columns, images
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{Database, DatabaseMpscCommand};
+    use crate::plugin::Plugins;
+    use chrono::NaiveDateTime;
+    use std::sync::Arc;
+
+    fn page(site_id: Uuid, path: &str, body: &str) -> schema::Page {
+        schema::Page {
+            site_id,
+            path: path.to_string(),
+            created_at: NaiveDateTime::default(),
+            created_by: Uuid::nil(),
+            modified_at: NaiveDateTime::default(),
+            modified_by: Uuid::nil(),
+            published: !path.starts_with("/draft"),
+            metadata: Vec::new(),
+            body: body.to_string(),
+            sort_order: 0,
+            content_type: None,
+            fields: Value::Object(Default::default()),
+            version: 1,
+        }
+    }
+
+    // Answers page and block lookups from the given bodies, anything missing is an error
+    fn state(pages: &[(&str, &str)], blocks: &[(&str, &str)]) -> AppState {
+        let (db, mut rx) = Database::stub();
+        let pages: HashMap<String, String> = pages
+            .iter()
+            .map(|(path, body)| (path.to_string(), body.to_string()))
+            .collect();
+        let blocks: HashMap<String, String> = blocks
+            .iter()
+            .map(|(name, body)| (name.to_string(), body.to_string()))
+            .collect();
+
+        tokio::spawn(async move {
+            while let Some((_, command)) = rx.recv().await {
+                match command {
+                    DatabaseMpscCommand::GetPage(site_id, path, _, reply) => {
+                        if let Some(body) = pages.get(&path) {
+                            let _ = reply.send(Ok(page(site_id, &path, body)));
+                        }
+                    }
+                    DatabaseMpscCommand::GetBlock(site_id, name, reply) => {
+                        if let Some(body) = blocks.get(&name) {
+                            let _ = reply.send(Ok(schema::Block {
+                                site_id,
+                                name,
+                                body: body.clone(),
+                                modified_at: NaiveDateTime::default(),
+                                modified_by: Uuid::nil(),
+                            }));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        AppState {
+            db,
+            plugins: Arc::new(Plugins::load(&[])),
+            trust_proxy: false,
+        }
+    }
+
+    async fn expand(state: &AppState, body: &str) -> String {
+        Expansion::new(state, Uuid::nil(), "/").expand(body).await
+    }
+
+    #[test]
+    fn parses_positional_named_and_quoted_arguments() {
+        let shortcode = parse(r#" gallery /a.jpg columns=2 title="a \"b\" c" "#).unwrap();
+        assert_eq!(shortcode.name, "gallery");
+        assert_eq!(shortcode.raw_args, r#"/a.jpg columns=2 title="a \"b\" c""#);
+        assert_eq!(shortcode.args.positional, ["/a.jpg"]);
+        assert_eq!(shortcode.args.named["columns"], "2");
+        assert_eq!(shortcode.args.named["title"], "a \"b\" c");
+
+        assert!(parse("").is_none());
+        assert!(parse("Not a shortcode").is_none());
+        assert!(parse("<b>").is_none());
+    }
+
+    #[tokio::test]
+    async fn leaves_unknown_and_unclosed_shortcodes_alone() {
+        let state = state(&[], &[]);
+        assert_eq!(
+            expand(
+                &state,
+                "[[nope x]] [[Upper]] [[youtube bad/id]] [[youtube x"
+            )
+            .await,
+            "[[nope x]] [[Upper]] [[youtube bad/id]] [[youtube x"
+        );
+    }
+
+    #[tokio::test]
+    async fn renders_builtins() {
+        let state = state(&[], &[]);
+
+        let html = expand(&state, "[[youtube dQw4w9WgXcQ title=\"<Rick>\"]]").await;
+        assert!(html.contains("/embed/dQw4w9WgXcQ\""));
+        assert!(html.contains("title=\"&lt;Rick&gt;\""));
+
+        let html = expand(&state, "[[gallery /a.jpg /b.jpg columns=2 id=team]]").await;
+        assert!(html.starts_with("<div class=\"gallery\" id=\"team\""));
+        assert!(html.contains("repeat(2, 1fr)"));
+        assert_eq!(html.matches("<img").count(), 2);
+
+        assert_eq!(
+            expand(&state, "[[gallery id=team]]").await,
+            "<!-- gallery without images -->"
+        );
+    }
+
+    #[tokio::test]
+    async fn includes_published_pages_and_stops_at_cycles() {
+        let state = state(
+            &[
+                ("/a", "A[[include /b]]"),
+                ("/b", "B[[include /a]]"),
+                ("/draft", "hidden"),
+            ],
+            &[],
+        );
+
+        assert_eq!(
+            expand(&state, "[[include /a]]").await,
+            "AB<!-- include cycle: /a -->"
+        );
+        assert_eq!(
+            expand(&state, "[[include /draft]][[include /missing]]").await,
+            "<!-- include not found: /draft --><!-- include not found: /missing -->"
+        );
+    }
+
+    #[tokio::test]
+    async fn expands_blocks_and_stops_at_cycles() {
+        let state = state(
+            &[],
+            &[("cta", "[[block inner]]!"), ("inner", "Buy [[block cta]]")],
+        );
+
+        let mut expansion = Expansion::new(&state, Uuid::nil(), "/");
+        assert_eq!(
+            expansion.expand("[[block cta]][[block missing]]").await,
+            "Buy <!-- block cycle: cta -->!"
+        );
+        assert_eq!(expansion.blocks, ["cta", "inner", "missing"]);
+    }
+
+    #[tokio::test]
+    async fn stops_expanding_at_the_limit() {
+        let state = state(&[], &[]);
+        let html = expand(&state, &"[[gallery]]".repeat(MAX_EXPANSIONS + 1)).await;
+        assert_eq!(
+            html.matches("<!-- gallery without images -->").count(),
+            MAX_EXPANSIONS
+        );
+        assert!(html.ends_with("<!-- shortcode limit reached -->"));
+    }
+}
@@ -0,0 +1,441 @@
+/*
+ * web/static_export.rs
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * Writes a site out as plain files for serving from a CDN: every published
+ * page rendered the way the server would, at <path>/index.html, next to a
+ * 404.html and a sitemap.xml. A manifest in the directory records when each
+ * page was last modified, later runs only render what changed since. Menus,
+ * blocks, content types and site settings show up on every page, so a
+ * change to any of them renders everything again.
+ */
+
+use super::{
+    error_page,
+    html::{escape, page_to_response, RenderContext},
+    i18n, AppState, ERROR_PAGE_PREFIX,
+};
+use crate::{
+    database::{schema, Database},
+    plugin::Plugins,
+};
+use actix_web::{body, http::StatusCode, HttpResponse};
+use chrono::{NaiveDateTime, SecondsFormat};
+use color_eyre::{eyre::eyre, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+use walkdir::WalkDir;
+
+const MANIFEST: &str = ".magnetite-static.json";
+const SITEMAP: &str = "sitemap.xml";
+const NOT_FOUND: &str = "404.html";
+// Pages including other pages can't tell which changed, they are rendered whenever any did
+const INCLUDE: &str = "[[include";
+
+pub struct Options {
+    pub dir: PathBuf,
+    // Scheme and host the site will be served from, for the sitemap and hreflang links
+    pub base_url: String,
+    // Copied into the output as is, e.g. images the pages link to
+    pub assets: Option<PathBuf>,
+    // Renders every page even if the manifest says it is up to date
+    pub full: bool,
+}
+
+#[derive(Default)]
+pub struct Summary {
+    pub rendered: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    pub assets: usize,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Manifest {
+    base_url: String,
+    // Hash of what every page shows besides itself
+    layout: String,
+    files: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+struct ManifestEntry {
+    path: String,
+    modified_at: NaiveDateTime,
+}
+
+// One file to write, a page in one of its locales
+struct Target {
+    file: String,
+    url: String,
+    page: schema::Page,
+    locale: String,
+    translations: Vec<schema::PageTranslation>,
+}
+
+pub async fn export(
+    site: &schema::Site,
+    db: Database,
+    plugins: Arc<Plugins>,
+    options: &Options,
+) -> Result<Summary> {
+    let data = AppState {
+        db,
+        plugins,
+        trust_proxy: false,
+    };
+    let base_url = options.base_url.trim_end_matches('/');
+    fs::create_dir_all(&options.dir)?;
+
+    let previous: Manifest = match fs::read(options.dir.join(MANIFEST)) {
+        Ok(contents) => serde_json::from_slice(&contents).unwrap_or_default(),
+        Err(_) => Manifest::default(),
+    };
+
+    let content_types = data.db.get_content_types(site.id).await?;
+    let layout = layout_hash(site, &data.db, &content_types).await?;
+    let full = options.full || previous.base_url != base_url || previous.layout != layout;
+
+    let pages = data
+        .db
+        .list_pages(
+            site.id,
+            schema::PageListQuery {
+                published: Some(true),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+    let mut targets = Vec::new();
+    for page in pages {
+        if page.path.starts_with(ERROR_PAGE_PREFIX) {
+            continue;
+        }
+
+        let translations = data.db.get_page_translations(site.id, &page.path).await?;
+        let locales: Vec<String> = i18n::available_locales(site, &translations)
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        // The bare path is the default locale, with translations it also has a prefixed copy
+        // since that is where the hreflang links point
+        let mut urls = vec![(page.path.clone(), site.default_locale.clone())];
+        if locales.len() > 1 {
+            for locale in &locales {
+                urls.push((i18n::localized_path(locale, &page.path), locale.clone()));
+            }
+        }
+
+        for (url, locale) in urls {
+            let localized = match i18n::localize(page.clone(), site, &translations, &locale) {
+                Some(localized) => localized,
+                None => continue,
+            };
+            targets.push(Target {
+                file: file_name(&url),
+                url,
+                page: localized,
+                locale,
+                translations: translations.clone(),
+            });
+        }
+    }
+
+    // Pages whose own file changed, pages showing them in breadcrumbs or [[include]] follow
+    let mut changed: HashSet<&str> = HashSet::new();
+    for target in &targets {
+        let entry = previous.files.get(&target.file);
+        if entry.map(|entry| entry.modified_at) != Some(target.page.modified_at) {
+            changed.insert(target.page.path.as_str());
+        }
+    }
+    let current: HashSet<&str> = targets.iter().map(|target| target.file.as_str()).collect();
+    for (file, entry) in &previous.files {
+        if !current.contains(file.as_str()) {
+            changed.insert(entry.path.as_str());
+        }
+    }
+
+    let templates: HashMap<&str, &str> = content_types
+        .iter()
+        .map(|content_type| (content_type.name.as_str(), content_type.template.as_str()))
+        .collect();
+
+    let mut summary = Summary::default();
+    for target in &targets {
+        let path = inside(&options.dir, &target.file)?;
+        let includes = target.page.body.contains(INCLUDE)
+            || target
+                .page
+                .content_type
+                .as_deref()
+                .and_then(|name| templates.get(name))
+                .is_some_and(|template| template.contains(INCLUDE));
+        let stale = full
+            || !path.exists()
+            || changed.contains(target.page.path.as_str())
+            || target
+                .page
+                .ancestor_paths()
+                .iter()
+                .any(|ancestor| changed.contains(ancestor.as_str()))
+            || (includes && !changed.is_empty());
+
+        if !stale {
+            summary.unchanged += 1;
+            continue;
+        }
+
+        let mut context = RenderContext::load(site, &target.page, &data.db).await;
+        context.locale = target.locale.clone();
+        context.alternates =
+            i18n::alternates(base_url, site, &target.page.path, &target.translations);
+
+        let response = page_to_response(target.page.clone(), StatusCode::OK, context, &data).await;
+        write(&path, response).await?;
+        summary.rendered += 1;
+    }
+
+    // Files of pages that were deleted or unpublished since the last run
+    for file in previous.files.keys() {
+        if !current.contains(file.as_str()) {
+            remove(&options.dir, file)?;
+            summary.removed += 1;
+        }
+    }
+
+    // The site's own 404 page if it has one, like the server would show
+    let not_found = error_page(StatusCode::NOT_FOUND, Some(site), &data).await;
+    write(&options.dir.join(NOT_FOUND), not_found).await?;
+
+    fs::write(options.dir.join(SITEMAP), sitemap(base_url, &targets))?;
+
+    if let Some(assets) = &options.assets {
+        summary.assets = copy_assets(assets, &options.dir)?;
+    }
+
+    let manifest = Manifest {
+        base_url: base_url.to_string(),
+        layout,
+        files: targets
+            .iter()
+            .map(|target| {
+                let entry = ManifestEntry {
+                    path: target.page.path.clone(),
+                    modified_at: target.page.modified_at,
+                };
+                (target.file.clone(), entry)
+            })
+            .collect(),
+    };
+    fs::write(
+        options.dir.join(MANIFEST),
+        serde_json::to_vec_pretty(&manifest)?,
+    )?;
+
+    Ok(summary)
+}
+
+// / -> index.html, /news/post -> news/post/index.html, /es/ -> es/index.html
+fn file_name(url: &str) -> String {
+    let path = url.trim_matches('/');
+    match path.is_empty() {
+        true => String::from("index.html"),
+        false => format!("{}/index.html", path),
+    }
+}
+
+// Everything rendered around the page itself, see the comment at the top
+async fn layout_hash(
+    site: &schema::Site,
+    db: &Database,
+    content_types: &[schema::ContentType],
+) -> Result<String> {
+    let menus = db.get_menus(site.id).await?;
+    let blocks = db.get_blocks(site.id).await?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_vec(site)?);
+    hasher.update(serde_json::to_vec(&menus)?);
+    hasher.update(serde_json::to_vec(&blocks)?);
+    hasher.update(serde_json::to_vec(content_types)?);
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+async fn write(path: &Path, response: HttpResponse) -> Result<()> {
+    let html = body::to_bytes(response.into_body())
+        .await
+        .map_err(|err| eyre!("Failed to render {}: {}", path.display(), err))?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, html)?;
+
+    Ok(())
+}
+
+// Pages saved before their paths were checked, or a manifest edited by hand,
+// could otherwise point outside of the export directory
+fn inside(root: &Path, file: &str) -> Result<PathBuf> {
+    let relative = Path::new(file);
+    if file.is_empty()
+        || !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(eyre!(
+            "Refusing to touch {} outside of {}",
+            file,
+            root.display()
+        ));
+    }
+
+    Ok(root.join(relative))
+}
+
+// Removes the file and the directories it leaves empty, up to the export root
+fn remove(root: &Path, file: &str) -> Result<()> {
+    let path = inside(root, file)?;
+    if path.exists() {
+        fs::remove_file(&path)?;
+    }
+
+    let mut dir = path.parent();
+    while let Some(parent) = dir {
+        if parent == root || fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+
+    Ok(())
+}
+
+fn sitemap(base_url: &str, targets: &[Target]) -> String {
+    let mut urls = String::new();
+    for target in targets {
+        urls.push_str(&format!(
+            "
+    <url>
+        <loc>{}</loc>
+        <lastmod>{}</lastmod>
+    </url>",
+            escape(&format!("{}{}", base_url, target.url)),
+            target
+                .page
+                .modified_at
+                .and_utc()
+                .to_rfc3339_opts(SecondsFormat::Secs, true)
+        ));
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">{}
+</urlset>
+",
+        urls
+    )
+}
+
+// Only files that are missing or differ in size or are newer than the copy are copied
+fn copy_assets(source: &Path, dir: &Path) -> Result<usize> {
+    if !source.is_dir() {
+        return Err(eyre!("{} is not a directory", source.display()));
+    }
+
+    let mut copied = 0;
+    for entry in WalkDir::new(source) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let destination = dir.join(entry.path().strip_prefix(source)?);
+        let metadata = entry.metadata()?;
+        let up_to_date = match fs::metadata(&destination) {
+            Ok(existing) => {
+                existing.len() == metadata.len() && existing.modified()? >= metadata.modified()?
+            }
+            Err(_) => false,
+        };
+        if up_to_date {
+            continue;
+        }
+
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(entry.path(), &destination)?;
+        copied += 1;
+    }
+
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn names_files_after_urls() {
+        assert_eq!(file_name("/"), "index.html");
+        assert_eq!(file_name("/about"), "about/index.html");
+        assert_eq!(file_name("/es/about/"), "es/about/index.html");
+    }
+
+    #[test]
+    fn stays_inside_the_export() {
+        let root = Path::new("/srv/export");
+        assert_eq!(
+            inside(root, "about/index.html").unwrap(),
+            root.join("about/index.html")
+        );
+
+        for file in [
+            "../index.html",
+            "about/../../index.html",
+            "/etc/passwd",
+            "./index.html",
+            "",
+        ] {
+            assert!(inside(root, file).is_err(), "{}", file);
+        }
+    }
+
+    #[test]
+    fn removes_files_and_the_directories_they_leave_empty() {
+        let root = std::env::temp_dir().join(format!("magnetite-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(root.join("news/post")).unwrap();
+        fs::write(root.join("news/post/index.html"), "post").unwrap();
+        fs::write(root.join("news/index.html"), "news").unwrap();
+
+        remove(&root, "news/post/index.html").unwrap();
+        assert!(!root.join("news/post").exists());
+        assert!(root.join("news/index.html").exists());
+
+        remove(&root, "news/index.html").unwrap();
+        assert!(!root.join("news").exists());
+        assert!(root.exists());
+        assert!(remove(&root, "../outside").is_err());
+
+        fs::remove_dir(&root).unwrap();
+    }
+}
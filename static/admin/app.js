@@ -0,0 +1,676 @@
+/*
+ * static/admin/app.js
+ * Copyright (c) 2024 Luke Harding
+ * This code is licensed under a GNU GPL v3 license.
+ * See the file "LICENSE" in the root of this project.
+ *
+ * Admin single page app, talks to the JSON API under /admin/api.
+ */
+
+"use strict";
+
+const TOKEN_KEY = "magnetite-token";
+const SITE_KEY = "magnetite-site";
+// Never shown by the rich text editor, they could run script with the admin's token
+const UNSAFE_ELEMENTS = "script, style, iframe, frame, object, embed, link, meta, base, form, svg, math";
+const URL_ATTRIBUTES = ["href", "src", "action", "formaction", "xlink:href"];
+
+// The token only lives as long as the tab, earlier versions kept it for good
+localStorage.removeItem(TOKEN_KEY);
+
+const state = {
+  token: sessionStorage.getItem(TOKEN_KEY),
+  siteId: localStorage.getItem(SITE_KEY),
+  superuser: false,
+  // Path the open page was loaded from, null while creating a new one
+  originalPath: null,
+  // Version of the open page, sent back so saves don't overwrite someone else's
+  version: null,
+  mode: "html",
+};
+
+const $ = (selector) => document.querySelector(selector);
+
+class ApiError extends Error {
+  constructor(status, message) {
+    super(message);
+    this.status = status;
+  }
+}
+
+async function api(method, path, body, extraHeaders = {}) {
+  const headers = { ...extraHeaders, Authorization: `Bearer ${state.token}` };
+  if (state.siteId) headers["X-Site-Id"] = state.siteId;
+  if (body !== undefined) headers["Content-Type"] = "application/json";
+
+  const response = await fetch(`/admin/api${path}`, {
+    method,
+    headers,
+    body: body === undefined ? undefined : JSON.stringify(body),
+  });
+
+  const text = await response.text();
+  let data = null;
+  try {
+    data = text ? JSON.parse(text) : null;
+  } catch {
+    data = text;
+  }
+
+  if (!response.ok) {
+    const message = data && data.error ? data.error : text || response.statusText;
+    throw new ApiError(response.status, message);
+  }
+  return data;
+}
+
+// Page paths start with a slash, API routes take them without
+const pagePath = (path) => encodeURI(path.replace(/^\//, ""));
+
+function escapeHtml(text) {
+  return text
+    .replace(/&/g, "&amp;")
+    .replace(/</g, "&lt;")
+    .replace(/>/g, "&gt;")
+    .replace(/"/g, "&quot;");
+}
+
+// Parsed in a document without a window, nothing in it loads or runs
+const parseHtml = (html) => new DOMParser().parseFromString(html, "text/html").body;
+
+function sanitizeHtml(html) {
+  const body = parseHtml(html);
+  body.querySelectorAll(UNSAFE_ELEMENTS).forEach((element) => element.remove());
+
+  for (const element of body.querySelectorAll("*")) {
+    for (const attribute of [...element.attributes]) {
+      const name = attribute.name.toLowerCase();
+      const value = attribute.value.replace(/[\s\u0000-\u001f]/g, "").toLowerCase();
+      const scriptUrl =
+        URL_ATTRIBUTES.includes(name) &&
+        /^(javascript|vbscript|data):/.test(value) &&
+        !value.startsWith("data:image/");
+
+      if (name.startsWith("on") || scriptUrl) {
+        element.removeAttribute(attribute.name);
+      }
+    }
+  }
+
+  return body.innerHTML;
+}
+
+function unescapeHtml(text) {
+  const element = document.createElement("textarea");
+  element.innerHTML = text;
+  return element.value;
+}
+
+function setStatus(id, message, isError) {
+  const element = $(id);
+  element.textContent = message;
+  element.classList.toggle("error", Boolean(isError));
+}
+
+/* Sign in */
+
+async function signIn() {
+  try {
+    await api("GET", "/children/");
+  } catch (err) {
+    if (err.status === 401) {
+      showLogin("That token was not accepted.");
+      return;
+    }
+    if (err.status !== 403) throw err;
+  }
+
+  try {
+    const sites = await api("GET", "/sites");
+    state.superuser = true;
+    fillSites(sites);
+  } catch (err) {
+    if (err.status !== 403) throw err;
+    state.superuser = false;
+  }
+
+  $("#login").hidden = true;
+  $("#app").hidden = false;
+  $("#users-tab").hidden = !state.superuser;
+  $("#site-select").hidden = !state.superuser;
+
+  await loadTree();
+}
+
+function showLogin(message) {
+  $("#app").hidden = true;
+  $("#login").hidden = false;
+  $("#login-error").textContent = message || "";
+}
+
+function fillSites(sites) {
+  const select = $("#site-select");
+  select.innerHTML = "";
+
+  for (const site of sites) {
+    const option = document.createElement("option");
+    option.value = site.id;
+    option.textContent = `${site.name} (${site.host})`;
+    select.appendChild(option);
+  }
+
+  if (!sites.some((site) => site.id === state.siteId)) {
+    const fallback = sites.find((site) => site.is_default) || sites[0];
+    state.siteId = fallback ? fallback.id : null;
+  }
+  select.value = state.siteId || "";
+}
+
+/* Page tree */
+
+async function loadTree() {
+  const tree = $("#page-tree");
+  tree.innerHTML = "";
+  await loadContentTypes();
+
+  try {
+    const root = await api("GET", "/pages/");
+    tree.appendChild(treeEntry(root, false));
+  } catch (err) {
+    if (err.status !== 404) throw err;
+  }
+
+  const children = await api("GET", "/children/");
+  for (const page of children) {
+    tree.appendChild(treeEntry(page, true));
+  }
+}
+
+async function loadContentTypes() {
+  const select = $("#content-type-select");
+  select.innerHTML = '<option value="">None</option>';
+
+  for (const contentType of await api("GET", "/content-types")) {
+    const option = document.createElement("option");
+    option.value = contentType.name;
+    option.textContent = contentType.label;
+    select.appendChild(option);
+  }
+}
+
+function treeEntry(page, expandable) {
+  const item = document.createElement("li");
+  const entry = document.createElement("div");
+  entry.className = "entry";
+
+  const toggle = document.createElement("button");
+  toggle.type = "button";
+  toggle.className = "toggle";
+  toggle.textContent = expandable ? "+" : "";
+  entry.appendChild(toggle);
+
+  const link = document.createElement("a");
+  link.textContent = page.path === "/" ? "/ (home)" : page.path.split("/").pop();
+  link.title = page.path;
+  link.dataset.path = page.path;
+  link.classList.toggle("draft", !page.published);
+  link.classList.toggle("current", page.path === state.originalPath);
+  link.addEventListener("click", () => openPage(page.path));
+  entry.appendChild(link);
+
+  item.appendChild(entry);
+
+  if (expandable) {
+    const list = document.createElement("ul");
+    list.hidden = true;
+    item.appendChild(list);
+
+    toggle.addEventListener("click", async () => {
+      if (list.hidden && !list.dataset.loaded) {
+        const children = await api("GET", `/children/${pagePath(page.path)}`);
+        for (const child of children) {
+          list.appendChild(treeEntry(child, true));
+        }
+        list.dataset.loaded = "true";
+        if (children.length === 0) toggle.textContent = "";
+      }
+      list.hidden = !list.hidden;
+      if (toggle.textContent) toggle.textContent = list.hidden ? "+" : "-";
+    });
+  }
+
+  return item;
+}
+
+function markCurrent() {
+  document.querySelectorAll("#page-tree a").forEach((link) => {
+    link.classList.toggle("current", link.dataset.path === state.originalPath);
+  });
+}
+
+/* Page editor */
+
+const form = () => $("#page-form");
+
+function currentMode() {
+  return form().elements.mode.value;
+}
+
+function readMetadata(metadata) {
+  const result = { title: "", description: "", extra: [] };
+
+  for (const line of metadata) {
+    const title = line.match(/^<title>(.*)<\/title>$/);
+    const description = line.match(/^<meta name="description" content="(.*)">$/);
+
+    if (title && !result.title) {
+      result.title = unescapeHtml(title[1]);
+    } else if (description && !result.description) {
+      result.description = unescapeHtml(description[1]);
+    } else {
+      result.extra.push(line);
+    }
+  }
+
+  return result;
+}
+
+function writeMetadata() {
+  const elements = form().elements;
+  const metadata = [];
+
+  if (elements.title.value) {
+    metadata.push(`<title>${escapeHtml(elements.title.value)}</title>`);
+  }
+  if (elements.description.value) {
+    metadata.push(`<meta name="description" content="${escapeHtml(elements.description.value)}">`);
+  }
+
+  return metadata.concat(
+    elements.extra_metadata.value
+      .split("\n")
+      .map((line) => line.trim())
+      .filter((line) => line)
+  );
+}
+
+function readBody() {
+  switch (currentMode()) {
+    case "rich":
+      return $("#rich-editor").innerHTML;
+    case "markdown":
+      return markdownToHtml($("#source-editor").value);
+    default:
+      return $("#source-editor").value;
+  }
+}
+
+// Bodies only get into the rich text editor sanitized, and only when that drops
+// nothing, otherwise saving would silently lose what was dropped
+function showRich(body) {
+  const sanitized = sanitizeHtml(body);
+  if (sanitized !== parseHtml(body).innerHTML) return false;
+
+  $("#rich-editor").innerHTML = sanitized;
+  return true;
+}
+
+function showBody(body, mode) {
+  $("#rich-editor").replaceChildren();
+  $("#source-editor").value = body;
+  if (mode === "rich" && !showRich(body)) mode = "html";
+  form().elements.mode.value = mode;
+  state.mode = mode;
+  applyMode();
+}
+
+function applyMode() {
+  const rich = currentMode() === "rich";
+  $("#rich-toolbar").hidden = !rich;
+  $("#rich-editor").hidden = !rich;
+  $("#source-editor").hidden = rich;
+  updatePreview();
+}
+
+// Markdown is turned into HTML when leaving the mode, HTML is carried over as is
+function switchMode() {
+  let body;
+  if (state.mode === "rich") {
+    body = $("#rich-editor").innerHTML;
+  } else if (state.mode === "markdown") {
+    body = markdownToHtml($("#source-editor").value);
+  } else {
+    body = $("#source-editor").value;
+  }
+
+  if (currentMode() === "rich" && !showRich(body)) {
+    form().elements.mode.value = state.mode;
+    setStatus("#page-status", "This body has scripts, embeds or event handlers, edit it as HTML", true);
+    return;
+  }
+
+  $("#source-editor").value = body;
+  state.mode = currentMode();
+  applyMode();
+}
+
+let previewTimer = null;
+
+function updatePreview() {
+  clearTimeout(previewTimer);
+  previewTimer = setTimeout(() => {
+    $("#preview").srcdoc = `<!DOCTYPE html><html><head>${writeMetadata().join("\n")}</head><body>${readBody()}</body></html>`;
+  }, 200);
+}
+
+function fillPage(page) {
+  const elements = form().elements;
+  const metadata = readMetadata(page.metadata);
+
+  elements.path.value = page.path;
+  elements.sort_order.value = page.sort_order;
+  elements.published.checked = page.published;
+  elements.title.value = metadata.title;
+  elements.description.value = metadata.description;
+  elements.extra_metadata.value = metadata.extra.join("\n");
+  elements.content_type.value = page.content_type || "";
+  elements.fields.value = JSON.stringify(page.fields || {}, null, 2);
+
+  showBody(page.body, "html");
+  setStatus("#page-status", "");
+
+  $("#delete-page").hidden = state.originalPath === null;
+  $("#view-page").hidden = state.originalPath === null;
+  $("#editor").hidden = false;
+  markCurrent();
+}
+
+async function openPage(path) {
+  try {
+    const page = await api("GET", `/pages/${pagePath(path)}`);
+    state.originalPath = page.path;
+    state.version = page.version;
+    fillPage(page);
+  } catch (err) {
+    setStatus("#page-status", err.message, true);
+  }
+}
+
+function newPage() {
+  state.originalPath = null;
+  state.version = null;
+  fillPage({ path: "/", sort_order: 0, published: false, metadata: [], body: "" });
+  form().elements.path.focus();
+}
+
+async function savePage(event) {
+  event.preventDefault();
+  const elements = form().elements;
+
+  let fields;
+  try {
+    fields = JSON.parse(elements.fields.value.trim() || "{}");
+  } catch {
+    setStatus("#page-status", "Fields are not valid JSON", true);
+    return;
+  }
+
+  const input = {
+    path: elements.path.value.trim(),
+    published: elements.published.checked,
+    metadata: writeMetadata(),
+    body: readBody(),
+    sort_order: Number(elements.sort_order.value) || 0,
+    content_type: elements.content_type.value || null,
+    fields,
+  };
+
+  try {
+    const page =
+      state.originalPath === null
+        ? await api("POST", "/pages", input)
+        : await api("PUT", `/pages/${pagePath(state.originalPath)}`, input, {
+            "If-Match": `"${state.version}"`,
+          });
+
+    state.originalPath = page.path;
+    state.version = page.version;
+    $("#delete-page").hidden = false;
+    $("#view-page").hidden = false;
+    setStatus("#page-status", `Saved at ${new Date().toLocaleTimeString()}`);
+    await loadTree();
+  } catch (err) {
+    const message =
+      err.status === 412 ? `${err.message}, reload the page to see their changes` : err.message;
+    setStatus("#page-status", message, true);
+  }
+}
+
+async function deletePage() {
+  if (state.originalPath === null || !confirm(`Delete ${state.originalPath}?`)) return;
+
+  try {
+    await api("DELETE", `/pages/${pagePath(state.originalPath)}`);
+    state.originalPath = null;
+    $("#editor").hidden = true;
+    await loadTree();
+  } catch (err) {
+    setStatus("#page-status", err.message, true);
+  }
+}
+
+/* Users */
+
+async function loadUsers() {
+  const rows = $("#user-rows");
+  rows.innerHTML = "";
+
+  try {
+    const users = await api("GET", "/users");
+    for (const user of users) {
+      rows.appendChild(userRow(user));
+    }
+  } catch (err) {
+    setStatus("#user-status", err.message, true);
+  }
+}
+
+function userRow(user) {
+  const row = document.createElement("tr");
+  row.innerHTML = `
+    <td><input type="text" name="username"></td>
+    <td><input type="email" name="email"></td>
+    <td><input type="checkbox" name="enabled"></td>
+    <td><input type="checkbox" name="superuser"></td>
+    <td class="actions-cell">
+      <button type="button" data-action="save">Save</button>
+      <button type="button" data-action="token" class="secondary">New token</button>
+      <button type="button" data-action="delete" class="danger">Delete</button>
+    </td>`;
+
+  const field = (name) => row.querySelector(`[name="${name}"]`);
+  field("username").value = user.username;
+  field("email").value = user.email;
+  field("enabled").checked = user.enabled;
+  field("superuser").checked = user.superuser;
+
+  row.addEventListener("click", async (event) => {
+    const action = event.target.dataset.action;
+    if (!action) return;
+
+    try {
+      if (action === "save") {
+        await api("PUT", `/users/${user.id}`, {
+          username: field("username").value,
+          email: field("email").value,
+          enabled: field("enabled").checked,
+          superuser: field("superuser").checked,
+        });
+        setStatus("#user-status", `Saved ${field("username").value}`);
+      } else if (action === "token") {
+        const result = await api("POST", `/users/${user.id}/tokens`);
+        prompt("New API token, it is only shown once:", result.token);
+      } else if (action === "delete" && confirm(`Delete ${user.username}?`)) {
+        await api("DELETE", `/users/${user.id}`);
+        row.remove();
+      }
+    } catch (err) {
+      setStatus("#user-status", err.message, true);
+    }
+  });
+
+  return row;
+}
+
+async function newUser() {
+  const username = prompt("Username");
+  if (!username) return;
+
+  try {
+    await api("POST", "/users", { username, email: "", enabled: true, superuser: false });
+    await loadUsers();
+  } catch (err) {
+    setStatus("#user-status", err.message, true);
+  }
+}
+
+/* Markdown, covering what editors use day to day */
+
+function inlineMarkdown(text) {
+  return text
+    .replace(/`([^`]+)`/g, (_, code) => `<code>${escapeHtml(code)}</code>`)
+    .replace(/!\[([^\]]*)\]\(([^)\s]+)\)/g, '<img src="$2" alt="$1">')
+    .replace(/\[([^\]]+)\]\(([^)\s]+)\)/g, '<a href="$2">$1</a>')
+    .replace(/\*\*([^*]+)\*\*/g, "<strong>$1</strong>")
+    .replace(/\*([^*]+)\*/g, "<em>$1</em>");
+}
+
+function markdownToHtml(markdown) {
+  const lines = markdown.replace(/\r\n/g, "\n").split("\n");
+  const html = [];
+  let paragraph = [];
+  let list = null;
+
+  const flushParagraph = () => {
+    if (paragraph.length) html.push(`<p>${inlineMarkdown(paragraph.join(" "))}</p>`);
+    paragraph = [];
+  };
+  const flushList = () => {
+    if (list) html.push(`<${list.tag}>${list.items.map((item) => `<li>${inlineMarkdown(item)}</li>`).join("")}</${list.tag}>`);
+    list = null;
+  };
+
+  for (let index = 0; index < lines.length; index++) {
+    const line = lines[index];
+    let match;
+
+    if (line.startsWith("```")) {
+      flushParagraph();
+      flushList();
+      const code = [];
+      while (++index < lines.length && !lines[index].startsWith("```")) {
+        code.push(lines[index]);
+      }
+      html.push(`<pre><code>${escapeHtml(code.join("\n"))}</code></pre>`);
+    } else if ((match = line.match(/^(#{1,6})\s+(.*)$/))) {
+      flushParagraph();
+      flushList();
+      const level = match[1].length;
+      html.push(`<h${level}>${inlineMarkdown(match[2])}</h${level}>`);
+    } else if (/^(-{3,}|\*{3,})$/.test(line.trim())) {
+      flushParagraph();
+      flushList();
+      html.push("<hr>");
+    } else if ((match = line.match(/^>\s?(.*)$/))) {
+      flushParagraph();
+      flushList();
+      html.push(`<blockquote>${inlineMarkdown(match[1])}</blockquote>`);
+    } else if ((match = line.match(/^\s*(?:[-*]|(\d+)\.)\s+(.*)$/))) {
+      flushParagraph();
+      const tag = match[1] ? "ol" : "ul";
+      if (list && list.tag !== tag) flushList();
+      if (!list) list = { tag, items: [] };
+      list.items.push(match[2]);
+    } else if (line.trim() === "") {
+      flushParagraph();
+      flushList();
+    } else {
+      flushList();
+      paragraph.push(line.trim());
+    }
+  }
+
+  flushParagraph();
+  flushList();
+  return html.join("\n");
+}
+
+/* Wiring */
+
+function showView(view) {
+  $("#pages-view").hidden = view !== "pages";
+  $("#users-view").hidden = view !== "users";
+  document.querySelectorAll(".topbar nav button").forEach((button) => {
+    button.classList.toggle("active", button.dataset.view === view);
+  });
+  if (view === "users") loadUsers();
+}
+
+document.addEventListener("DOMContentLoaded", () => {
+  $("#login-form").addEventListener("submit", (event) => {
+    event.preventDefault();
+    state.token = event.target.elements.token.value.trim();
+    sessionStorage.setItem(TOKEN_KEY, state.token);
+    signIn().catch((err) => showLogin(err.message));
+  });
+
+  $("#logout").addEventListener("click", () => {
+    sessionStorage.removeItem(TOKEN_KEY);
+    state.token = null;
+    showLogin();
+  });
+
+  $("#site-select").addEventListener("change", (event) => {
+    state.siteId = event.target.value;
+    localStorage.setItem(SITE_KEY, state.siteId);
+    state.originalPath = null;
+    $("#editor").hidden = true;
+    loadTree();
+  });
+
+  document.querySelectorAll(".topbar nav button").forEach((button) => {
+    button.addEventListener("click", () => showView(button.dataset.view));
+  });
+
+  $("#new-page").addEventListener("click", newPage);
+  $("#page-form").addEventListener("submit", savePage);
+  $("#page-form").addEventListener("input", updatePreview);
+  $("#rich-editor").addEventListener("input", updatePreview);
+  $("#delete-page").addEventListener("click", deletePage);
+  $("#view-page").addEventListener("click", () => window.open(state.originalPath, "_blank"));
+  $("#new-user").addEventListener("click", newUser);
+
+  form().querySelectorAll('[name="mode"]').forEach((radio) => {
+    radio.addEventListener("change", switchMode);
+  });
+
+  $("#rich-toolbar").addEventListener("click", (event) => {
+    const button = event.target.closest("button");
+    if (!button) return;
+
+    let value = button.dataset.value || null;
+    if (button.dataset.command === "createLink") {
+      value = prompt("Link URL");
+      if (!value) return;
+    }
+    document.execCommand(button.dataset.command, false, value);
+    $("#rich-editor").focus();
+    updatePreview();
+  });
+
+  if (state.token) {
+    signIn().catch((err) => showLogin(err.message));
+  } else {
+    showLogin();
+  }
+});